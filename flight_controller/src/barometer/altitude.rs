@@ -0,0 +1,62 @@
+// Pressure altitude of the international standard atmosphere, valid in the troposphere (below 11 km):
+//
+// h = 44330 m * (1 - (p / p0)^(1 / 5.255))
+//
+// The weather moves the pressure at sea level by several hPa (10 m per hPa), so the absolute altitude is meaningless
+// for flying. The altitude above the ground is the difference to the pressure altitude of the ground, which is
+// measured when the drone is armed. Both use the same standard sea level, so the offset of the weather cancels.
+use libm::powf;
+
+// Pa
+pub const SEA_LEVEL_PRESSURE: f32 = 101325.0;
+
+const SCALE_HEIGHT: f32 = 44330.0;        // m
+const EXPONENT: f32     = 1.0 / 5.255;
+
+// Altitude in m above the standard sea level
+pub fn pressure_altitude(pressure: f32) -> f32 {
+    SCALE_HEIGHT * (1.0 - powf(pressure / SEA_LEVEL_PRESSURE, EXPONENT))
+}
+
+// Inverse of `pressure_altitude`, in Pa
+pub fn altitude_pressure(altitude: f32) -> f32 {
+    SEA_LEVEL_PRESSURE * powf(1.0 - altitude / SCALE_HEIGHT, 1.0 / EXPONENT)
+}
+
+// Altitude above the point where the ground reference was taken
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Altimeter {
+    ground: Option<f32> // m, pressure altitude of the ground
+}
+
+impl Altimeter {
+    pub fn new() -> Self {
+        Self { ground: None }
+    }
+
+    // At arming, pressure is the mean of a few measurements while the drone stands on the ground
+    pub fn set_ground_reference(&mut self, pressure: f32) {
+        assert!(pressure > 0.0, "Pressure must be positive");
+        self.ground = Some(pressure_altitude(pressure));
+    }
+
+    pub fn clear_ground_reference(&mut self) {
+        self.ground = None;
+    }
+
+    // Pressure altitude of the ground in m, None before `set_ground_reference`
+    pub fn ground(&self) -> Option<f32> {
+        self.ground
+    }
+
+    // m above the ground, None without a ground reference
+    pub fn altitude(&self, pressure: f32) -> Option<f32> {
+        self.ground.map(|ground| pressure_altitude(pressure) - ground)
+    }
+}
+
+impl Default for Altimeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
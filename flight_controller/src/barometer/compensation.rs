@@ -0,0 +1,185 @@
+// Compensation of the raw ADC values with the trimming parameters each chip stores in its NVM, exactly as given in
+// the Bosch datasheets. The BMP280 uses the 32 bit temperature and the 64 bit pressure formula, they stay within
+// 0.02 Pa of the floating point example of the datasheet without an FPU heavy double calculation. The BMP388 only
+// has the floating point version, its parameters are scaled by powers of two before use.
+
+// Size of the trimming parameters in the NVM
+pub const BMP280_CALIBRATION_SIZE: usize = 24;
+pub const BMP388_CALIBRATION_SIZE: usize = 21;
+
+// dig_T1 - dig_P9 of the BMP280 datasheet, registers 0x88 - 0x9F
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bmp280Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p: [i16; 8] // dig_P2 - dig_P9
+}
+
+impl Bmp280Calibration {
+    // Little endian, in the order of the registers
+    pub fn from_registers(registers: &[u8; BMP280_CALIBRATION_SIZE]) -> Self {
+        let word = |index: usize| -> [u8; 2] { [registers[2 * index], registers[2 * index + 1]] };
+
+        Self {
+            t1: u16::from_le_bytes(word(0)),
+            t2: i16::from_le_bytes(word(1)),
+            t3: i16::from_le_bytes(word(2)),
+            p1: u16::from_le_bytes(word(3)),
+            p: [0, 1, 2, 3, 4, 5, 6, 7].map(|index| i16::from_le_bytes(word(4 + index)))
+        }
+    }
+
+    // adc_t is the 20 bit temperature. Returns t_fine, which the pressure needs, and the temperature in 0.01 °C
+    pub fn temperature(&self, adc_t: i32) -> (i32, i32) {
+        let (t1, t2, t3) = (self.t1 as i32, self.t2 as i32, self.t3 as i32);
+
+        let var1: i32 = (((adc_t >> 3) - (t1 << 1)) * t2) >> 11;
+        let var2: i32 = (((((adc_t >> 4) - t1) * ((adc_t >> 4) - t1)) >> 12) * t3) >> 14;
+        let t_fine: i32 = var1 + var2;
+
+        (t_fine, (t_fine * 5 + 128) >> 8)
+    }
+
+    // adc_p is the 20 bit pressure. Returns the pressure in Pa as Q24.8 (1/256 Pa), None for invalid parameters
+    pub fn pressure(&self, adc_p: i32, t_fine: i32) -> Option<u32> {
+        let [p2, p3, p4, p5, p6, p7, p8, p9] = self.p.map(|value| value as i64);
+
+        let mut var1: i64 = t_fine as i64 - 128000;
+        let mut var2: i64 = var1 * var1 * p6;
+        var2 += (var1 * p5) << 17;
+        var2 += p4 << 35;
+        var1 = ((var1 * var1 * p3) >> 8) + ((var1 * p2) << 12);
+        var1 = (((1i64 << 47) + var1) * self.p1 as i64) >> 33;
+
+        // Avoids a division by zero
+        if var1 == 0 {
+            return None;
+        }
+
+        let mut p: i64 = 1048576 - adc_p as i64;
+        p = (((p << 31) - var2) * 3125) / var1;
+        var1 = (p9 * (p >> 13) * (p >> 13)) >> 25;
+        var2 = (p8 * p) >> 19;
+        p = ((p + var1 + var2) >> 8) + (p7 << 4);
+
+        u32::try_from(p).ok()
+    }
+}
+
+// PAR_T1 - PAR_P11 of the BMP388 datasheet, registers 0x31 - 0x45, already scaled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bmp388Calibration {
+    t: [f64; 3],
+    p: [f64; 11]
+}
+
+impl Bmp388Calibration {
+    // Little endian, in the order of the registers
+    pub fn from_registers(registers: &[u8; BMP388_CALIBRATION_SIZE]) -> Self {
+        let u16_at = |index: usize| -> f64 { u16::from_le_bytes([registers[index], registers[index + 1]]) as f64 };
+        let i16_at = |index: usize| -> f64 { i16::from_le_bytes([registers[index], registers[index + 1]]) as f64 };
+        let i8_at = |index: usize| -> f64 { registers[index] as i8 as f64 };
+
+        // 2^exponent, also for exponents beyond the range of an integer shift
+        let power = |exponent: i32| -> f64 { libm::ldexp(1.0, exponent) };
+
+        Self {
+            t: [u16_at(0) * power(8), u16_at(2) / power(30), i8_at(4) / power(48)],
+            p: [
+                (i16_at(5) - power(14)) / power(20),
+                (i16_at(7) - power(14)) / power(29),
+                i8_at(9) / power(32),
+                i8_at(10) / power(37),
+                u16_at(11) * power(3),
+                u16_at(13) / power(6),
+                i8_at(15) / power(8),
+                i8_at(16) / power(15),
+                i16_at(17) / power(48),
+                i8_at(19) / power(48),
+                i8_at(20) / power(65)
+            ]
+        }
+    }
+
+    // raw is the 24 bit temperature. Returns t_lin, the temperature in °C
+    pub fn temperature(&self, raw: u32) -> f64 {
+        let partial1: f64 = raw as f64 - self.t[0];
+        let partial2: f64 = partial1 * self.t[1];
+        partial2 + partial1 * partial1 * self.t[2]
+    }
+
+    // raw is the 24 bit pressure, t_lin the result of `temperature`. Returns the pressure in Pa
+    pub fn pressure(&self, raw: u32, t_lin: f64) -> f64 {
+        let p: &[f64; 11] = &self.p;
+        let raw: f64 = raw as f64;
+        let (t2, t3): (f64, f64) = (t_lin * t_lin, t_lin * t_lin * t_lin);
+
+        let offset: f64 = p[4] + p[5] * t_lin + p[6] * t2 + p[7] * t3;
+        let sensitivity: f64 = raw * (p[0] + p[1] * t_lin + p[2] * t2 + p[3] * t3);
+        let quadratic: f64 = raw * raw * (p[8] + p[9] * t_lin) + raw * raw * raw * p[10];
+
+        offset + sensitivity + quadratic
+    }
+}
+
+// Run on the host by `cargo test` in the simulator
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::barometer::{mock::{MockBarometer, DATASHEET_ADC_P, DATASHEET_ADC_T}, Address, BarometerModel};
+
+    #[test]
+    fn bmp280_reproduces_the_datasheet_example() {
+        let mock: MockBarometer = MockBarometer::new(BarometerModel::Bmp280, Address::SdoHigh);
+        let calibration: Bmp280Calibration = Bmp280Calibration::from_registers(&mock.calibration());
+
+        let (t_fine, temperature) = calibration.temperature(DATASHEET_ADC_T as i32);
+        assert_eq!((t_fine, temperature), (128422, 2508));
+
+        // The example is computed in double precision, the fixed point formula truncates to 1/256 Pa steps
+        let pressure: u32 = calibration.pressure(DATASHEET_ADC_P as i32, t_fine).expect("valid parameters");
+        assert!((pressure as f64 / 256.0 - 100653.27).abs() < 0.03, "{pressure}");
+    }
+
+    // NVM of a BMP388: PAR_T1 = 27488, PAR_T2 = 19103, PAR_T3 = -7, PAR_P1 = -1234, PAR_P2 = -2890, PAR_P3 = 35,
+    // PAR_P4 = 1, PAR_P5 = 25632, PAR_P6 = 30203, PAR_P7 = 3, PAR_P8 = -6, PAR_P9 = 3980, PAR_P10 = 14, PAR_P11 = -55
+    const BMP388_NVM: [u8; BMP388_CALIBRATION_SIZE] = [
+        0x60, 0x6B, 0x9F, 0x4A, 0xF9, 0x2E, 0xFB, 0xB6, 0xF4, 0x23, 0x01, 0x20, 0x64, 0xFB, 0x75, 0x03, 0xFA, 0x8C, 0x0F, 0x0E, 0xC9
+    ];
+
+    #[test]
+    fn bmp388_scales_the_nvm_parameters() {
+        let calibration: Bmp388Calibration = Bmp388Calibration::from_registers(&BMP388_NVM);
+
+        // Exact in binary: PAR_T1 * 2^8, (PAR_P1 - 2^14) / 2^20, PAR_P5 * 2^3, PAR_P6 / 2^6, PAR_P7 / 2^8, PAR_P8 / 2^15
+        assert_eq!(calibration.t[0], 7036928.0);
+        assert_eq!(calibration.p[0], -17618.0 / 1048576.0);
+        assert_eq!(calibration.p[4], 205056.0);
+        assert_eq!(calibration.p[5], 471.921875);
+        assert_eq!(calibration.p[6], 0.01171875);
+        assert_eq!(calibration.p[7], -0.00018310546875);
+    }
+
+    #[test]
+    fn bmp388_compensates_a_fixed_vector() {
+        // Worked out by hand from the formulas of the datasheet in exact fractions
+        let calibration: Bmp388Calibration = Bmp388Calibration::from_registers(&BMP388_NVM);
+
+        let t_lin: f64 = calibration.temperature(8_400_000);
+        assert!((t_lin - 24.204283385).abs() < 1e-8, "{t_lin}");
+
+        let pressure: f64 = calibration.pressure(6_500_000, t_lin);
+        assert!((pressure - 101893.278216).abs() < 1e-4, "{pressure}");
+    }
+
+    #[test]
+    fn bmp280_rejects_a_zero_dig_p1() {
+        let mut registers: [u8; BMP280_CALIBRATION_SIZE] = MockBarometer::new(BarometerModel::Bmp280, Address::SdoHigh).calibration();
+        registers[6..8].copy_from_slice(&[0, 0]);
+
+        let calibration: Bmp280Calibration = Bmp280Calibration::from_registers(&registers);
+        assert_eq!(calibration.pressure(DATASHEET_ADC_P as i32, 128422), None);
+    }
+}
@@ -0,0 +1,100 @@
+use super::BarometerModel;
+
+// Samples averaged per measurement. Every doubling halves the noise power and costs about 2 ms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Oversampling {
+    X1,
+    X2,
+    X4,
+    X8,
+    X16
+}
+
+impl Oversampling {
+    pub fn samples(&self) -> u32 {
+        1 << self.exponent()
+    }
+
+    // osrs_x of the BMP280 and osr_x of the BMP388
+    pub(crate) fn register(&self, model: BarometerModel) -> u8 {
+        match model {
+            BarometerModel::Bmp280 => self.exponent() + 1, // 0 skips the measurement
+            BarometerModel::Bmp388 => self.exponent()
+        }
+    }
+
+    fn exponent(&self) -> u8 {
+        match self {
+            Oversampling::X1  => 0,
+            Oversampling::X2  => 1,
+            Oversampling::X4  => 2,
+            Oversampling::X8  => 3,
+            Oversampling::X16 => 4
+        }
+    }
+}
+
+// IIR filter of the chip, the output is (previous * (c - 1) + new) / c. The BMP388 datasheet names the coefficients
+// c - 1, the register values are the same for both chips
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IirFilter {
+    Off = 0,
+    C2  = 1,
+    C4  = 2,
+    C8  = 3,
+    C16 = 4
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+    pub(crate) pressure: Oversampling,
+    pub(crate) temperature: Oversampling,
+    pub(crate) filter: IirFilter
+}
+
+impl Config {
+    // The settings the BMP388 datasheet recommends for drones, a measurement takes about 20 ms
+    pub fn new() -> Self {
+        Self { pressure: Oversampling::X8, temperature: Oversampling::X1, filter: IirFilter::C4 }
+    }
+
+    pub fn set_pressure_oversampling(mut self, oversampling: Oversampling) -> Self {
+        self.pressure = oversampling;
+        self
+    }
+
+    // More than X2 doesn't improve the pressure, see the recommended settings of the datasheets
+    pub fn set_temperature_oversampling(mut self, oversampling: Oversampling) -> Self {
+        self.temperature = oversampling;
+        self
+    }
+
+    pub fn set_filter(mut self, filter: IirFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    // Maximum duration of one measurement of pressure and temperature in µs
+    pub fn measurement_time(&self, model: BarometerModel) -> u32 {
+        let (pressure, temperature): (u32, u32) = (self.pressure.samples(), self.temperature.samples());
+
+        match model {
+            BarometerModel::Bmp280 => 1250 + 2300 * temperature + 2300 * pressure + 575,
+            BarometerModel::Bmp388 => 234 + 392 + 2020 * pressure + 163 + 2020 * temperature
+        }
+    }
+
+    // odr_sel of the BMP388: the fastest output rate (200 Hz / 2^odr_sel) whose period fits a measurement, a faster
+    // one is rejected with a configuration error
+    pub(crate) fn bmp388_odr(&self) -> u8 {
+        let time: u32 = self.measurement_time(BarometerModel::Bmp388);
+        (0..17).find(|&odr| 5000 << odr >= time).unwrap_or(17)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
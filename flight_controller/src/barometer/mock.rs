@@ -0,0 +1,233 @@
+// Register level models of the BMP280 and the BMP388. The trimming parameters of the BMP280 are the example of its
+// datasheet, so with `DATASHEET_ADC_P` and `DATASHEET_ADC_T` the driver has to read the example result. The
+// BMP388 datasheet has no example, its parameters are plausible values, not the ones of a real chip.
+//
+// Like the other mocks the raw values are latched into the data registers at the start of every transaction, but only
+// in normal mode. The BMP388 model rejects an output rate faster than the measurement (ERR_REG conf_err), as the
+// chip does.
+use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation, SevenBitAddress};
+
+use super::{Address, BarometerModel, BMP280_CALIBRATION_SIZE, BMP388_CALIBRATION_SIZE};
+use crate::gy521::mock::MockError;
+
+const REGISTER_COUNT: usize = 256;
+
+// dig_T1 - dig_P9 and the raw measurement of the compensation example of the BMP280 datasheet, they result in
+// 25.08 °C and 100653.27 Pa
+pub const DATASHEET_CALIBRATION: [i32; 12] = [27504, 26435, -1000, 36477, -10685, 3024, 2855, 140, -7, 15500, -14600, 6000];
+pub const DATASHEET_ADC_T: u32 = 519888;
+pub const DATASHEET_ADC_P: u32 = 415148;
+
+// par_t1 - par_p11 of the BMP388, as stored in the NVM (before scaling)
+const BMP388_CALIBRATION: [i32; 14] = [27000, 19000, -7, -5000, -3000, 30, 0, 25000, 30000, 3, -5, 4000, 10, -50];
+const BMP388_CALIBRATION_BYTES: [usize; 14] = [2, 2, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 1, 1];
+
+// BMP280
+const BMP280_CALIB_ADDR: usize     = 0x88;
+const BMP280_CHIP_ID_ADDR: usize   = 0xD0;
+const BMP280_RESET_ADDR: usize     = 0xE0;
+const BMP280_CTRL_MEAS_ADDR: usize = 0xF4;
+const BMP280_CONFIG_ADDR: usize    = 0xF5;
+const BMP280_DATA_ADDR: usize      = 0xF7;
+const BMP280_MODE_MASK: u8         = 0b11;
+const BMP280_OSRS_P_MASK: u8       = 0b111 << 2;
+
+// BMP388
+const BMP388_CHIP_ID_ADDR: usize  = 0x00;
+const BMP388_ERR_ADDR: usize      = 0x02;
+const BMP388_DATA_ADDR: usize     = 0x04;
+const BMP388_PWR_CTRL_ADDR: usize = 0x1B;
+const BMP388_OSR_ADDR: usize      = 0x1C;
+const BMP388_ODR_ADDR: usize      = 0x1D;
+const BMP388_CONFIG_ADDR: usize   = 0x1F;
+const BMP388_CALIB_ADDR: usize    = 0x31;
+const BMP388_CMD_ADDR: usize      = 0x7E;
+const BMP388_NORMAL: u8           = 0b0011_0011;
+const BMP388_MODE_MASK: u8        = 0b0011_0000;
+const BMP388_CONF_ERR: u8         = 1 << 2;
+
+const SOFT_RESET: u8 = 0xB6;
+
+pub struct MockBarometer {
+    model: BarometerModel,
+    address: u8,
+    registers: [u8; REGISTER_COUNT],
+    calibration: [u8; BMP280_CALIBRATION_SIZE],
+    pointer: usize,
+    raw: (u32, u32) // Pressure, temperature
+}
+
+impl MockBarometer {
+    pub fn new(model: BarometerModel, address: Address) -> Self {
+        let mut calibration: [u8; BMP280_CALIBRATION_SIZE] = [0; BMP280_CALIBRATION_SIZE];
+        match model {
+            BarometerModel::Bmp280 => {
+                // dig_T1 and dig_P1 are unsigned, the others two's complement, both truncate to the same bytes
+                for (bytes, value) in calibration.chunks_exact_mut(2).zip(DATASHEET_CALIBRATION) {
+                    bytes.copy_from_slice(&(value as u16).to_le_bytes());
+                }
+            },
+            BarometerModel::Bmp388 => {
+                let mut index: usize = 0;
+                for (value, size) in BMP388_CALIBRATION.into_iter().zip(BMP388_CALIBRATION_BYTES) {
+                    calibration[index..index + size].copy_from_slice(&value.to_le_bytes()[..size]);
+                    index += size;
+                }
+            }
+        }
+
+        let mut barometer: Self = Self { model, address: address as u8, registers: [0; REGISTER_COUNT], calibration, pointer: 0, raw: (0, 0) };
+        barometer.reset();
+        barometer
+    }
+
+    pub fn model(&self) -> BarometerModel {
+        self.model
+    }
+
+    // Trimming parameters as stored in the NVM, in the order of the registers. The BMP388 uses the first
+    // `BMP388_CALIBRATION_SIZE` bytes
+    pub fn calibration(&self) -> [u8; BMP280_CALIBRATION_SIZE] {
+        self.calibration
+    }
+
+    // A brown-out: every register is back in the state after power on
+    pub fn power_cycle(&mut self) {
+        self.reset();
+        self.pointer = 0;
+    }
+
+    pub fn set_calibration(&mut self, calibration: [u8; BMP280_CALIBRATION_SIZE]) {
+        self.calibration = calibration;
+        self.reset();
+    }
+
+    // Raw ADC values, 20 bit for the BMP280 and 24 bit for the BMP388
+    pub fn set_raw(&mut self, pressure: u32, temperature: u32) {
+        self.raw = (pressure, temperature);
+    }
+
+    pub fn register(&self, register: u8) -> u8 {
+        self.registers[register as usize]
+    }
+
+    // State after power on: sleep mode, no measurement
+    fn reset(&mut self) {
+        self.registers = [0; REGISTER_COUNT];
+        match self.model {
+            BarometerModel::Bmp280 => {
+                self.registers[BMP280_CHIP_ID_ADDR] = 0x58;
+                self.registers[BMP280_CALIB_ADDR..BMP280_CALIB_ADDR + BMP280_CALIBRATION_SIZE].copy_from_slice(&self.calibration);
+                // 0x80000, a skipped measurement
+                self.registers[BMP280_DATA_ADDR] = 0x80;
+                self.registers[BMP280_DATA_ADDR + 3] = 0x80;
+            },
+            BarometerModel::Bmp388 => {
+                self.registers[BMP388_CHIP_ID_ADDR] = 0x50;
+                self.registers[BMP388_OSR_ADDR] = 0x02;
+                // 0x800000, no measurement
+                self.registers[BMP388_DATA_ADDR + 2] = 0x80;
+                self.registers[BMP388_DATA_ADDR + 5] = 0x80;
+                self.registers[BMP388_CALIB_ADDR..BMP388_CALIB_ADDR + BMP388_CALIBRATION_SIZE].copy_from_slice(&self.calibration[..BMP388_CALIBRATION_SIZE]);
+            }
+        }
+    }
+
+    fn latch_raw(&mut self) {
+        let (pressure, temperature) = self.raw;
+
+        match self.model {
+            BarometerModel::Bmp280 => {
+                let ctrl_meas: u8 = self.registers[BMP280_CTRL_MEAS_ADDR];
+                if ctrl_meas & BMP280_MODE_MASK != BMP280_MODE_MASK || ctrl_meas & BMP280_OSRS_P_MASK == 0 {
+                    return;
+                }
+                for (i, value) in [pressure, temperature].into_iter().enumerate() {
+                    let bytes: [u8; 4] = (value << 4).to_be_bytes();
+                    self.registers[BMP280_DATA_ADDR + 3 * i..BMP280_DATA_ADDR + 3 * i + 3].copy_from_slice(&bytes[1..]);
+                }
+            },
+            BarometerModel::Bmp388 => {
+                if self.registers[BMP388_PWR_CTRL_ADDR] != BMP388_NORMAL {
+                    return;
+                }
+                for (i, value) in [pressure, temperature].into_iter().enumerate() {
+                    self.registers[BMP388_DATA_ADDR + 3 * i..BMP388_DATA_ADDR + 3 * i + 3].copy_from_slice(&value.to_le_bytes()[..3]);
+                }
+            }
+        }
+    }
+
+    // Measurement time of the datasheet against the period of the output rate
+    fn check_bmp388_odr(&mut self) {
+        let osr: u8 = self.registers[BMP388_OSR_ADDR];
+        let (pressure, temperature): (u32, u32) = (1 << (osr & 0b111), 1 << (osr >> 3 & 0b111));
+        let time: u32 = 234 + 392 + 2020 * pressure + 163 + 2020 * temperature;
+
+        if 5000 << self.registers[BMP388_ODR_ADDR] < time {
+            self.registers[BMP388_ERR_ADDR] |= BMP388_CONF_ERR;
+            self.registers[BMP388_PWR_CTRL_ADDR] &= !BMP388_MODE_MASK;
+        }
+    }
+
+    fn write_registers(&mut self, bytes: &[u8]) {
+        if let Some((&register, data)) = bytes.split_first() {
+            self.pointer = register as usize;
+
+            for &byte in data {
+                match (self.model, self.pointer) {
+                    (BarometerModel::Bmp280, BMP280_RESET_ADDR) | (BarometerModel::Bmp388, BMP388_CMD_ADDR) if byte == SOFT_RESET => self.reset(),
+                    (BarometerModel::Bmp280, BMP280_CTRL_MEAS_ADDR) => self.registers[BMP280_CTRL_MEAS_ADDR] = byte,
+                    // Only taken in sleep mode
+                    (BarometerModel::Bmp280, BMP280_CONFIG_ADDR) if self.registers[BMP280_CTRL_MEAS_ADDR] & BMP280_MODE_MASK == 0 => {
+                        self.registers[BMP280_CONFIG_ADDR] = byte;
+                    },
+                    (BarometerModel::Bmp388, BMP388_PWR_CTRL_ADDR) => {
+                        self.registers[BMP388_PWR_CTRL_ADDR] = byte;
+                        if byte & BMP388_MODE_MASK == BMP388_MODE_MASK {
+                            self.check_bmp388_odr();
+                        }
+                    },
+                    (BarometerModel::Bmp388, BMP388_OSR_ADDR | BMP388_ODR_ADDR | BMP388_CONFIG_ADDR) => self.registers[self.pointer] = byte,
+                    // Everything else is read only
+                    _ => {}
+                }
+                self.pointer = (self.pointer + 1) % REGISTER_COUNT;
+            }
+        }
+    }
+
+    fn read_registers(&mut self, buffer: &mut [u8]) {
+        for byte in buffer.iter_mut() {
+            *byte = self.registers[self.pointer];
+
+            // ERR_REG clears on read
+            if self.model == BarometerModel::Bmp388 && self.pointer == BMP388_ERR_ADDR {
+                self.registers[BMP388_ERR_ADDR] = 0;
+            }
+            self.pointer = (self.pointer + 1) % REGISTER_COUNT;
+        }
+    }
+}
+
+impl ErrorType for MockBarometer {
+    type Error = MockError;
+}
+
+impl I2c<SevenBitAddress> for MockBarometer {
+    fn transaction(&mut self, address: SevenBitAddress, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        if address != self.address {
+            return Err(MockError(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)));
+        }
+
+        self.latch_raw();
+
+        for operation in operations.iter_mut() {
+            match operation {
+                Operation::Write(bytes) => self.write_registers(bytes),
+                Operation::Read(buffer) => self.read_registers(buffer)
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+// Driver for the Bosch BMP280 and BMP388 (also BMP390) barometers. Both answer on 0x76 or 0x77, so they share the
+// I2C bus of the MPU-6050 directly, without the bypass the compass needs.
+//
+// `init` detects the chip by its id, reads the trimming parameters from its NVM and starts measuring continuously
+// (normal mode) with the oversampling and IIR filter of the `BaroConfig`. `read` returns the compensated pressure and
+// temperature, `Altimeter` turns the pressure into the altitude above the ground.
+use embedded_hal::{delay::DelayNs, i2c::I2c};
+
+// Re-export
+pub use altitude::{altitude_pressure, pressure_altitude, Altimeter, SEA_LEVEL_PRESSURE};
+pub use compensation::{Bmp280Calibration, Bmp388Calibration, BMP280_CALIBRATION_SIZE, BMP388_CALIBRATION_SIZE};
+pub use configuration::{Config as BaroConfig, IirFilter, Oversampling};
+pub use error_handling::BarometerError;
+
+// BMP280, see its datasheet. Pressure and temperature are 20 bit, big endian with the lowest 4 bits in xlsb[7:4]
+const BMP280_CALIB_ADDR: u8     = 0x88; // dig_T1 - dig_P9
+const BMP280_CHIP_ID_ADDR: u8   = 0xD0;
+const BMP280_RESET_ADDR: u8     = 0xE0;
+const BMP280_CTRL_MEAS_ADDR: u8 = 0xF4;
+const BMP280_CONFIG_ADDR: u8    = 0xF5;
+const BMP280_DATA_ADDR: u8      = 0xF7; // press_msb, _lsb, _xlsb, temp_msb, _lsb, _xlsb
+
+const BMP280_CHIP_ID: u8   = 0x58;
+const BMP280_SKIPPED: i32  = 0x80000; // Value of a measurement that hasn't taken place
+const BMP280_NORMAL: u8    = 0b11;    // mode of CTRL_MEAS
+const BMP280_STANDBY: u8   = 0b000;   // t_sb of CONFIG, 0.5 ms between measurements
+
+// BMP388, see its datasheet. Pressure and temperature are 24 bit, little endian
+const BMP388_CHIP_ID_ADDR: u8  = 0x00;
+const BMP388_DATA_ADDR: u8     = 0x04; // press xlsb, lsb, msb, temp xlsb, lsb, msb
+const BMP388_PWR_CTRL_ADDR: u8 = 0x1B;
+const BMP388_OSR_ADDR: u8      = 0x1C;
+const BMP388_ODR_ADDR: u8      = 0x1D;
+const BMP388_CONFIG_ADDR: u8   = 0x1F;
+const BMP388_CALIB_ADDR: u8    = 0x31; // PAR_T1 - PAR_P11
+const BMP388_CMD_ADDR: u8      = 0x7E;
+
+const BMP388_CHIP_IDS: [u8; 2] = [0x50, 0x60]; // BMP388, BMP390
+const BMP388_SKIPPED: u32      = 0x800000;     // Reset value of the data registers, no measurement yet
+const BMP388_PWR_CTRL: u8      = 0b0011_0011;  // Normal mode, pressure and temperature enabled
+
+const SOFT_RESET: u8  = 0xB6; // RESET of the BMP280 and CMD of the BMP388
+const STARTUP_MS: u32 = 2;    // After power on or a soft reset, both chips
+
+mod error_handling {
+    use core::fmt::Debug;
+
+    // E is the error of the I2C bus
+    pub enum BarometerError<E> {
+        I2C(E),
+        // Neither the id of a BMP280 nor the one of a BMP388 was read
+        UnknownDevice,
+        // `init` has to be called first
+        NotInitialized,
+        // No measurement has finished since the start, or the trimming parameters are broken
+        InvalidData,
+        // The chip didn't keep the value written to the register
+        Verification { register: u8, written: u8, read: u8 }
+    }
+
+    impl<E> From<E> for BarometerError<E> {
+        fn from(err: E) -> Self {
+            BarometerError::I2C(err)
+        }
+    }
+
+    impl<E: Debug> Debug for BarometerError<E> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match self {
+                BarometerError::I2C(err) => write!(f, "I2C transaction with the barometer failed with: {err:?}")?,
+                BarometerError::UnknownDevice => write!(f, "Found neither a BMP280 nor a BMP388")?,
+                BarometerError::NotInitialized => write!(f, "Initialize the barometer with init first")?,
+                BarometerError::InvalidData => write!(f, "The barometer returned no valid measurement")?,
+                BarometerError::Verification { register, written, read } => write!(f, "Register {register:#04x} reads {read:#04x} after writing {written:#04x}")?
+            }
+            Ok(())
+        }
+    }
+}
+
+// Oversampling and IIR filter
+mod configuration;
+
+// Compensation formulas of the datasheets
+mod compensation;
+
+// Pressure altitude and the ground reference
+mod altitude;
+
+// Register level BMP280 and BMP388 for running the driver without hardware
+pub mod mock;
+
+// The I2C address is selected with the SDO pin of the breakout
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Address {
+    SdoLow  = 0x76,
+    SdoHigh = 0x77
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BarometerModel {
+    Bmp280,
+    Bmp388
+}
+
+// Trimming parameters of the detected chip
+#[derive(Clone, Copy)]
+enum Calibration {
+    Bmp280(Bmp280Calibration),
+    Bmp388(Bmp388Calibration)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BarometerData {
+    pub pressure: f32,   // Pa
+    pub temperature: f32 // °C, of the chip
+}
+
+pub struct Barometer<I2C, D> {
+    master: I2C,
+    address: u8,
+    delay: D,
+    config: Option<BaroConfig>,
+    calibration: Option<Calibration>
+}
+
+impl<I2C: I2c, D: DelayNs> Barometer<I2C, D> {
+    pub fn new(master: I2C, address: Address, delay: D) -> Self {
+        Self { master, address: address as u8, delay, config: None, calibration: None }
+    }
+
+    // Gives the bus back, e.g. to share it with another sensor
+    pub fn release(self) -> (I2C, D) {
+        (self.master, self.delay)
+    }
+
+    // None before `init`
+    pub fn model(&self) -> Option<BarometerModel> {
+        match self.calibration? {
+            Calibration::Bmp280(_) => Some(BarometerModel::Bmp280),
+            Calibration::Bmp388(_) => Some(BarometerModel::Bmp388)
+        }
+    }
+
+    // Identifies the chip at the address by its id. The BMP280 is asked first, on a BMP388 its id register is reserved
+    pub fn probe(&mut self) -> Result<BarometerModel, BarometerError<I2C::Error>> {
+        if self.read_register(BMP280_CHIP_ID_ADDR)? == BMP280_CHIP_ID {
+            return Ok(BarometerModel::Bmp280);
+        }
+        if BMP388_CHIP_IDS.contains(&self.read_register(BMP388_CHIP_ID_ADDR)?) {
+            return Ok(BarometerModel::Bmp388);
+        }
+        Err(BarometerError::UnknownDevice)
+    }
+
+    // Probes and resets the chip, reads its trimming parameters and starts continuous measurements. Every register
+    // written is read back
+    pub fn init(&mut self, config: BaroConfig) -> Result<BarometerModel, BarometerError<I2C::Error>> {
+        let model: BarometerModel = self.probe()?;
+
+        // The configuration of the BMP280 is only taken in sleep mode, which a reset enters on both chips
+        let reset_register: u8 = match model {
+            BarometerModel::Bmp280 => BMP280_RESET_ADDR,
+            BarometerModel::Bmp388 => BMP388_CMD_ADDR
+        };
+        self.master.write(self.address, &[reset_register, SOFT_RESET])?;
+        self.delay.delay_ms(STARTUP_MS);
+
+        let calibration: Calibration = match model {
+            BarometerModel::Bmp280 => {
+                let mut registers: [u8; BMP280_CALIBRATION_SIZE] = [0; BMP280_CALIBRATION_SIZE];
+                self.master.write_read(self.address, &[BMP280_CALIB_ADDR], &mut registers)?;
+                Calibration::Bmp280(Bmp280Calibration::from_registers(&registers))
+            },
+            BarometerModel::Bmp388 => {
+                let mut registers: [u8; BMP388_CALIBRATION_SIZE] = [0; BMP388_CALIBRATION_SIZE];
+                self.master.write_read(self.address, &[BMP388_CALIB_ADDR], &mut registers)?;
+                Calibration::Bmp388(Bmp388Calibration::from_registers(&registers))
+            }
+        };
+
+        let pressure: u8 = config.pressure.register(model);
+        let temperature: u8 = config.temperature.register(model);
+        let filter: u8 = config.filter as u8;
+
+        // The mode comes last, the measurements start with the final configuration
+        let bmp280: [(u8, u8); 2] = [
+            (BMP280_CONFIG_ADDR, BMP280_STANDBY << 5 | filter << 2),
+            (BMP280_CTRL_MEAS_ADDR, temperature << 5 | pressure << 2 | BMP280_NORMAL)
+        ];
+        let bmp388: [(u8, u8); 4] = [
+            (BMP388_OSR_ADDR, temperature << 3 | pressure),
+            (BMP388_ODR_ADDR, config.bmp388_odr()),
+            (BMP388_CONFIG_ADDR, filter << 1),
+            (BMP388_PWR_CTRL_ADDR, BMP388_PWR_CTRL)
+        ];
+        let registers: &[(u8, u8)] = match model {
+            BarometerModel::Bmp280 => &bmp280,
+            BarometerModel::Bmp388 => &bmp388
+        };
+
+        for &(register, value) in registers {
+            self.master.write(self.address, &[register, value])?;
+        }
+        for &(register, value) in registers {
+            self.verify_register(register, value)?;
+        }
+
+        self.config = Some(config);
+        self.calibration = Some(calibration);
+        Ok(model)
+    }
+
+    // After the chip lost its registers or the bus was cleared: runs `init` with the last configuration
+    pub fn reinit(&mut self) -> Result<BarometerModel, BarometerError<I2C::Error>> {
+        let config: BaroConfig = self.config.ok_or(BarometerError::NotInitialized)?;
+        self.init(config)
+    }
+
+    // Latest measurement. In normal mode the chip measures continuously, see `BaroConfig::measurement_time`
+    pub fn read(&mut self) -> Result<BarometerData, BarometerError<I2C::Error>> {
+        let calibration: Calibration = self.calibration.ok_or(BarometerError::NotInitialized)?;
+
+        match calibration {
+            Calibration::Bmp280(calibration) => {
+                let mut data: [u8; 6] = [0; 6];
+                self.master.write_read(self.address, &[BMP280_DATA_ADDR], &mut data)?;
+
+                let [adc_p, adc_t] = [0, 3].map(|i| (data[i] as i32) << 12 | (data[i + 1] as i32) << 4 | (data[i + 2] as i32) >> 4);
+                if adc_p == BMP280_SKIPPED || adc_t == BMP280_SKIPPED {
+                    return Err(BarometerError::InvalidData);
+                }
+
+                let (t_fine, temperature) = calibration.temperature(adc_t);
+                let pressure: u32 = calibration.pressure(adc_p, t_fine).ok_or(BarometerError::InvalidData)?;
+                Ok(BarometerData { pressure: pressure as f32 / 256.0, temperature: temperature as f32 / 100.0 })
+            },
+            Calibration::Bmp388(calibration) => {
+                let mut data: [u8; 6] = [0; 6];
+                self.master.write_read(self.address, &[BMP388_DATA_ADDR], &mut data)?;
+
+                let [raw_p, raw_t] = [0, 3].map(|i| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], 0]));
+                if raw_p == BMP388_SKIPPED || raw_t == BMP388_SKIPPED {
+                    return Err(BarometerError::InvalidData);
+                }
+
+                let t_lin: f64 = calibration.temperature(raw_t);
+                let pressure: f64 = calibration.pressure(raw_p, t_lin);
+                if pressure.is_nan() || pressure <= 0.0 {
+                    return Err(BarometerError::InvalidData);
+                }
+                Ok(BarometerData { pressure: pressure as f32, temperature: t_lin as f32 })
+            }
+        }
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u8, BarometerError<I2C::Error>> {
+        let mut value: [u8; 1] = [0];
+        self.master.write_read(self.address, &[register], &mut value)?;
+        Ok(value[0])
+    }
+
+    fn verify_register(&mut self, register: u8, written: u8) -> Result<(), BarometerError<I2C::Error>> {
+        let read: u8 = self.read_register(register)?;
+
+        if read != written {
+            return Err(BarometerError::Verification { register, written, read });
+        }
+        Ok(())
+    }
+}
+
+// The driver against the register level mock, run on the host by `cargo test` in the simulator
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use embedded_hal_bus::i2c::RefCellDevice;
+    use crate::gy521::mock::MockDelay;
+    use mock::{MockBarometer, DATASHEET_ADC_P, DATASHEET_ADC_T};
+
+    #[test]
+    fn read_rejects_the_data_registers_before_the_first_measurement() {
+        // Raw values at room temperature and ground level pressure
+        for (model, raw) in [(BarometerModel::Bmp280, (DATASHEET_ADC_P, DATASHEET_ADC_T)), (BarometerModel::Bmp388, (5_000_000, 8_400_000))] {
+            let bus: RefCell<MockBarometer> = RefCell::new(MockBarometer::new(model, Address::SdoHigh));
+            let mut barometer: Barometer<RefCellDevice<MockBarometer>, MockDelay> = Barometer::new(RefCellDevice::new(&bus), Address::SdoHigh, MockDelay);
+            assert!(matches!(barometer.read(), Err(BarometerError::NotInitialized)));
+
+            bus.borrow_mut().set_raw(raw.0, raw.1);
+            assert_eq!(barometer.init(BaroConfig::default()).ok(), Some(model));
+            assert!(barometer.read().is_ok());
+
+            // After a brown-out the chip sleeps with the reset values in the data registers
+            bus.borrow_mut().power_cycle();
+            assert!(matches!(barometer.read(), Err(BarometerError::InvalidData)));
+
+            barometer.reinit().expect("mock barometer");
+            assert!(barometer.read().is_ok());
+        }
+    }
+
+    #[test]
+    fn bmp388_reads_a_zero_measurement() {
+        // 0 is a valid raw value of the BMP388, only the reset value means that nothing was measured
+        let bus: RefCell<MockBarometer> = RefCell::new(MockBarometer::new(BarometerModel::Bmp388, Address::SdoHigh));
+        let mut barometer: Barometer<RefCellDevice<MockBarometer>, MockDelay> = Barometer::new(RefCellDevice::new(&bus), Address::SdoHigh, MockDelay);
+        barometer.init(BaroConfig::default()).expect("mock barometer");
+
+        bus.borrow_mut().set_raw(5_000_000, 0);
+        assert!(barometer.read().is_ok());
+    }
+}
@@ -2,12 +2,74 @@
 #![no_main]
 #![feature(allocator_api)]
 
+use core::cell::RefCell;
+use critical_section::Mutex;
 use esp_backtrace as _;
 use esp_hal::clock::CpuClock;
-use esp_hal::peripherals::{Peripherals, TIMG0};
+use esp_hal::delay::Delay;
+use esp_hal::gpio::{Event, GpioPin, Input, Io, Level, OutputOpenDrain, Pull};
+use esp_hal::handler;
+use esp_hal::interrupt::InterruptConfigurable;
+use embedded_hal::i2c::{Error as _, ErrorKind, ErrorType, Operation};
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_hal::i2c::master::{Config as I2cConfig, ConfigError as I2cConfigError, Error as I2cError, I2c};
+use esp_hal::Blocking;
+use esp_hal::peripheral::Peripheral;
+use esp_hal::peripherals::{Peripherals, I2C0, TIMG0};
 use esp_hal::Config;
 use esp_hal::timer::timg::TimerGroup;
+use esp_storage::FlashStorage;
+use flight_controller::barometer::{Address as BaroAddress, Altimeter, Barometer, BaroConfig};
+use flight_controller::compass::{Compass, MagCalibrationSweep};
 use flight_controller::esc::{ESCControler, RotorStrength};
+use flight_controller::gy521::{
+    clear_bus, load_calibration, store_calibration, Address, BoardAlignment, CalibrationOffsets, DataFrame, DataReady, GY521,
+    GY521Error, InterruptConfig, LinkMonitor, MPUConfig, SelfTestResult, SensorAxis, StorageError
+};
+use flight_controller::health::{HealthState, SensorHealth};
+use flight_controller::math::{compute_angle_magnetometer, ComplementaryFilter, MagnetometerData, TimeStep};
+use flight_controller::redundancy::{ActiveImu, ImuUnit, ImuVoter};
+use flight_controller::stationary::GyroBiasEstimator;
+
+// Start of the calib partition in partitions.csv
+const CALIBRATION_PARTITION: u32 = 0x3F0000;
+// One flash sector per IMU
+const CALIBRATION_SLOT: u32 = 0x1000;
+
+// An MPU-6050 on the shared I2C0
+#[cfg(not(feature = "wifi"))]
+type Imu<'bus> = GY521<RefCellDevice<'bus, I2cBus>, Delay>;
+
+// Mounting of the GY521 on the frame: sensor axes pointing to the nose and up, fine trim (roll, pitch, yaw) in degrees
+const IMU_FORWARD: SensorAxis = SensorAxis::PosX;
+const IMU_UP: SensorAxis = SensorAxis::PosZ;
+const IMU_TRIM: [f32; 3] = [0.0, 0.0, 0.0];
+
+// Mounting of the GY-271 magnetometer, read every COMPASS_PERIOD µs (it measures at 75 or 100 Hz)
+const COMPASS_FORWARD: SensorAxis = SensorAxis::PosX;
+const COMPASS_UP: SensorAxis = SensorAxis::PosZ;
+const COMPASS_PERIOD: u64 = 10_000;
+
+// The barometer is read every BARO_PERIOD µs (the default configuration measures at 50 Hz), the ground reference is
+// the mean of the first BARO_GROUND_SAMPLES measurements
+const BARO_PERIOD: u64 = 20_000;
+const BARO_GROUND_SAMPLES: u32 = 50;
+
+// INT pin of the MPU-6050, set by the GPIO interrupt handler and awaited by the control loop
+static IMU_DATA_READY: DataReady = DataReady::new();
+static IMU_INT_PIN: Mutex<RefCell<Option<Input>>> = Mutex::new(RefCell::new(None));
+
+#[handler]
+fn gpio_handler() {
+    critical_section::with(|cs| {
+        if let Some(pin) = IMU_INT_PIN.borrow_ref_mut(cs).as_mut() {
+            if pin.is_interrupt_set() {
+                pin.clear_interrupt();
+                IMU_DATA_READY.signal();
+            }
+        }
+    });
+}
 
 #[esp_hal::main]
 fn main() -> ! {
@@ -52,4 +114,322 @@ fn regular_main(peripherals: Peripherals) {
 
     esc_controller.init().unwrap();
     esc_controller.update_rotor_frequency(RotorStrength::new(0, 50, 0, 0)).unwrap();
+
+    let i2c: I2cBus = I2cBus::new(peripherals.I2C0, peripherals.GPIO21, peripherals.GPIO22).expect("Creation of Master failed");
+
+    let clock: fn() -> u64 = || esp_hal::time::now().duration_since_epoch().to_micros();
+    let alignment: BoardAlignment = BoardAlignment::new(IMU_FORWARD, IMU_UP)
+        .expect("IMU_FORWARD and IMU_UP have to be perpendicular")
+        .set_trim(IMU_TRIM[0], IMU_TRIM[1], IMU_TRIM[2]);
+
+    // Both MPU-6050s share I2C0, AD0 of the secondary is pulled high. Each one keeps its own calibration
+    let i2c_bus: RefCell<I2cBus> = RefCell::new(i2c);
+    let mut flash: FlashStorage = FlashStorage::new();
+
+    let mut primary: Option<Imu> = setup_imu(&i2c_bus, Address::AD0Low, &mut flash, CALIBRATION_PARTITION, alignment, clock)
+        .inspect_err(|err| log::error!("The primary IMU failed: {err:?}"))
+        .ok();
+    let mut secondary: Option<Imu> = setup_imu(&i2c_bus, Address::AD0High, &mut flash, CALIBRATION_PARTITION + CALIBRATION_SLOT, alignment, clock)
+        .inspect_err(|err| log::warn!("No secondary IMU: {err:?}"))
+        .ok();
+    assert!(primary.is_some() || secondary.is_some(), "No IMU left");
+
+    // The magnetometer sits on the auxiliary bus of the primary (GY-87) or directly on I2C0 (GY-271)
+    if let Some(Err(err)) = primary.as_mut().map(|imu| imu.set_bypass(true)) {
+        log::warn!("Enabling the bypass of the primary IMU failed: {err:?}");
+    }
+    let compass_alignment: BoardAlignment = BoardAlignment::new(COMPASS_FORWARD, COMPASS_UP)
+        .expect("COMPASS_FORWARD and COMPASS_UP have to be perpendicular");
+    let mut compass: Option<Compass<RefCellDevice<I2cBus>>> = Compass::detect(RefCellDevice::new(&i2c_bus))
+        .ok()
+        .and_then(|mut magnetometer| {
+            magnetometer.set_alignment(compass_alignment);
+            magnetometer.init()
+                .inspect_err(|err| log::error!("The magnetometer failed: {err:?}"))
+                .ok()
+                .map(|_| magnetometer)
+        });
+    log::info!("Magnetometer: {:?}", compass.as_ref().map(|magnetometer| magnetometer.model()));
+
+    // The barometer sits directly on I2C0, SDO selects its address
+    let mut barometer: Option<Barometer<RefCellDevice<I2cBus>, Delay>> = [BaroAddress::SdoHigh, BaroAddress::SdoLow]
+        .into_iter()
+        .find_map(|address| {
+            let mut sensor: Barometer<RefCellDevice<I2cBus>, Delay> = Barometer::new(RefCellDevice::new(&i2c_bus), address, Delay::new());
+            sensor.init(BaroConfig::default()).ok().map(|_| sensor)
+        });
+    log::info!("Barometer: {:?}", barometer.as_ref().and_then(|sensor| sensor.model()));
+
+    // Only the INT pin of the primary is connected
+    if let Some(imu) = primary.as_mut() {
+        imu.enable_data_ready(InterruptConfig::default()).unwrap();
+    }
+
+    let mut io: Io = Io::new(peripherals.IO_MUX);
+    io.set_interrupt_handler(gpio_handler);
+
+    let mut int_pin: Input = Input::new(peripherals.GPIO19, Pull::Down);
+    critical_section::with(|cs| {
+        int_pin.listen(Event::RisingEdge);
+        IMU_INT_PIN.borrow_ref_mut(cs).replace(int_pin);
+    });
+
+    let sample_period: u64 = 1_000_000 / MPUConfig::default().get_sample_rate() as u64;
+    let mut voter: ImuVoter = ImuVoter::new();
+    let mut vote_step: TimeStep = TimeStep::new();
+    let mut active: ActiveImu = ActiveImu::None;
+    let mut links: [LinkMonitor; 2] = [LinkMonitor::new(), LinkMonitor::new()];
+    let mut health: SensorHealth = SensorHealth::new(&MPUConfig::default());
+    let mut health_state: HealthState = HealthState::Failed;
+
+    let mut time_step: TimeStep = TimeStep::new();
+    let mut attitude: ComplementaryFilter = ComplementaryFilter::new(0.5);
+    let mut gyro_bias: GyroBiasEstimator = GyroBiasEstimator::new();
+
+    // There is no stored compass calibration, it is fitted while the drone is turned in every direction after boot.
+    // Until then the heading isn't used
+    let mut compass_sweep: Option<MagCalibrationSweep> = Some(MagCalibrationSweep::new());
+    let mut compass_step: TimeStep = TimeStep::new();
+    let mut next_compass: u64 = 0;
+
+    // There is no arming yet, the ground reference is taken right after boot while the drone stands on the ground
+    let mut altimeter: Altimeter = Altimeter::new();
+    let mut ground_samples: (u32, f32) = (0, 0.0); // Count, sum of the pressure in Pa
+    let mut next_baro: u64 = 0;
+
+    loop {
+        // Paced by the primary. Without its pulse (e.g. the primary is gone) the cycle runs after three sample periods
+        IMU_DATA_READY.wait_timeout(clock, 3 * sample_period);
+
+        // Both units are read directly instead of through the FIFO, so their frames are from the same instant
+        let primary_frame: Option<DataFrame> = primary.as_mut().and_then(|imu| {
+            let result: Result<DataFrame, GY521Error<BusError>> = imu.read();
+            links[ImuUnit::Primary as usize].report(&result);
+            result.ok()
+        });
+        let secondary_frame: Option<DataFrame> = secondary.as_mut().and_then(|imu| {
+            let result: Result<DataFrame, GY521Error<BusError>> = imu.read();
+            links[ImuUnit::Secondary as usize].report(&result);
+            result.ok()
+        });
+
+        let dt: f32 = vote_step.step(clock()).unwrap_or(0.0);
+        let frame: Option<DataFrame> = voter.update(primary_frame, secondary_frame, dt);
+
+        if voter.active() != active {
+            active = voter.active();
+            log::warn!(
+                "Active IMU: {active:?}, primary: {:?}, secondary: {:?}",
+                voter.fault(ImuUnit::Primary), voter.fault(ImuUnit::Secondary)
+            );
+        }
+
+        // There is no failsafe yet, changes are only logged
+        let state: HealthState = match frame.as_ref() {
+            Some(frame) => health.update(frame),
+            None => health.check(clock())
+        };
+        if state != health_state {
+            health_state = state;
+            log::warn!(
+                "IMU health: {state:?} {:?}, vibration: {:?} g {:?} °/s",
+                health.issues(), health.accel_vibration(), health.gyro_vibration()
+            );
+        }
+
+        // A hung bus takes both units down, so one bus clear serves both. It takes a few milliseconds, meanwhile the
+        // estimator keeps its last attitude
+        let now: u64 = clock();
+        let due: [bool; 2] = [ImuUnit::Primary, ImuUnit::Secondary].map(|unit| links[unit as usize].should_recover(now));
+        if due.contains(&true) {
+            let released: bool = i2c_bus.borrow_mut().recover();
+
+            for ((imu, link), due) in [primary.as_mut(), secondary.as_mut()].into_iter().zip(links.iter_mut()).zip(due) {
+                if let (Some(imu), true) = (imu, due) {
+                    let result: Result<(), GY521Error<BusError>> = imu.reinit();
+                    link.recovered(released && result.is_ok());
+                    log::warn!("I2C recovery, SDA released: {released}, reinit: {result:?}, recoveries: {}", link.recoveries());
+                }
+            }
+
+            // The magnetometer may have lost its mode as well, reinit brought the bypass back
+            if let Some(Err(err)) = compass.as_mut().map(|magnetometer| magnetometer.init()) {
+                log::warn!("The magnetometer didn't come back: {err:?}");
+            }
+            if let Some(Err(err)) = barometer.as_mut().map(|sensor| sensor.reinit()) {
+                log::warn!("The barometer didn't come back: {err:?}");
+            }
+        }
+
+        // Frames with an implausible time step are skipped, the next one is measured against this frame again
+        if let Some(frame) = frame {
+            if let Some(dt) = time_step.step_frame(&frame) {
+                // There is no arming yet, spinning motors keep the estimator from refining the bias through vibration
+                gyro_bias.update(&frame, dt);
+                attitude.update(&gyro_bias.correct(&frame), dt);
+            }
+
+            if let Some(magnetometer) = compass.as_mut().filter(|_| clock() >= next_compass) {
+                next_compass = clock() + COMPASS_PERIOD;
+
+                match compass_sweep.as_mut() {
+                    Some(sweep) => {
+                        if let Ok(raw) = magnetometer.read_raw() {
+                            sweep.add(raw);
+                        }
+                        if let Some(calibration) = sweep.fit() {
+                            log::info!("Magnetometer calibrated: {calibration:?}");
+                            magnetometer.set_calibration(calibration);
+                            compass_sweep = None;
+                        }
+                    },
+                    None => {
+                        let mag: Option<MagnetometerData> = magnetometer.read().ok();
+                        if let (Some(mag), Some(dt)) = (mag, compass_step.step(clock())) {
+                            attitude.fuse_heading(compute_angle_magnetometer(frame.get_accel(), &mag).yaw(), dt);
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(sensor) = barometer.as_mut().filter(|_| clock() >= next_baro) {
+            next_baro = clock() + BARO_PERIOD;
+
+            if let Ok(data) = sensor.read() {
+                match altimeter.altitude(data.pressure) {
+                    // Nothing uses the altitude yet
+                    Some(altitude) => log::debug!("Altitude: {altitude:.2} m, {:.2} °C", data.temperature),
+                    None => {
+                        ground_samples = (ground_samples.0 + 1, ground_samples.1 + data.pressure);
+                        if ground_samples.0 == BARO_GROUND_SAMPLES {
+                            altimeter.set_ground_reference(ground_samples.1 / BARO_GROUND_SAMPLES as f32);
+                            log::info!("Ground reference: {:?} m above sea level", altimeter.ground());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Initializes, self-tests and calibrates one MPU-6050. Its calibration is loaded from offset or, if there is none,
+// measured and stored there, then the board has to rest level
+#[cfg(not(feature = "wifi"))]
+fn setup_imu<'bus>(
+    bus: &'bus RefCell<I2cBus>,
+    address: Address,
+    flash: &mut FlashStorage,
+    offset: u32,
+    alignment: BoardAlignment,
+    clock: fn() -> u64
+) -> Result<Imu<'bus>, GY521Error<BusError>> {
+    let mut gy521: Imu = GY521::new(RefCellDevice::new(bus), address, Delay::new(), clock);
+    gy521.init(MPUConfig::default())?;
+
+    let self_test: SelfTestResult = gy521.self_test()?;
+    if !self_test.passed() {
+        log::error!("IMU self-test failed, deviation from the factory trim: {self_test:?}");
+    }
+
+    let stored: Result<CalibrationOffsets, StorageError<_>> = load_calibration(flash, offset);
+    match stored {
+        Ok(calibration) => gy521.set_calibration_offsets(calibration),
+        Err(err) => {
+            log::warn!("Calibrating the IMU, no usable calibration in flash: {err:?}");
+            gy521.calibrate(500)?;
+
+            if let Err(err) = store_calibration(flash, offset, gy521.calibration_offsets()) {
+                log::error!("Storing the IMU calibration failed: {err:?}");
+            }
+        }
+    }
+
+    gy521.set_alignment(alignment);
+    Ok(gy521)
+}
+
+// I2C0 with SDA on GPIO21 and SCL on GPIO22. The bus owns the peripheral and both pins, the master is built from them
+// once and only torn down by `recover`, which drives the pins as plain GPIOs for the bus clear and then builds a new
+// master, since the old one may hang in its own state machine as well
+#[cfg(not(feature = "wifi"))]
+struct I2cBus {
+    i2c: I2C0,
+    sda: GpioPin<21>,
+    scl: GpioPin<22>,
+    // None if the master couldn't be built again after a recovery
+    master: Option<I2c<'static, Blocking>>
+}
+
+#[cfg(not(feature = "wifi"))]
+impl I2cBus {
+    // Fails if the peripheral doesn't take the configuration
+    fn new(i2c: I2C0, sda: GpioPin<21>, scl: GpioPin<22>) -> Result<Self, I2cConfigError> {
+        let mut bus: Self = Self { i2c, sda, scl, master: None };
+        bus.master = Some(bus.build_master()?);
+        Ok(bus)
+    }
+
+    fn build_master(&mut self) -> Result<I2c<'static, Blocking>, I2cConfigError> {
+        // SAFETY: the bus owns the peripheral and the pins and the master is the only user of the copies. The bus
+        // itself only uses them in `recover`, after the master was dropped
+        let (i2c, sda, scl) = unsafe { (self.i2c.clone_unchecked(), self.sda.clone_unchecked(), self.scl.clone_unchecked()) };
+        Ok(I2c::new(i2c, I2cConfig::default())?.with_sda(sda).with_scl(scl))
+    }
+
+    // Bus clear with both pins as GPIOs, see `clear_bus`, then a new master. Returns whether the slaves released SDA
+    // and the master is back
+    fn recover(&mut self) -> bool {
+        // Releases the pins
+        self.master = None;
+
+        let released: bool = {
+            let mut scl: OutputOpenDrain = OutputOpenDrain::new(&mut self.scl, Level::High, Pull::Up);
+            let mut sda: OutputOpenDrain = OutputOpenDrain::new(&mut self.sda, Level::High, Pull::Up);
+            clear_bus(&mut scl, &mut sda, &mut Delay::new())
+        };
+
+        match self.build_master() {
+            Ok(master) => {
+                self.master = Some(master);
+                released
+            },
+            Err(err) => {
+                log::error!("Creation of Master failed: {err:?}");
+                false
+            }
+        }
+    }
+}
+
+// Error of a transaction on the `I2cBus`
+#[cfg(not(feature = "wifi"))]
+#[derive(Debug)]
+enum BusError {
+    // The last recovery couldn't build the master, the next one tries again
+    NoMaster,
+    Transfer(I2cError)
+}
+
+#[cfg(not(feature = "wifi"))]
+impl embedded_hal::i2c::Error for BusError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            BusError::NoMaster => ErrorKind::Other,
+            BusError::Transfer(err) => err.kind()
+        }
+    }
+}
+
+#[cfg(not(feature = "wifi"))]
+impl ErrorType for I2cBus {
+    type Error = BusError;
+}
+
+#[cfg(not(feature = "wifi"))]
+impl embedded_hal::i2c::I2c for I2cBus {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        let master: &mut I2c<'static, Blocking> = self.master.as_mut().ok_or(BusError::NoMaster)?;
+        embedded_hal::i2c::I2c::transaction(master, address, operations).map_err(BusError::Transfer)
+    }
 }
@@ -0,0 +1,293 @@
+// Hard and soft iron calibration of the magnetometer.
+//
+// Turned in every direction, an undistorted magnetometer draws a sphere around the origin. Magnetized parts of the
+// frame (hard iron) shift it by a constant offset, soft magnetic material and different axis gains (soft iron)
+// squeeze it into an ellipsoid. The ellipsoid is fitted by least squares as the quadric
+//
+// A x² + B y² + C z² + 2D xy + 2E xz + 2F yz + 2G x + 2H y + 2I z = 1
+//
+// whose center is the offset. The symmetric square root of its matrix turns the ellipsoid back into a sphere,
+// scaled to the mean radius, so the output stays in gauss:
+//
+// corrected = matrix * (raw - offset)
+use libm::{fabs, pow, sqrt};
+
+// Fewer samples can't constrain the nine parameters against noise
+pub const MIN_SAMPLES: u32 = 100;
+
+// Smallest ratio of the covered range of one axis to the one of the best covered axis. Turning the drone only
+// around yaw leaves z nearly constant, the fit would invent its gain
+const MIN_COVERAGE: f32 = 0.7;
+
+// Jacobi rotations for the eigenvalues, a 3x3 matrix converges after a handful
+const JACOBI_SWEEPS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MagCalibration {
+    pub(crate) offset: [f32; 3],     // Ga
+    pub(crate) matrix: [[f32; 3]; 3]
+}
+
+impl MagCalibration {
+    // Hard iron offset in gauss
+    pub fn offset(&self) -> [f32; 3] {
+        self.offset
+    }
+
+    // Soft iron correction
+    pub fn matrix(&self) -> [[f32; 3]; 3] {
+        self.matrix
+    }
+
+    pub fn apply(&self, raw: [f32; 3]) -> [f32; 3] {
+        let centered: [f32; 3] = [raw[0] - self.offset[0], raw[1] - self.offset[1], raw[2] - self.offset[2]];
+        self.matrix.map(|row| row[0] * centered[0] + row[1] * centered[1] + row[2] * centered[2])
+    }
+}
+
+impl Default for MagCalibration {
+    // Passes the raw field unchanged
+    fn default() -> Self {
+        Self { offset: [0.0; 3], matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]] }
+    }
+}
+
+// Collects raw readings while the drone is turned in every direction, far from metal. Like `TemperatureSweep` only
+// the sums of the least squares problem are kept.
+#[derive(Debug, Clone)]
+pub struct MagCalibrationSweep {
+    samples: u32,
+    min: [f32; 3],
+    max: [f32; 3],
+    normal: [[f64; 9]; 9], // Σ v vᵀ
+    rhs: [f64; 9]          // Σ v
+}
+
+impl MagCalibrationSweep {
+    pub fn new() -> Self {
+        Self { samples: 0, min: [f32::INFINITY; 3], max: [f32::NEG_INFINITY; 3], normal: [[0.0; 9]; 9], rhs: [0.0; 9] }
+    }
+
+    // raw is the output of `Compass::read_raw`
+    pub fn add(&mut self, raw: [f32; 3]) {
+        let [x, y, z] = raw.map(|value| value as f64);
+        let v: [f64; 9] = [x * x, y * y, z * z, 2.0 * x * y, 2.0 * x * z, 2.0 * y * z, 2.0 * x, 2.0 * y, 2.0 * z];
+
+        for (row, value) in self.normal.iter_mut().zip(v) {
+            for (sum, other) in row.iter_mut().zip(v) {
+                *sum += value * other;
+            }
+        }
+        for (sum, value) in self.rhs.iter_mut().zip(v) {
+            *sum += value;
+        }
+
+        for ((min, max), value) in self.min.iter_mut().zip(self.max.iter_mut()).zip(raw) {
+            *min = min.min(value);
+            *max = max.max(value);
+        }
+        self.samples += 1;
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    // 0 - 1, the range of the worst covered axis relative to the best covered one
+    pub fn coverage(&self) -> f32 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+
+        let span: [f32; 3] = [0, 1, 2].map(|axis| self.max[axis] - self.min[axis]);
+        let widest: f32 = span[0].max(span[1]).max(span[2]);
+        if widest > 0.0 { span[0].min(span[1]).min(span[2]) / widest } else { 0.0 }
+    }
+
+    // True once there are enough samples and every axis is covered
+    pub fn is_complete(&self) -> bool {
+        self.samples >= MIN_SAMPLES && self.coverage() >= MIN_COVERAGE
+    }
+
+    // None if the sweep isn't complete or the samples don't lie on an ellipsoid
+    pub fn fit(&self) -> Option<MagCalibration> {
+        if !self.is_complete() {
+            return None;
+        }
+
+        let p: [f64; 9] = solve(self.normal, self.rhs)?;
+        let quadric: [[f64; 3]; 3] = [[p[0], p[3], p[4]], [p[3], p[1], p[5]], [p[4], p[5], p[2]]];
+
+        // Center: the gradient A c + b vanishes
+        let center: [f64; 3] = solve(quadric, [-p[6], -p[7], -p[8]])?;
+
+        // (x - c)ᵀ A (x - c) = 1 + cᵀ A c
+        let scale: f64 = 1.0 + (0..3).map(|row| center[row] * (0..3).map(|k| quadric[row][k] * center[k]).sum::<f64>()).sum::<f64>();
+        if scale.is_nan() || scale <= 0.0 {
+            return None;
+        }
+
+        let (eigenvalues, eigenvectors) = eigen(quadric.map(|row| row.map(|value| value / scale)));
+        if eigenvalues.iter().any(|&value| value.is_nan() || value <= 0.0) {
+            return None;
+        }
+
+        // The semi axes are 1 / √λ, their geometric mean is the radius of the sphere
+        let radius: f64 = pow(eigenvalues[0] * eigenvalues[1] * eigenvalues[2], -1.0 / 6.0);
+        let roots: [f64; 3] = eigenvalues.map(sqrt);
+
+        // radius * V diag(√λ) Vᵀ
+        let matrix: [[f32; 3]; 3] = [0, 1, 2].map(|row| {
+            [0, 1, 2].map(|column| {
+                (radius * (0..3).map(|k| eigenvectors[row][k] * roots[k] * eigenvectors[column][k]).sum::<f64>()) as f32
+            })
+        });
+
+        Some(MagCalibration { offset: center.map(|value| value as f32), matrix })
+    }
+}
+
+impl Default for MagCalibrationSweep {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Gaussian elimination with partial pivoting, None for a singular matrix
+fn solve<const N: usize>(mut matrix: [[f64; N]; N], mut rhs: [f64; N]) -> Option<[f64; N]> {
+    for column in 0..N {
+        let pivot: usize = (column..N).max_by(|&a, &b| fabs(matrix[a][column]).total_cmp(&fabs(matrix[b][column])))?;
+        if fabs(matrix[pivot][column]) < 1e-12 {
+            return None;
+        }
+        matrix.swap(column, pivot);
+        rhs.swap(column, pivot);
+
+        for row in column + 1..N {
+            let factor: f64 = matrix[row][column] / matrix[column][column];
+            let pivot_row: [f64; N] = matrix[column];
+            for (value, pivot_value) in matrix[row].iter_mut().zip(pivot_row).skip(column) {
+                *value -= factor * pivot_value;
+            }
+            rhs[row] -= factor * rhs[column];
+        }
+    }
+
+    let mut solution: [f64; N] = [0.0; N];
+    for row in (0..N).rev() {
+        let known: f64 = (row + 1..N).map(|k| matrix[row][k] * solution[k]).sum();
+        solution[row] = (rhs[row] - known) / matrix[row][row];
+    }
+    Some(solution)
+}
+
+// Eigenvalues and eigenvectors (the columns) of a symmetric matrix by cyclic Jacobi rotations: every rotation zeroes
+// one off-diagonal element, the product of the rotations collects the eigenvectors
+fn eigen(mut a: [[f64; 3]; 3]) -> ([f64; 3], [[f64; 3]; 3]) {
+    let mut v: [[f64; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    for _ in 0..JACOBI_SWEEPS {
+        let off_diagonal: f64 = a[0][1] * a[0][1] + a[0][2] * a[0][2] + a[1][2] * a[1][2];
+        let diagonal: f64 = a[0][0] * a[0][0] + a[1][1] * a[1][1] + a[2][2] * a[2][2];
+        if off_diagonal <= 1e-30 * diagonal {
+            break;
+        }
+
+        for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+            if a[p][q] == 0.0 {
+                continue;
+            }
+
+            let theta: f64 = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+            let t: f64 = if theta >= 0.0 { 1.0 } else { -1.0 } / (fabs(theta) + sqrt(theta * theta + 1.0));
+            let c: f64 = 1.0 / sqrt(t * t + 1.0);
+            let s: f64 = t * c;
+
+            // a = Pᵀ a P, v = v P
+            for row in a.iter_mut().chain(v.iter_mut()) {
+                let (x, y) = (row[p], row[q]);
+                row[p] = c * x - s * y;
+                row[q] = s * x + c * y;
+            }
+            let (row_p, row_q) = (a[p], a[q]);
+            for k in 0..3 {
+                a[p][k] = c * row_p[k] - s * row_q[k];
+                a[q][k] = s * row_p[k] + c * row_q[k];
+            }
+        }
+    }
+
+    ([a[0][0], a[1][1], a[2][2]], v)
+}
+
+// Run on the host by `cargo test` in the simulator, on an ellipsoid with a known distortion
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libm::{cbrt, cos, sin};
+
+    const OFFSET: [f64; 3] = [0.12, -0.05, 0.3];
+
+    // Soft iron distortion, symmetric like the one of the ellipsoid
+    const DISTORTION: [[f64; 3]; 3] = [[1.15, 0.06, -0.03], [0.06, 0.9, 0.04], [-0.03, 0.04, 1.05]];
+
+    const RADIUS: f64 = 0.5; // Ga
+
+    // Evenly spread directions on the unit sphere (Fibonacci lattice)
+    fn directions(count: usize) -> impl Iterator<Item = [f64; 3]> {
+        let golden_angle: f64 = core::f64::consts::PI * (3.0 - sqrt(5.0));
+        (0..count).map(move |i| {
+            let z: f64 = 1.0 - 2.0 * (i as f64 + 0.5) / count as f64;
+            let r: f64 = sqrt(1.0 - z * z);
+            let angle: f64 = golden_angle * i as f64;
+            [r * cos(angle), r * sin(angle), z]
+        })
+    }
+
+    // DISTORTION * RADIUS * direction + OFFSET
+    fn distorted(direction: [f64; 3]) -> [f32; 3] {
+        [0, 1, 2].map(|row| (OFFSET[row] + RADIUS * (0..3).map(|k| DISTORTION[row][k] * direction[k]).sum::<f64>()) as f32)
+    }
+
+    #[test]
+    fn fit_recovers_the_offset_and_the_matrix() {
+        let mut sweep: MagCalibrationSweep = MagCalibrationSweep::new();
+        directions(200).for_each(|direction| sweep.add(distorted(direction)));
+        assert!(sweep.is_complete());
+
+        let calibration: MagCalibration = sweep.fit().expect("points on an ellipsoid");
+        for (fitted, offset) in calibration.offset().into_iter().zip(OFFSET) {
+            assert!(fabs(fitted as f64 - offset) < 1e-4, "offset {:?}", calibration.offset());
+        }
+
+        // The correction undoes the distortion up to the scale to the mean radius: matrix * DISTORTION = ∛det · I
+        let d: [[f64; 3]; 3] = DISTORTION;
+        let determinant: f64 = d[0][0] * (d[1][1] * d[2][2] - d[1][2] * d[2][1])
+            - d[0][1] * (d[1][0] * d[2][2] - d[1][2] * d[2][0])
+            + d[0][2] * (d[1][0] * d[2][1] - d[1][1] * d[2][0]);
+        let matrix: [[f32; 3]; 3] = calibration.matrix();
+        let product: [[f64; 3]; 3] = matrix.map(|row| [0, 1, 2].map(|column| (0..3).map(|k| row[k] as f64 * d[k][column]).sum()));
+        for (row, values) in product.iter().enumerate() {
+            for (column, &value) in values.iter().enumerate() {
+                let expected: f64 = if row == column { cbrt(determinant) } else { 0.0 };
+                assert!(fabs(value - expected) < 1e-4, "matrix {:?}", matrix);
+            }
+        }
+    }
+
+    #[test]
+    fn fit_needs_every_axis() {
+        // Turned only around yaw: z barely changes
+        let mut sweep: MagCalibrationSweep = MagCalibrationSweep::new();
+        for i in 0..2 * MIN_SAMPLES {
+            let angle: f64 = 2.0 * core::f64::consts::PI * i as f64 / MIN_SAMPLES as f64;
+            let tilt: f64 = 0.02 * sin(3.0 * angle);
+            sweep.add(distorted([cos(angle), sin(angle), tilt]));
+        }
+
+        assert!(sweep.samples() >= MIN_SAMPLES);
+        assert!(sweep.coverage() < MIN_COVERAGE);
+        assert!(!sweep.is_complete());
+        assert_eq!(sweep.fit(), None);
+    }
+}
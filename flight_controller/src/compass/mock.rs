@@ -0,0 +1,196 @@
+// Register level models of the HMC5883L and the QMC5883L, and a bus on which one of them sits behind the auxiliary
+// bus of a `MockMPU6050`, like on a GY-87. The magnetometer only answers while the MPU-6050 bypasses its auxiliary
+// bus (I2C_BYPASS_EN set, I2C_MST_EN cleared), otherwise its address isn't acknowledged.
+//
+// Like the MPU-6050 mock the field is latched into the data registers at the start of every transaction, but only in
+// continuous mode, which the chips don't start in.
+use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation, SevenBitAddress};
+
+use super::CompassModel;
+use crate::gy521::mock::{MockError, MockMPU6050};
+
+const REGISTER_COUNT: usize = 16;
+
+// MPU-6050
+const INT_PIN_CFG_ADDR: u8 = 0x37;
+const USER_CTRL_ADDR: u8   = 0x6A;
+const I2C_BYPASS_EN: u8    = 1 << 1;
+const I2C_MST_EN: u8       = 1 << 5;
+
+// HMC5883L
+const HMC_MODE_ADDR: usize   = 0x02;
+const HMC_DATA_ADDR: usize   = 0x03;
+const HMC_STATUS_ADDR: usize = 0x09;
+const HMC_ID_ADDR: usize     = 0x0A;
+const HMC_MODE_MASK: u8      = 0b11;
+const HMC_ADC_LIMIT: i16     = 2047; // 12 bit ADC, beyond it the register reads -4096
+
+// QMC5883L
+const QMC_STATUS_ADDR: usize    = 0x06;
+const QMC_CONTROL_1_ADDR: usize = 0x09;
+const QMC_CONTROL_2_ADDR: usize = 0x0A;
+const QMC_CHIP_ID_ADDR: usize   = 0x0D;
+const QMC_MODE_MASK: u8         = 0b11;
+const QMC_SOFT_RST: u8          = 1 << 7;
+const QMC_OVERFLOW: u8          = 1 << 1;
+
+const DATA_READY: u8 = 1 << 0;
+
+pub struct MockMagnetometer {
+    model: CompassModel,
+    registers: [u8; REGISTER_COUNT],
+    pointer: usize,
+    field: [i16; 3],
+    overflow: bool
+}
+
+impl MockMagnetometer {
+    pub fn new(model: CompassModel) -> Self {
+        let mut magnetometer: Self = Self { model, registers: [0; REGISTER_COUNT], pointer: 0, field: [0; 3], overflow: false };
+        magnetometer.reset();
+        magnetometer
+    }
+
+    pub fn model(&self) -> CompassModel {
+        self.model
+    }
+
+    // Raw values (LSB) in the range the driver configures, see `CompassModel::sensitivity`
+    pub fn set_field(&mut self, field: [i16; 3]) {
+        self.field = field;
+    }
+
+    // The next measurements are out of range
+    pub fn set_overflow(&mut self, overflow: bool) {
+        self.overflow = overflow;
+    }
+
+    pub fn register(&self, register: u8) -> u8 {
+        self.registers[register as usize % REGISTER_COUNT]
+    }
+
+    // State after power on: single measurement mode (HMC5883L) or standby (QMC5883L)
+    fn reset(&mut self) {
+        self.registers = [0; REGISTER_COUNT];
+        match self.model {
+            CompassModel::Hmc5883l => {
+                self.registers[..3].copy_from_slice(&[0x10, 0x20, 0x01]);
+                self.registers[HMC_ID_ADDR..HMC_ID_ADDR + 3].copy_from_slice(b"H43");
+            },
+            CompassModel::Qmc5883l => self.registers[QMC_CHIP_ID_ADDR] = 0xFF
+        }
+    }
+
+    fn is_measuring(&self) -> bool {
+        match self.model {
+            CompassModel::Hmc5883l => self.registers[HMC_MODE_ADDR] & HMC_MODE_MASK == 0,
+            CompassModel::Qmc5883l => self.registers[QMC_CONTROL_1_ADDR] & QMC_MODE_MASK == 1
+        }
+    }
+
+    fn latch_field(&mut self) {
+        if !self.is_measuring() {
+            return;
+        }
+
+        match self.model {
+            CompassModel::Hmc5883l => {
+                let [x, y, z] = self.field.map(|value| {
+                    if self.overflow || !(-HMC_ADC_LIMIT - 1..=HMC_ADC_LIMIT).contains(&value) { -4096 } else { value }
+                });
+                for (i, value) in [x, z, y].into_iter().enumerate() {
+                    self.registers[HMC_DATA_ADDR + 2 * i..HMC_DATA_ADDR + 2 * i + 2].copy_from_slice(&value.to_be_bytes());
+                }
+                self.registers[HMC_STATUS_ADDR] |= DATA_READY;
+            },
+            CompassModel::Qmc5883l => {
+                for (i, value) in self.field.into_iter().enumerate() {
+                    self.registers[2 * i..2 * i + 2].copy_from_slice(&value.to_le_bytes());
+                }
+                self.registers[QMC_STATUS_ADDR] = DATA_READY | if self.overflow { QMC_OVERFLOW } else { 0 };
+            }
+        }
+    }
+
+    fn write_registers(&mut self, bytes: &[u8]) {
+        if let Some((&register, data)) = bytes.split_first() {
+            self.pointer = register as usize % REGISTER_COUNT;
+
+            for &byte in data {
+                match (self.model, self.pointer) {
+                    // Identification is read only
+                    (CompassModel::Hmc5883l, HMC_ID_ADDR..) | (CompassModel::Qmc5883l, QMC_CHIP_ID_ADDR) => {},
+                    (CompassModel::Qmc5883l, QMC_CONTROL_2_ADDR) if byte & QMC_SOFT_RST != 0 => self.reset(),
+                    (_, pointer) => self.registers[pointer] = byte
+                }
+                self.pointer = (self.pointer + 1) % REGISTER_COUNT;
+            }
+        }
+    }
+
+    fn read_registers(&mut self, buffer: &mut [u8]) {
+        for byte in buffer.iter_mut() {
+            *byte = self.registers[self.pointer];
+
+            // Reading the status (QMC5883L) or the last data register (HMC5883L) clears data ready
+            match (self.model, self.pointer) {
+                (CompassModel::Hmc5883l, pointer) if pointer == HMC_DATA_ADDR + 5 => self.registers[HMC_STATUS_ADDR] &= !DATA_READY,
+                (CompassModel::Qmc5883l, QMC_STATUS_ADDR) => self.registers[QMC_STATUS_ADDR] = 0,
+                _ => {}
+            }
+            self.pointer = (self.pointer + 1) % REGISTER_COUNT;
+        }
+    }
+}
+
+impl ErrorType for MockMagnetometer {
+    type Error = MockError;
+}
+
+impl I2c<SevenBitAddress> for MockMagnetometer {
+    fn transaction(&mut self, address: SevenBitAddress, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        if address != self.model.address() {
+            return Err(MockError(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)));
+        }
+
+        self.latch_field();
+
+        for operation in operations.iter_mut() {
+            match operation {
+                Operation::Write(bytes) => self.write_registers(bytes),
+                Operation::Read(buffer) => self.read_registers(buffer)
+            }
+        }
+        Ok(())
+    }
+}
+
+// An MPU-6050 with a magnetometer on its auxiliary bus
+pub struct MockBus {
+    pub mpu: MockMPU6050,
+    pub magnetometer: MockMagnetometer
+}
+
+impl MockBus {
+    pub fn new(mpu: MockMPU6050, magnetometer: MockMagnetometer) -> Self {
+        Self { mpu, magnetometer }
+    }
+
+    pub fn is_bypassed(&self) -> bool {
+        self.mpu.register(INT_PIN_CFG_ADDR) & I2C_BYPASS_EN != 0 && self.mpu.register(USER_CTRL_ADDR) & I2C_MST_EN == 0
+    }
+}
+
+impl ErrorType for MockBus {
+    type Error = MockError;
+}
+
+impl I2c<SevenBitAddress> for MockBus {
+    fn transaction(&mut self, address: SevenBitAddress, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        if address == self.magnetometer.model().address() && self.is_bypassed() {
+            self.magnetometer.transaction(address, operations)
+        } else {
+            self.mpu.transaction(address, operations)
+        }
+    }
+}
@@ -0,0 +1,395 @@
+// Driver for the magnetometer of a GY-271 (HMC5883L or its replacement QMC5883L). On boards like the GY-87 it sits on
+// the auxiliary bus of the MPU-6050 and is only reachable after `GY521::set_bypass(true)`, then it shares the bus of
+// the IMU like any other device.
+//
+// Readings are in gauss. `read` removes the hard and soft iron distortion with the `MagCalibration` and rotates the
+// field into the body axes, `math::compute_angle_magnetometer` turns it into a tilt compensated heading.
+use embedded_hal::i2c::I2c;
+
+use crate::gy521::BoardAlignment;
+use crate::math::MagnetometerData;
+
+// Re-export
+pub use calibration::{MagCalibration, MagCalibrationSweep, MIN_SAMPLES};
+pub use error_handling::CompassError;
+
+// HMC5883L, see its datasheet. The data registers are in the order X, Z, Y, big endian
+const HMC_CONFIG_A_ADDR: u8 = 0x00;
+const HMC_CONFIG_B_ADDR: u8 = 0x01;
+const HMC_MODE_ADDR: u8     = 0x02;
+const HMC_DATA_ADDR: u8     = 0x03;
+const HMC_STATUS_ADDR: u8   = 0x09;
+const HMC_ID_ADDR: u8       = 0x0A; // IDENTIFICATION_A, _B and _C
+
+const HMC_ID: [u8; 3]      = *b"H43";
+const HMC_CONFIG_A: u8     = 0b0111_1000; // 8 samples averaged, 75 Hz
+const HMC_CONFIG_B: u8     = 0b0010_0000; // ±1.3 Ga
+const HMC_CONTINUOUS: u8   = 0x00;
+const HMC_OVERFLOW: i16    = -4096;       // Value of an axis out of range
+const HMC_SENSITIVITY: f32 = 1090.0;      // LSB/Ga at ±1.3 Ga
+
+// QMC5883L, see its datasheet. The data registers are in the order X, Y, Z, little endian
+const QMC_DATA_ADDR: u8      = 0x00;
+const QMC_STATUS_ADDR: u8    = 0x06;
+const QMC_CONTROL_1_ADDR: u8 = 0x09;
+const QMC_SET_RESET_ADDR: u8 = 0x0B;
+const QMC_CHIP_ID_ADDR: u8   = 0x0D;
+
+const QMC_CHIP_ID: u8      = 0xFF;
+const QMC_CONTROL_1: u8    = 0b0001_1001; // Oversampling 512, ±8 G, 100 Hz, continuous
+const QMC_SET_RESET: u8    = 0x01;        // Recommended by the datasheet
+const QMC_OVERFLOW: u8     = 1 << 1;      // OVL of the status register
+const QMC_SENSITIVITY: f32 = 3000.0;      // LSB/G at ±8 G
+
+const DATA_READY: u8 = 1 << 0; // RDY and DRDY of the status registers
+
+mod error_handling {
+    use core::fmt::Debug;
+
+    // E is the error of the I2C bus
+    pub enum CompassError<E> {
+        I2C(E),
+        // Neither an HMC5883L nor a QMC5883L answered, or the identification doesn't match
+        UnknownDevice,
+        // An axis is out of the measurement range, e.g. a magnet close to the sensor
+        Overflow,
+        // The chip didn't keep the value written to the register
+        Verification { register: u8, written: u8, read: u8 }
+    }
+
+    impl<E> From<E> for CompassError<E> {
+        fn from(err: E) -> Self {
+            CompassError::I2C(err)
+        }
+    }
+
+    impl<E: Debug> Debug for CompassError<E> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match self {
+                CompassError::I2C(err) => write!(f, "I2C transaction with the magnetometer failed with: {err:?}")?,
+                CompassError::UnknownDevice => write!(f, "Found neither an HMC5883L nor a QMC5883L")?,
+                CompassError::Overflow => write!(f, "The magnetic field is out of the measurement range")?,
+                CompassError::Verification { register, written, read } => write!(f, "Register {register:#04x} reads {read:#04x} after writing {written:#04x}")?
+            }
+            Ok(())
+        }
+    }
+}
+
+// Hard and soft iron calibration by ellipsoid fitting
+mod calibration;
+
+// Register level magnetometers and the MPU-6050 bypass for running the driver without hardware
+pub mod mock;
+
+// The chip on the GY-271, both have a fixed address
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompassModel {
+    Hmc5883l,
+    Qmc5883l
+}
+
+impl CompassModel {
+    pub fn address(&self) -> u8 {
+        match self {
+            CompassModel::Hmc5883l => 0x1E,
+            CompassModel::Qmc5883l => 0x0D
+        }
+    }
+
+    // LSB per gauss in the range the driver configures
+    pub fn sensitivity(&self) -> f32 {
+        match self {
+            CompassModel::Hmc5883l => HMC_SENSITIVITY,
+            CompassModel::Qmc5883l => QMC_SENSITIVITY
+        }
+    }
+}
+
+pub struct Compass<I2C> {
+    master: I2C,
+    model: CompassModel,
+    calibration: MagCalibration,
+    alignment: BoardAlignment
+}
+
+impl<I2C: I2c> Compass<I2C> {
+    pub fn new(master: I2C, model: CompassModel) -> Self {
+        Self { master, model, calibration: MagCalibration::default(), alignment: BoardAlignment::default() }
+    }
+
+    // Looks for an HMC5883L first, then for a QMC5883L. Gives the bus back if there is neither
+    pub fn detect(master: I2C) -> Result<Self, I2C> {
+        let mut compass: Self = Self::new(master, CompassModel::Hmc5883l);
+
+        for model in [CompassModel::Hmc5883l, CompassModel::Qmc5883l] {
+            compass.model = model;
+            if compass.probe().is_ok() {
+                return Ok(compass);
+            }
+        }
+        Err(compass.master)
+    }
+
+    // Gives the bus back, e.g. to share it with another sensor
+    pub fn release(self) -> I2C {
+        self.master
+    }
+
+    pub fn model(&self) -> CompassModel {
+        self.model
+    }
+
+    // Checks the identification registers
+    pub fn probe(&mut self) -> Result<(), CompassError<I2C::Error>> {
+        let address: u8 = self.model.address();
+
+        let known: bool = match self.model {
+            CompassModel::Hmc5883l => {
+                let mut id: [u8; 3] = [0; 3];
+                self.master.write_read(address, &[HMC_ID_ADDR], &mut id)?;
+                id == HMC_ID
+            },
+            CompassModel::Qmc5883l => {
+                let mut id: [u8; 1] = [0];
+                self.master.write_read(address, &[QMC_CHIP_ID_ADDR], &mut id)?;
+                id[0] == QMC_CHIP_ID
+            }
+        };
+
+        if known { Ok(()) } else { Err(CompassError::UnknownDevice) }
+    }
+
+    // Probes the chip and starts continuous measurements, every register is read back
+    pub fn init(&mut self) -> Result<(), CompassError<I2C::Error>> {
+        self.probe()?;
+
+        let registers: &[(u8, u8)] = match self.model {
+            CompassModel::Hmc5883l => &[(HMC_CONFIG_A_ADDR, HMC_CONFIG_A), (HMC_CONFIG_B_ADDR, HMC_CONFIG_B), (HMC_MODE_ADDR, HMC_CONTINUOUS)],
+            CompassModel::Qmc5883l => &[(QMC_SET_RESET_ADDR, QMC_SET_RESET), (QMC_CONTROL_1_ADDR, QMC_CONTROL_1)]
+        };
+
+        for &(register, value) in registers {
+            self.master.write(self.model.address(), &[register, value])?;
+        }
+        for &(register, value) in registers {
+            self.verify_register(register, value)?;
+        }
+        Ok(())
+    }
+
+    pub fn calibration(&self) -> &MagCalibration {
+        &self.calibration
+    }
+
+    // e.g. the result of `MagCalibrationSweep::fit`
+    pub fn set_calibration(&mut self, calibration: MagCalibration) {
+        self.calibration = calibration;
+    }
+
+    pub fn alignment(&self) -> &BoardAlignment {
+        &self.alignment
+    }
+
+    // Mounting of the GY-271 on the frame, independent of the one of the IMU
+    pub fn set_alignment(&mut self, alignment: BoardAlignment) {
+        self.alignment = alignment;
+    }
+
+    // True if a new measurement arrived since the last read
+    pub fn data_ready(&mut self) -> Result<bool, CompassError<I2C::Error>> {
+        let register: u8 = match self.model {
+            CompassModel::Hmc5883l => HMC_STATUS_ADDR,
+            CompassModel::Qmc5883l => QMC_STATUS_ADDR
+        };
+
+        let mut status: [u8; 1] = [0];
+        self.master.write_read(self.model.address(), &[register], &mut status)?;
+        Ok(status[0] & DATA_READY != 0)
+    }
+
+    // Field in gauss in the axes of the chip, without calibration. This is what `MagCalibrationSweep` needs
+    pub fn read_raw(&mut self) -> Result<[f32; 3], CompassError<I2C::Error>> {
+        let address: u8 = self.model.address();
+
+        let raw: [i16; 3] = match self.model {
+            CompassModel::Hmc5883l => {
+                let mut data: [u8; 6] = [0; 6];
+                self.master.write_read(address, &[HMC_DATA_ADDR], &mut data)?;
+
+                let [x, z, y] = [0, 2, 4].map(|i| i16::from_be_bytes([data[i], data[i + 1]]));
+                if [x, y, z].contains(&HMC_OVERFLOW) {
+                    return Err(CompassError::Overflow);
+                }
+                [x, y, z]
+            },
+            CompassModel::Qmc5883l => {
+                // The status follows the data, reading it releases the data registers for the next measurement
+                let mut data: [u8; 7] = [0; 7];
+                self.master.write_read(address, &[QMC_DATA_ADDR], &mut data)?;
+
+                if data[6] & QMC_OVERFLOW != 0 {
+                    return Err(CompassError::Overflow);
+                }
+                [0, 2, 4].map(|i| i16::from_le_bytes([data[i], data[i + 1]]))
+            }
+        };
+
+        let sensitivity: f32 = self.model.sensitivity();
+        Ok(raw.map(|value| value as f32 / sensitivity))
+    }
+
+    // Calibrated field in gauss in body axes
+    pub fn read(&mut self) -> Result<MagnetometerData, CompassError<I2C::Error>> {
+        let raw: [f32; 3] = self.read_raw()?;
+        let [x, y, z] = self.alignment.rotate(self.calibration.apply(raw));
+        Ok(MagnetometerData { x, y, z })
+    }
+
+    fn verify_register(&mut self, register: u8, written: u8) -> Result<(), CompassError<I2C::Error>> {
+        let mut read: [u8; 1] = [0];
+        self.master.write_read(self.model.address(), &[register], &mut read)?;
+
+        if read[0] != written {
+            return Err(CompassError::Verification { register, written, read: read[0] });
+        }
+        Ok(())
+    }
+}
+
+// The driver against the magnetometers of `mock`, run on the host by `cargo test` in the simulator
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use embedded_hal::i2c::{ErrorType, Operation, SevenBitAddress};
+    use embedded_hal_bus::i2c::RefCellDevice;
+    use crate::gy521::mock::{mock_clock, MockDelay, MockError, MockMPU6050};
+    use crate::gy521::{Address, GY521};
+    use mock::{MockBus, MockMagnetometer};
+
+    const MODELS: [CompassModel; 2] = [CompassModel::Hmc5883l, CompassModel::Qmc5883l];
+
+    // Drops register writes but answers reads, like a chip whose configuration doesn't stick
+    struct IgnoringWrites<'a>(&'a mut MockMagnetometer);
+
+    impl ErrorType for IgnoringWrites<'_> {
+        type Error = MockError;
+    }
+
+    impl I2c<SevenBitAddress> for IgnoringWrites<'_> {
+        fn transaction(&mut self, address: SevenBitAddress, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+            for operation in operations.iter_mut() {
+                match operation {
+                    // Only the register pointer of a read gets through
+                    Operation::Write(bytes) if bytes.len() > 1 => self.0.transaction(address, &mut [Operation::Write(&bytes[..1])])?,
+                    _ => self.0.transaction(address, core::slice::from_mut(operation))?
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn initialized(magnetometer: &mut MockMagnetometer) -> Compass<&mut MockMagnetometer> {
+        let model: CompassModel = magnetometer.model();
+        let mut compass: Compass<&mut MockMagnetometer> = Compass::new(magnetometer, model);
+        compass.init().expect("mock magnetometer");
+        compass
+    }
+
+    #[test]
+    fn detection_needs_the_bypass() {
+        for model in MODELS {
+            let bus: RefCell<MockBus> = RefCell::new(MockBus::new(MockMPU6050::new(Address::AD0Low), MockMagnetometer::new(model)));
+            let mut imu: GY521<RefCellDevice<MockBus>, MockDelay> = GY521::new(RefCellDevice::new(&bus), Address::AD0Low, MockDelay, mock_clock);
+
+            // Behind the auxiliary bus of the MPU-6050 the address isn't acknowledged
+            assert!(Compass::detect(RefCellDevice::new(&bus)).is_err());
+
+            imu.set_bypass(true).expect("mock MPU-6050");
+            let Ok(compass) = Compass::detect(RefCellDevice::new(&bus)) else {
+                panic!("No magnetometer found behind the bypass");
+            };
+            assert_eq!(compass.model(), model);
+
+            imu.set_bypass(false).expect("mock MPU-6050");
+            assert!(Compass::detect(RefCellDevice::new(&bus)).is_err());
+        }
+    }
+
+    #[test]
+    fn init_starts_continuous_measurements() {
+        let mut hmc: MockMagnetometer = MockMagnetometer::new(CompassModel::Hmc5883l);
+        initialized(&mut hmc);
+        assert_eq!(hmc.register(HMC_CONFIG_A_ADDR), HMC_CONFIG_A);
+        assert_eq!(hmc.register(HMC_CONFIG_B_ADDR), HMC_CONFIG_B);
+        assert_eq!(hmc.register(HMC_MODE_ADDR), HMC_CONTINUOUS);
+
+        let mut qmc: MockMagnetometer = MockMagnetometer::new(CompassModel::Qmc5883l);
+        initialized(&mut qmc);
+        assert_eq!(qmc.register(QMC_SET_RESET_ADDR), QMC_SET_RESET);
+        assert_eq!(qmc.register(QMC_CONTROL_1_ADDR), QMC_CONTROL_1);
+    }
+
+    #[test]
+    fn init_reads_back_the_configuration() {
+        // The power on values stay, the first register written is the first to mismatch
+        let mut hmc: MockMagnetometer = MockMagnetometer::new(CompassModel::Hmc5883l);
+        let mut compass: Compass<IgnoringWrites> = Compass::new(IgnoringWrites(&mut hmc), CompassModel::Hmc5883l);
+        assert!(matches!(
+            compass.init(),
+            Err(CompassError::Verification { register: HMC_CONFIG_A_ADDR, written: HMC_CONFIG_A, read: 0x10 })
+        ));
+
+        let mut qmc: MockMagnetometer = MockMagnetometer::new(CompassModel::Qmc5883l);
+        let mut compass: Compass<IgnoringWrites> = Compass::new(IgnoringWrites(&mut qmc), CompassModel::Qmc5883l);
+        assert!(matches!(
+            compass.init(),
+            Err(CompassError::Verification { register: QMC_SET_RESET_ADDR, written: QMC_SET_RESET, read: 0 })
+        ));
+    }
+
+    #[test]
+    fn read_raw_decodes_the_byte_order() {
+        // x = 0x0102, y = 0x0304, z = 0xFFFE
+        let field: [i16; 3] = [258, 772, -2];
+
+        // X, Z, Y big endian
+        let mut hmc: MockMagnetometer = MockMagnetometer::new(CompassModel::Hmc5883l);
+        hmc.set_field(field);
+        let raw: [f32; 3] = initialized(&mut hmc).read_raw().expect("mock magnetometer");
+        assert_eq!(raw, field.map(|value| value as f32 / HMC_SENSITIVITY));
+        assert_eq!([0, 1, 2, 3, 4, 5].map(|i| hmc.register(HMC_DATA_ADDR + i)), [0x01, 0x02, 0xFF, 0xFE, 0x03, 0x04]);
+
+        // X, Y, Z little endian
+        let mut qmc: MockMagnetometer = MockMagnetometer::new(CompassModel::Qmc5883l);
+        qmc.set_field(field);
+        let raw: [f32; 3] = initialized(&mut qmc).read_raw().expect("mock magnetometer");
+        assert_eq!(raw, field.map(|value| value as f32 / QMC_SENSITIVITY));
+        assert_eq!([0, 1, 2, 3, 4, 5].map(|i| qmc.register(QMC_DATA_ADDR + i)), [0x02, 0x01, 0x04, 0x03, 0xFE, 0xFF]);
+    }
+
+    #[test]
+    fn read_raw_reports_an_overflow() {
+        for model in MODELS {
+            let bus: RefCell<MockMagnetometer> = RefCell::new(MockMagnetometer::new(model));
+            let mut compass: Compass<RefCellDevice<MockMagnetometer>> = Compass::new(RefCellDevice::new(&bus), model);
+            compass.init().expect("mock magnetometer");
+            bus.borrow_mut().set_field([100, -100, 400]);
+            assert!(compass.read_raw().is_ok());
+
+            bus.borrow_mut().set_overflow(true);
+            assert!(matches!(compass.read_raw(), Err(CompassError::Overflow)));
+            assert!(matches!(compass.read(), Err(CompassError::Overflow)));
+
+            // The next measurement in range reads again
+            bus.borrow_mut().set_overflow(false);
+            assert!(compass.read_raw().is_ok());
+        }
+
+        // The HMC5883L has no flag, a single axis beyond its 12 bit ADC reads -4096
+        let mut hmc: MockMagnetometer = MockMagnetometer::new(CompassModel::Hmc5883l);
+        hmc.set_field([0, 2048, 0]);
+        assert!(matches!(initialized(&mut hmc).read_raw(), Err(CompassError::Overflow)));
+    }
+}
@@ -0,0 +1,213 @@
+// Cascaded attitude controller: an outer angle loop feeds an inner rate loop, the inner loop produces the
+// roll/pitch/yaw demands for the mixer.
+//
+//   angle setpoint ──► [angle PID] ──► rate setpoint ──► [rate PID] ──► demand ──► Mixer
+//                          ▲                                 ▲
+//                   attitude estimate                  gyroscope rate
+//
+// Acro:    The sticks command rotation rates, the angle loop is not used. The drone holds whatever attitude it has.
+// Angle:   The sticks command roll and pitch angles, the drone levels itself when the sticks are centered.
+// Horizon: Angle mode around the center of the sticks, blending into Acro towards full deflection, which allows flips.
+//
+// Yaw is always rate controlled, there is no absolute heading reference.
+//
+// All angles are in degrees and all rates in °/s. The signs follow the gyroscope axes like in the mixer: positive roll
+// lowers the right side, positive pitch lowers the nose and positive yaw turns the nose to the left.
+use libm::fabsf;
+
+use crate::{
+    gy521::GyroscopeData,
+    math::{Angle, AntiWindup, PID},
+    mixer::MixerInput
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlightMode {
+    Acro,
+    Angle,
+    Horizon
+}
+
+// Pilot input. throttle is in the range of 0 - 1, roll, pitch and yaw in the range of -1 - 1 (full stick deflection)
+#[derive(Debug, Clone, Copy)]
+pub struct Setpoint {
+    pub throttle: f32,
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32
+}
+
+const ROLL: usize = 0;
+const PITCH: usize = 1;
+const YAW: usize = 2;
+
+pub struct AttitudeController {
+    mode: FlightMode,
+
+    rate_pids: [PID; 3],
+    angle_pids: [PID; 2],
+
+    max_rate: [f32; 3],
+    max_angle: f32,
+
+    // Bumpless mode switching: right after a switch the rate setpoint is faded from the last one of the old mode
+    // to the one of the new mode
+    transition_time: f32,
+    transition_left: f32,
+    transition_start: [f32; 3],
+    rate_setpoint: [f32; 3]
+}
+
+impl AttitudeController {
+    // rate_pids in the order roll, pitch, yaw with an output range of -1 - 1, angle_pids in the order roll, pitch
+    pub fn new(rate_pids: [PID; 3], angle_pids: [PID; 2]) -> Self {
+        Self {
+            mode: FlightMode::Angle,
+            rate_pids,
+            angle_pids,
+            max_rate: [360.0, 360.0, 180.0],
+            max_angle: 30.0,
+            transition_time: 0.3,
+            transition_left: 0.0,
+            transition_start: [0.0; 3],
+            rate_setpoint: [0.0; 3]
+        }
+    }
+
+    // Rate at full stick deflection in °/s, also the limit of the angle loop
+    pub fn set_max_rate(mut self, roll: f32, pitch: f32, yaw: f32) -> Self {
+        assert!(roll > 0.0 && pitch > 0.0 && yaw > 0.0, "The maximal rates must be positive");
+        self.max_rate = [roll, pitch, yaw];
+        self
+    }
+
+    // Angle at full stick deflection in Angle and Horizon mode in degrees
+    pub fn set_max_angle(mut self, max_angle: f32) -> Self {
+        assert!(max_angle > 0.0 && max_angle < 90.0, "The maximal angle must be between 0° and 90°");
+        self.max_angle = max_angle;
+        self
+    }
+
+    // Duration of the fade between two modes in seconds. 0 switches instantly.
+    pub fn set_transition_time(mut self, transition_time: f32) -> Self {
+        assert!(transition_time >= 0.0, "The transition time cannot be negative");
+        self.transition_time = transition_time;
+        self
+    }
+
+    pub fn mode(&self) -> FlightMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: FlightMode) {
+        if mode == self.mode {
+            return;
+        }
+
+        // The angle loop wasn't running in Acro, its old state would produce a kick
+        if self.mode == FlightMode::Acro {
+            self.angle_pids.iter_mut().for_each(PID::reset);
+        }
+
+        self.mode = mode;
+        self.transition_start = self.rate_setpoint;
+        self.transition_left = self.transition_time;
+    }
+
+    pub fn rate_pid_mut(&mut self) -> &mut [PID; 3] {
+        &mut self.rate_pids
+    }
+
+    pub fn angle_pid_mut(&mut self) -> &mut [PID; 2] {
+        &mut self.angle_pids
+    }
+
+    // The rate setpoint of the last update in the order roll, pitch, yaw
+    pub fn rate_setpoint(&self) -> [f32; 3] {
+        self.rate_setpoint
+    }
+
+    // Has to be called on arming and disarming
+    pub fn reset(&mut self) {
+        self.rate_pids.iter_mut().for_each(PID::reset);
+        self.angle_pids.iter_mut().for_each(PID::reset);
+        self.transition_left = 0.0;
+        self.rate_setpoint = [0.0; 3];
+    }
+
+    // dt is the time in seconds since the last update
+    pub fn update(&mut self, setpoint: &Setpoint, attitude: &Angle, gyro: &GyroscopeData, dt: f32) -> MixerInput {
+        let stick: [f32; 3] = [setpoint.roll, setpoint.pitch, setpoint.yaw].map(|value| value.clamp(-1.0, 1.0));
+        let stick_rate: [f32; 3] = [stick[ROLL] * self.max_rate[ROLL], stick[PITCH] * self.max_rate[PITCH], stick[YAW] * self.max_rate[YAW]];
+
+        let mut rate_setpoint: [f32; 3] = match self.mode {
+            FlightMode::Acro => stick_rate,
+            FlightMode::Angle => {
+                let [roll, pitch] = self.angle_loop(&stick, attitude, dt);
+                [roll, pitch, stick_rate[YAW]]
+            },
+            FlightMode::Horizon => {
+                // Full self leveling with centered sticks, none at full deflection
+                let leveling: f32 = 1.0 - fabsf(stick[ROLL]).max(fabsf(stick[PITCH]));
+                let [roll, pitch] = self.angle_loop(&stick, attitude, dt);
+
+                [
+                    leveling * roll + (1.0 - leveling) * stick_rate[ROLL],
+                    leveling * pitch + (1.0 - leveling) * stick_rate[PITCH],
+                    stick_rate[YAW]
+                ]
+            }
+        };
+
+        if self.transition_left > 0.0 && self.transition_time > 0.0 {
+            let weight: f32 = self.transition_left / self.transition_time;
+
+            for (rate, start) in rate_setpoint.iter_mut().zip(self.transition_start) {
+                *rate = weight * start + (1.0 - weight) * *rate;
+            }
+
+            self.transition_left = (self.transition_left - dt).max(0.0);
+        }
+
+        self.rate_setpoint = rate_setpoint;
+
+        let measured_rate: [f32; 3] = [gyro.x, gyro.y, gyro.z];
+        let mut demand: [f32; 3] = [0.0; 3];
+
+        for axis in [ROLL, PITCH, YAW] {
+            demand[axis] = self.rate_pids[axis].update(rate_setpoint[axis], measured_rate[axis], dt);
+        }
+
+        MixerInput { throttle: setpoint.throttle, roll: demand[ROLL], pitch: demand[PITCH], yaw: demand[YAW] }
+    }
+
+    // Returns the rate setpoints for roll and pitch, limited to the maximal rates
+    fn angle_loop(&mut self, stick: &[f32; 3], attitude: &Angle, dt: f32) -> [f32; 2] {
+        let measured: [f32; 2] = [attitude.roll(), attitude.pitch()];
+        let mut rate: [f32; 2] = [0.0; 2];
+
+        for axis in [ROLL, PITCH] {
+            let target: f32 = stick[axis] * self.max_angle;
+            let max_rate: f32 = self.max_rate[axis];
+
+            rate[axis] = self.angle_pids[axis].update(target, measured[axis], dt).clamp(-max_rate, max_rate);
+        }
+
+        rate
+    }
+}
+
+impl Default for AttitudeController {
+    fn default() -> Self {
+        let rate_pid = |kp: f32, ki: f32, kd: f32| PID::new(kp, ki, kd)
+            .set_output_limits(-1.0, 1.0)
+            .set_integral_limit(0.3)
+            .set_anti_windup(AntiWindup::Clamping)
+            .set_d_cutoff(50.0);
+
+        Self::new(
+            [rate_pid(0.004, 0.01, 0.0001), rate_pid(0.004, 0.01, 0.0001), rate_pid(0.006, 0.01, 0.0)],
+            [PID::new(4.0, 0.0, 0.0), PID::new(4.0, 0.0, 0.0)]
+        )
+    }
+}
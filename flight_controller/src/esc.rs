@@ -1,11 +1,11 @@
-use alloc::boxed::Box;
-use crate::{mem::{ALLOCATOR, BumpAllocator}, sync::Mutex};
+use alloc::vec::Vec;
+use crate::{mem::{ALLOCATOR, BumpAllocator}, mixer::{MotorOutputs, MAX_MOTORS}, sync::Mutex};
 
 static mut TIMER: Option<Timer<'static, LowSpeed>> = None;
 const ESC_HZ_FREQUENCY: u32 = 50;
 
 use esp_hal::{
-    gpio::GpioPin, 
+    gpio::{AnyPin, GpioPin, Pin}, 
     ledc::{
         channel::{
             config::{Config as ChannelConfig, PinConfig}, Channel, ChannelIFace, Number as ChannelNumber
@@ -32,7 +32,9 @@ mod error_handling {
     pub enum ESCError {
         TimerConfigError,
         ChannelConfigError(u8, Error),
-        DutyError(u8, Error)
+        DutyError(u8, Error),
+        ChannelCount(usize),
+        OutputCount { expected: usize, got: usize }
     }
 
     impl Debug for ESCError {
@@ -40,7 +42,9 @@ mod error_handling {
             match self {
                 ESCError::ChannelConfigError(channel, err) => write!(f, "Configuration of Channel {channel} failed with: {err:#?}")?,
                 ESCError::DutyError(channel, err) => write!(f, "Setting duty for Channel {channel} failed with: {err:#?}")?,
-                ESCError::TimerConfigError => write!(f, "Configuring Timer failed: Please refer to: https://docs.esp-rs.org/esp-hal/esp-hal/0.23.1/esp32/esp_hal/ledc/timer/enum.Error.html")?
+                ESCError::TimerConfigError => write!(f, "Configuring Timer failed: Please refer to: https://docs.esp-rs.org/esp-hal/esp-hal/0.23.1/esp32/esp_hal/ledc/timer/enum.Error.html")?,
+                ESCError::ChannelCount(count) => write!(f, "The LEDC peripheral supports 1 to {} channels, got {count}", super::MAX_MOTORS)?,
+                ESCError::OutputCount { expected, got } => write!(f, "Expected outputs for {expected} motors, got {got}")?
             }
             Ok(())
         }
@@ -62,19 +66,57 @@ impl RotorStrength {
     }
 }
 
+// Rounds a mixer output (0 - 1) to a whole percentage
+fn to_percentage(output: f32) -> u8 {
+    (output.clamp(0.0, 1.0) * 100.0 + 0.5) as u8
+}
+
+// Only possible for frames with four motors
+impl TryFrom<&MotorOutputs> for RotorStrength {
+    type Error = ESCError;
+
+    fn try_from(outputs: &MotorOutputs) -> Result<Self, Self::Error> {
+        match *outputs.as_slice() {
+            [m1, m2, m3, m4] => Ok(Self::new(to_percentage(m1), to_percentage(m2), to_percentage(m3), to_percentage(m4))),
+            _ => Err(ESCError::OutputCount { expected: 4, got: outputs.len() })
+        }
+    }
+}
+
+
+const CHANNEL_NUMBERS: [ChannelNumber; MAX_MOTORS] = [
+    ChannelNumber::Channel0,
+    ChannelNumber::Channel1,
+    ChannelNumber::Channel2,
+    ChannelNumber::Channel3,
+    ChannelNumber::Channel4,
+    ChannelNumber::Channel5,
+    ChannelNumber::Channel6,
+    ChannelNumber::Channel7
+];
 
 /// The ESC 30A operates at 50-60hz
 pub struct ESCControler<'controller> {
     ledc: Ledc<'controller>,
     #[cfg(feature = "wifi")]
-    channels: Box<[Channel<'controller, LowSpeed>; 4], &'controller Mutex<BumpAllocator>>,
+    channels: Vec<Channel<'controller, LowSpeed>, &'controller Mutex<BumpAllocator>>,
     #[cfg(not(feature = "wifi"))]
-    channels: Box<[Channel<'controller, LowSpeed>; 4]>,
+    channels: Vec<Channel<'controller, LowSpeed>>,
 }
 
 impl <'controller> ESCControler<'controller> {
-    /// Creates a new ESCController. After creation you must call `init`, otherwise all subsequent calls will fail
+    /// Creates a new ESCController for a Quad frame. After creation you must call `init`, otherwise all subsequent calls will fail
     pub fn new(ledc: LEDC, pin27: GpioPin<27>, pin26: GpioPin<26>, pin25: GpioPin<25>, pin23: GpioPin<23>) -> Result<Self, ESCError> {
+        Self::with_pins(ledc, [pin27.degrade(), pin26.degrade(), pin25.degrade(), pin23.degrade()])
+    }
+
+    /// Creates a new ESCController with one LEDC channel per pin (at most 8). The order of the pins has to match the
+    /// motor order of the mixer geometry. After creation you must call `init`, otherwise all subsequent calls will fail
+    pub fn with_pins<const N: usize>(ledc: LEDC, pins: [AnyPin; N]) -> Result<Self, ESCError> {
+        if N == 0 || N > MAX_MOTORS {
+            return Err(ESCError::ChannelCount(N));
+        }
+
         let mut ledc: Ledc = Ledc::new(ledc);
         ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
 
@@ -93,20 +135,14 @@ impl <'controller> ESCControler<'controller> {
         }
 
         #[cfg(feature = "wifi")]
-        let channels: Box<[Channel<'controller, LowSpeed>; 4], &Mutex<BumpAllocator>> = Box::new_in([
-            ledc.channel(ChannelNumber::Channel0, pin27),
-            ledc.channel(ChannelNumber::Channel1, pin26),
-            ledc.channel(ChannelNumber::Channel2, pin25),
-            ledc.channel(ChannelNumber::Channel3, pin23),
-        ], &ALLOCATOR);
+        let mut channels: Vec<Channel<'controller, LowSpeed>, &Mutex<BumpAllocator>> = Vec::with_capacity_in(N, &ALLOCATOR);
 
         #[cfg(not(feature = "wifi"))]
-        let channels: Box<[Channel<'controller, LowSpeed>; 4]> = Box::new([
-            ledc.channel(ChannelNumber::Channel0, pin27),
-            ledc.channel(ChannelNumber::Channel1, pin26),
-            ledc.channel(ChannelNumber::Channel2, pin25),
-            ledc.channel(ChannelNumber::Channel3, pin23),
-        ]);
+        let mut channels: Vec<Channel<'controller, LowSpeed>> = Vec::with_capacity(N);
+
+        for (number, pin) in CHANNEL_NUMBERS.into_iter().zip(pins) {
+            channels.push(ledc.channel(number, pin));
+        }
 
 
         Ok(Self { ledc, channels })
@@ -116,21 +152,42 @@ impl <'controller> ESCControler<'controller> {
     /// If you don't call this function before you use the motors, it will fail.
     #[allow(static_mut_refs)]
     pub fn init(&mut self) -> Result<(), ESCError> {
-        self.channels[0].configure(ChannelConfig { timer: unsafe { TIMER.as_ref().unwrap() }, duty_pct: 0, pin_config: PinConfig::PushPull }).map_err(|err| ESCError::ChannelConfigError(0, err))?;
-        self.channels[1].configure(ChannelConfig { timer: unsafe { TIMER.as_ref().unwrap() }, duty_pct: 0, pin_config: PinConfig::PushPull }).map_err(|err| ESCError::ChannelConfigError(1, err))?;
-        self.channels[2].configure(ChannelConfig { timer: unsafe { TIMER.as_ref().unwrap() }, duty_pct: 0, pin_config: PinConfig::PushPull }).map_err(|err| ESCError::ChannelConfigError(2, err))?;
-        self.channels[3].configure(ChannelConfig { timer: unsafe { TIMER.as_ref().unwrap() }, duty_pct: 0, pin_config: PinConfig::PushPull }).map_err(|err| ESCError::ChannelConfigError(3, err))?;
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            channel.configure(ChannelConfig { timer: unsafe { TIMER.as_ref().unwrap() }, duty_pct: 0, pin_config: PinConfig::PushPull }).map_err(|err| ESCError::ChannelConfigError(index as u8, err))?;
+        }
 
         Ok(())
     }
 
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Only works on a controller with exactly four channels
     pub fn update_rotor_frequency(&mut self, rotor_strength: RotorStrength) -> Result<(), ESCError> {
         let RotorStrength { m1, m2, m3, m4 } = rotor_strength;
+        self.set_duties(&[m1, m2, m3, m4])
+    }
+
+    /// Applies the mixer outputs, one per channel
+    pub fn update_motor_outputs(&mut self, outputs: &MotorOutputs) -> Result<(), ESCError> {
+        let mut duties: [u8; MAX_MOTORS] = [0; MAX_MOTORS];
 
-        self.channels[0].set_duty(m1).map_err(|err| ESCError::DutyError(0, err))?;
-        self.channels[1].set_duty(m2).map_err(|err| ESCError::DutyError(1, err))?;
-        self.channels[2].set_duty(m3).map_err(|err| ESCError::DutyError(2, err))?;
-        self.channels[3].set_duty(m4).map_err(|err| ESCError::DutyError(3, err))?;
+        for (duty, &output) in duties.iter_mut().zip(outputs.as_slice()) {
+            *duty = to_percentage(output);
+        }
+
+        self.set_duties(&duties[..outputs.len()])
+    }
+
+    fn set_duties(&mut self, duties: &[u8]) -> Result<(), ESCError> {
+        if duties.len() != self.channels.len() {
+            return Err(ESCError::OutputCount { expected: self.channels.len(), got: duties.len() });
+        }
+
+        for (index, (channel, &duty)) in self.channels.iter_mut().zip(duties).enumerate() {
+            channel.set_duty(duty).map_err(|err| ESCError::DutyError(index as u8, err))?;
+        }
 
         Ok(())
     }
@@ -0,0 +1,360 @@
+// Digital filters for the sensor data, in addition to the `Dlpf` of the MPU-6050. A high DLPF setting delays
+// every axis by up to 19 ms, while a software filter can be placed exactly where the noise is (e.g. a notch on the
+// motor frequency) and costs far less delay for the same attenuation.
+//
+// Every filter is a biquad in transposed direct form II:
+//
+// y = b0 * x + s1,   s1 = b1 * x - a1 * y + s2,   s2 = b2 * x - a2 * y
+//
+// PT1 and PT2 only use a part of the coefficients. The low-pass and the notch follow the "Audio EQ Cookbook"
+// (R. Bristow-Johnson), whose bilinear transform is prewarped, so the response at the cutoff is exact.
+use core::f32::consts::{FRAC_1_SQRT_2, PI};
+use heapless::Vec;
+use libm::{cosf, sinf, sqrtf};
+
+use crate::gy521::{AccelometerData, GyroscopeData};
+
+// Frequencies in Hz
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterType {
+    // First order low-pass, -20 dB/decade
+    Pt1 { cutoff: f32 },
+    // Second order low-pass without overshoot, -40 dB/decade
+    Pt2 { cutoff: f32 },
+    // Second order low-pass, q = 0.707 is a Butterworth, higher values peak at the cutoff
+    LowPass { cutoff: f32, q: f32 },
+    // Removes a narrow band around center, the higher q the narrower
+    Notch { center: f32, q: f32 }
+}
+
+impl FilterType {
+    fn frequency(&self) -> f32 {
+        match *self {
+            FilterType::Pt1 { cutoff } | FilterType::Pt2 { cutoff } | FilterType::LowPass { cutoff, .. } => cutoff,
+            FilterType::Notch { center, .. } => center
+        }
+    }
+
+    fn check(&self, sample_rate: f32) -> Result<(), FilterError> {
+        let frequency: f32 = self.frequency();
+        // Also false for NaN
+        if !(frequency > 0.0 && frequency < sample_rate / 2.0) {
+            return Err(FilterError::Frequency);
+        }
+
+        match *self {
+            FilterType::LowPass { q, .. } | FilterType::Notch { q, .. } if q.is_nan() || q <= 0.0 => Err(FilterError::Quality),
+            _ => Ok(())
+        }
+    }
+}
+
+// Why a filter couldn't be configured
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterError {
+    // The frequency isn't between 0 and half the sample rate, e.g. a notch following motors which stand still
+    Frequency,
+    // q isn't positive
+    Quality,
+    // There is no filter at the index of `FilterChain::set`
+    Index
+}
+
+// One axis
+#[derive(Debug, Clone, Copy)]
+pub struct Filter {
+    filter_type: FilterType,
+    sample_rate: f32,
+    b: [f32; 3],
+    a: [f32; 2],
+    state: [f32; 2]
+}
+
+impl Filter {
+    // sample_rate is the rate `apply` is called with, the frequency of the filter has to be below half of it
+    pub fn new(filter_type: FilterType, sample_rate: f32) -> Self {
+        let mut filter: Self = Self { filter_type, sample_rate, b: [1.0, 0.0, 0.0], a: [0.0; 2], state: [0.0; 2] };
+        let result: Result<(), FilterError> = filter.configure(filter_type, sample_rate);
+        assert!(result.is_ok(), "The frequency must be between 0 and half the sample rate and q positive");
+        filter
+    }
+
+    pub fn filter_type(&self) -> FilterType {
+        self.filter_type
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    // Changes the filter while running, the state is kept so the output doesn't jump. A filter type the sample rate
+    // can't represent doesn't panic in flight: the filter passes the signal unchanged until the next valid one
+    pub fn configure(&mut self, filter_type: FilterType, sample_rate: f32) -> Result<(), FilterError> {
+        self.filter_type = filter_type;
+        self.sample_rate = sample_rate;
+
+        if let Err(err) = filter_type.check(sample_rate) {
+            self.bypass();
+            return Err(err);
+        }
+
+        (self.b, self.a) = match filter_type {
+            FilterType::Pt1 { cutoff } => {
+                let k: f32 = pt1_gain(2.0 * PI * cutoff / sample_rate, 0.5);
+                ([k, 0.0, 0.0], [k - 1.0, 0.0])
+            },
+            FilterType::Pt2 { cutoff } => {
+                // Two PT1 in series, each one contributes half of the -3 dB
+                let k: f32 = pt1_gain(2.0 * PI * cutoff / sample_rate, FRAC_1_SQRT_2);
+                ([k * k, 0.0, 0.0], [2.0 * (k - 1.0), (1.0 - k) * (1.0 - k)])
+            },
+            FilterType::LowPass { cutoff, q } => {
+                let omega: f32 = 2.0 * PI * cutoff / sample_rate;
+                let (sin, cos) = (sinf(omega), cosf(omega));
+                let alpha: f32 = sin / (2.0 * q);
+                let a0: f32 = 1.0 + alpha;
+
+                let b1: f32 = (1.0 - cos) / a0;
+                ([b1 / 2.0, b1, b1 / 2.0], [-2.0 * cos / a0, (1.0 - alpha) / a0])
+            },
+            FilterType::Notch { center, q } => {
+                let omega: f32 = 2.0 * PI * center / sample_rate;
+                let (sin, cos) = (sinf(omega), cosf(omega));
+                let alpha: f32 = sin / (2.0 * q);
+                let a0: f32 = 1.0 + alpha;
+
+                ([1.0 / a0, -2.0 * cos / a0, 1.0 / a0], [-2.0 * cos / a0, (1.0 - alpha) / a0])
+            }
+        };
+        Ok(())
+    }
+
+    // False while `configure` bypasses the filter
+    pub fn is_active(&self) -> bool {
+        self.filter_type.check(self.sample_rate).is_ok()
+    }
+
+    pub fn reset(&mut self) {
+        self.state = [0.0; 2];
+    }
+
+    // Gain 1 at every frequency. The state is cleared, the next valid filter type starts like a new filter
+    fn bypass(&mut self) {
+        (self.b, self.a) = ([1.0, 0.0, 0.0], [0.0; 2]);
+        self.reset();
+    }
+
+    pub fn apply(&mut self, input: f32) -> f32 {
+        let output: f32 = self.b[0] * input + self.state[0];
+        self.state[0] = self.b[1] * input - self.a[0] * output + self.state[1];
+        self.state[1] = self.b[2] * input - self.a[1] * output;
+        output
+    }
+
+    // Magnitude of the transfer function at frequency (Hz), 1 passes the signal unchanged
+    pub fn gain(&self, frequency: f32) -> f32 {
+        let omega: f32 = 2.0 * PI * frequency / self.sample_rate;
+        let (cos, sin) = ([1.0, cosf(omega), cosf(2.0 * omega)], [0.0, sinf(omega), sinf(2.0 * omega)]);
+
+        // H(e^jω) = (b0 + b1 e^-jω + b2 e^-2jω) / (1 + a1 e^-jω + a2 e^-2jω)
+        let numerator: (f32, f32) = (
+            self.b[0] * cos[0] + self.b[1] * cos[1] + self.b[2] * cos[2],
+            -(self.b[1] * sin[1] + self.b[2] * sin[2])
+        );
+        let denominator: (f32, f32) = (
+            cos[0] + self.a[0] * cos[1] + self.a[1] * cos[2],
+            -(self.a[0] * sin[1] + self.a[1] * sin[2])
+        );
+
+        sqrtf(numerator.0 * numerator.0 + numerator.1 * numerator.1) / sqrtf(denominator.0 * denominator.0 + denominator.1 * denominator.1)
+    }
+}
+
+// k of the PT1 y += k * (x - y) whose squared gain at omega (rad per sample) is power. The usual k = dt / (RC + dt)
+// is only close to that far below the sample rate. With u = 1 - k and c = cos(omega) the condition
+// k² = power * |1 - u * e^-jω|² is a quadratic in u:
+//
+// (1 - power) * u² - 2 * (1 - power * c) * u + (1 - power) = 0
+fn pt1_gain(omega: f32, power: f32) -> f32 {
+    let c: f32 = cosf(omega);
+    let p: f32 = 1.0 - power * c;
+    let u: f32 = (p - sqrtf(p * p - (1.0 - power) * (1.0 - power))) / (1.0 - power);
+    1.0 - u
+}
+
+// Up to N filters in series, each on all three axes. Filters are applied in the order they were added
+#[derive(Debug, Clone)]
+pub struct FilterChain<const N: usize> {
+    sample_rate: f32,
+    filters: Vec<[Filter; 3], N>
+}
+
+impl<const N: usize> FilterChain<N> {
+    // sample_rate of the data, e.g. `MPUConfig::get_sample_rate`
+    pub fn new(sample_rate: f32) -> Self {
+        assert!(sample_rate > 0.0, "The sample rate must be positive");
+        Self { sample_rate, filters: Vec::new() }
+    }
+
+    // Gives the filter type back if the chain is full
+    pub fn push(&mut self, filter_type: FilterType) -> Result<(), FilterType> {
+        let filter: Filter = Filter::new(filter_type, self.sample_rate);
+        self.filters.push([filter; 3]).map_err(|_| filter_type)
+    }
+
+    // Reconfigures the filter at index, e.g. to follow the motor frequency with a notch. If the sample rate can't
+    // represent the filter type, the filter is bypassed until it is set to a valid one, see `Filter::configure`
+    pub fn set(&mut self, index: usize, filter_type: FilterType) -> Result<(), FilterError> {
+        let filters: &mut [Filter; 3] = self.filters.get_mut(index).ok_or(FilterError::Index)?;
+
+        let mut result: Result<(), FilterError> = Ok(());
+        for filter in filters.iter_mut() {
+            result = filter.configure(filter_type, self.sample_rate);
+        }
+        result
+    }
+
+    pub fn clear(&mut self) {
+        self.filters.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn reset(&mut self) {
+        self.filters.iter_mut().flatten().for_each(Filter::reset);
+    }
+
+    // Product of the gains of all filters, see `Filter::gain`
+    pub fn gain(&self, frequency: f32) -> f32 {
+        self.filters.iter().map(|filters| filters[0].gain(frequency)).product()
+    }
+
+    pub fn apply(&mut self, values: [f32; 3]) -> [f32; 3] {
+        let mut values: [f32; 3] = values;
+
+        for filters in self.filters.iter_mut() {
+            for (value, filter) in values.iter_mut().zip(filters.iter_mut()) {
+                *value = filter.apply(*value);
+            }
+        }
+        values
+    }
+
+    pub fn apply_gyro(&mut self, gyro: &GyroscopeData) -> GyroscopeData {
+        let [x, y, z] = self.apply([gyro.x, gyro.y, gyro.z]);
+        GyroscopeData { x, y, z }
+    }
+
+    pub fn apply_accel(&mut self, accel: &AccelometerData) -> AccelometerData {
+        let [x, y, z] = self.apply([accel.x, accel.y, accel.z]);
+        AccelometerData { x, y, z }
+    }
+}
+
+// Checked on the host by `cargo test` in the simulator
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUTTERWORTH: FilterType = FilterType::LowPass { cutoff: 100.0, q: FRAC_1_SQRT_2 };
+    const NOTCH: FilterType = FilterType::Notch { center: 200.0, q: 5.0 };
+
+    fn chain(filter_types: &[FilterType]) -> FilterChain<4> {
+        let mut chain: FilterChain<4> = FilterChain::new(1000.0);
+        for &filter_type in filter_types {
+            chain.push(filter_type).expect("room for the filter");
+        }
+        chain
+    }
+
+    // Steady state amplitude of a unit sine at frequency (Hz) after the chain, sampled at 1 kHz
+    fn measure_gain(filter_types: &[FilterType], frequency: f64) -> f64 {
+        let mut chain: FilterChain<4> = chain(filter_types);
+
+        // Correlation with sine and cosine over the last second (whole periods), after the filters settled
+        let (mut in_phase, mut quadrature): (f64, f64) = (0.0, 0.0);
+        for step in 0..4000 {
+            let phase: f64 = 2.0 * core::f64::consts::PI * frequency * step as f64 / 1000.0;
+            let output: [f32; 3] = chain.apply([libm::sin(phase) as f32, -libm::sin(phase) as f32, 0.0]);
+            assert_eq!(output[0], -output[1]);
+
+            if step >= 3000 {
+                in_phase += output[0] as f64 * libm::sin(phase) / 500.0;
+                quadrature += output[0] as f64 * libm::cos(phase) / 500.0;
+            }
+        }
+        libm::hypot(in_phase, quadrature)
+    }
+
+    #[test]
+    fn sines_follow_the_transfer_function() {
+        let chains: [&[FilterType]; 5] = [
+            &[FilterType::Pt1 { cutoff: 100.0 }],
+            &[FilterType::Pt2 { cutoff: 100.0 }],
+            &[BUTTERWORTH],
+            &[NOTCH],
+            &[NOTCH, BUTTERWORTH]
+        ];
+
+        for filter_types in chains {
+            for frequency in [5.0, 50.0, 100.0, 150.0, 200.0, 300.0, 450.0] {
+                let (measured, expected) = (measure_gain(filter_types, frequency), chain(filter_types).gain(frequency as f32) as f64);
+                assert!((measured - expected).abs() < 0.01, "{filter_types:?} at {frequency} Hz: {measured} instead of {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn gains_at_the_known_points() {
+        for filter_type in [FilterType::Pt1 { cutoff: 100.0 }, FilterType::Pt2 { cutoff: 100.0 }] {
+            let gain: f32 = Filter::new(filter_type, 1000.0).gain(100.0);
+            assert!((gain - FRAC_1_SQRT_2).abs() < 0.05, "{filter_type:?}: {gain}");
+        }
+
+        // The prewarped low-pass is exact at the cutoff
+        assert!((Filter::new(BUTTERWORTH, 1000.0).gain(100.0) - FRAC_1_SQRT_2).abs() < 0.001);
+
+        let notch: Filter = Filter::new(NOTCH, 1000.0);
+        assert!(notch.gain(200.0) < 1e-3 && measure_gain(&[NOTCH], 200.0) < 0.01);
+        assert!((notch.gain(5.0) - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn invalid_types_bypass_instead_of_panicking() {
+        let mut chain: FilterChain<4> = chain(&[NOTCH, BUTTERWORTH]);
+
+        // Motors standing still, above Nyquist and a broken q
+        for (notch, expected) in [
+            (FilterType::Notch { center: 0.0, q: 5.0 }, FilterError::Frequency),
+            (FilterType::Notch { center: 600.0, q: 5.0 }, FilterError::Frequency),
+            (FilterType::Notch { center: f32::NAN, q: 5.0 }, FilterError::Frequency),
+            (FilterType::Notch { center: 200.0, q: 0.0 }, FilterError::Quality)
+        ] {
+            assert_eq!(chain.set(0, notch), Err(expected));
+            assert!(!chain.filters[0][0].is_active());
+
+            // Only the low-pass is left
+            let gain: f32 = chain.gain(200.0);
+            assert!((gain - Filter::new(BUTTERWORTH, 1000.0).gain(200.0)).abs() < 1e-6, "{notch:?}: {gain}");
+            assert!(chain.apply([1.0, -1.0, 0.5]).iter().all(|value| value.is_finite()));
+        }
+
+        // Back in range the notch works again
+        assert_eq!(chain.set(0, NOTCH), Ok(()));
+        assert!(chain.filters[0][0].is_active() && chain.gain(200.0) < 1e-3);
+
+        assert_eq!(chain.set(2, NOTCH), Err(FilterError::Index));
+    }
+
+    #[test]
+    #[should_panic]
+    fn new_rejects_invalid_types() {
+        Filter::new(FilterType::Pt1 { cutoff: 500.0 }, 1000.0);
+    }
+}
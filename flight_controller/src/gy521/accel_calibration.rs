@@ -0,0 +1,209 @@
+// Six position calibration of the accelerometer. The board rests once with each axis pointing up and once pointing
+// down, so every axis measures +1g and -1g. Half the sum of both readings is the bias, half the difference the
+// response of the sensor to 1g along that axis. The responses of all three axes form a matrix, whose inverse corrects
+// scale and, if wanted, the misalignment between the axes.
+use libm::fabsf;
+
+use super::CalibrationOffsets;
+
+// The sensor axis which points up, against gravity. ZUp is the breakout lying level, independent of `BoardAlignment`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    ZUp,
+    ZDown,
+    YUp,
+    YDown,
+    XUp,
+    XDown
+}
+
+impl Orientation {
+    pub const ALL: [Orientation; 6] = [
+        Orientation::ZUp, Orientation::ZDown, Orientation::YUp, Orientation::YDown, Orientation::XUp, Orientation::XDown
+    ];
+
+    pub fn axis(&self) -> usize {
+        match self {
+            Orientation::XUp | Orientation::XDown => 0,
+            Orientation::YUp | Orientation::YDown => 1,
+            Orientation::ZUp | Orientation::ZDown => 2
+        }
+    }
+
+    pub fn is_up(&self) -> bool {
+        matches!(self, Orientation::XUp | Orientation::YUp | Orientation::ZUp)
+    }
+
+    fn index(&self) -> usize {
+        2 * (2 - self.axis()) + if self.is_up() { 0 } else { 1 }
+    }
+
+    // The axis of the orientation has to dominate the measurement (raw, LSB) with the right sign
+    pub(crate) fn matches(&self, accel: [f32; 3]) -> bool {
+        let axis: usize = self.axis();
+        let value: f32 = if self.is_up() { accel[axis] } else { -accel[axis] };
+
+        value > 0.0 && (0..3).filter(|&other| other != axis).all(|other| fabsf(accel[other]) < value)
+    }
+}
+
+// Collects the mean accelerometer reading (raw, LSB) of each orientation, see `GY521::measure_orientation`
+#[derive(Debug, Clone, Default)]
+pub struct AccelCalibration {
+    measurements: [Option<[f32; 3]>; 6]
+}
+
+impl AccelCalibration {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Guides through the calibration: the first orientation which isn't measured yet
+    pub fn next_orientation(&self) -> Option<Orientation> {
+        Orientation::ALL.into_iter().find(|orientation| self.measurements[orientation.index()].is_none())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.next_orientation().is_none()
+    }
+
+    pub(crate) fn add(&mut self, orientation: Orientation, accel: [f32; 3]) {
+        self.measurements[orientation.index()] = Some(accel);
+    }
+
+    // one_g is the scaling factor of the accelerometer range (LSB/g). Without misalignment only the scale of each axis
+    // is corrected. None if an orientation is missing or the measurements are degenerated
+    pub(crate) fn solve(&self, one_g: f32, misalignment: bool, calibration: &CalibrationOffsets) -> Option<CalibrationOffsets> {
+        let mut bias: [f32; 3] = [0.0; 3];
+        let mut response: [[f32; 3]; 3] = [[0.0; 3]; 3]; // response[row][column]: reading of row for 1g along column
+
+        let pairs: [(Orientation, Orientation); 3] = [
+            (Orientation::XUp, Orientation::XDown), (Orientation::YUp, Orientation::YDown), (Orientation::ZUp, Orientation::ZDown)
+        ];
+
+        for (axis, (up, down)) in pairs.iter().enumerate() {
+            let up: [f32; 3] = self.measurements[up.index()]?;
+            let down: [f32; 3] = self.measurements[down.index()]?;
+
+            for row in 0..3 {
+                bias[row] += (up[row] + down[row]) / 6.0; // Mean of all six
+                response[row][axis] = (up[row] - down[row]) / 2.0;
+            }
+        }
+
+        if !misalignment {
+            for (row, values) in response.iter_mut().enumerate() {
+                for (column, value) in values.iter_mut().enumerate() {
+                    if row != column {
+                        *value = 0.0;
+                    }
+                }
+            }
+        }
+
+        let inverse: [[f32; 3]; 3] = invert(response)?;
+
+        Some(CalibrationOffsets {
+            accel_bias: bias,
+            accel_matrix: inverse.map(|row| row.map(|value| value * one_g)),
+            ..*calibration
+        })
+    }
+}
+
+// Cramer's rule, None for a singular matrix
+pub(crate) fn invert(m: [[f32; 3]; 3]) -> Option<[[f32; 3]; 3]> {
+    let cofactor = |row: usize, column: usize| -> f32 {
+        let (r0, r1) = ((row + 1) % 3, (row + 2) % 3);
+        let (c0, c1) = ((column + 1) % 3, (column + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+
+    let determinant: f32 = m[0][0] * cofactor(0, 0) + m[0][1] * cofactor(0, 1) + m[0][2] * cofactor(0, 2);
+    if fabsf(determinant) < f32::EPSILON {
+        return None;
+    }
+
+    let mut inverse: [[f32; 3]; 3] = [[0.0; 3]; 3];
+    for (row, values) in inverse.iter_mut().enumerate() {
+        for (column, value) in values.iter_mut().enumerate() {
+            *value = cofactor(column, row) / determinant; // Adjugate is the transposed cofactor matrix
+        }
+    }
+    Some(inverse)
+}
+
+// Run on the host by `cargo test` in the simulator, on readings of a sensor with a known error
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE_G: f32 = 4096.0;
+    const BIAS: [f32; 3] = [120.0, -80.0, 200.0];
+    // LSB of row for 1g along column: scale on the diagonal, misalignment off it
+    const RESPONSE: [[f32; 3]; 3] = [[4000.0, 30.0, -20.0], [25.0, 4150.0, 40.0], [-15.0, 35.0, 4050.0]];
+
+    fn reading(g: [f32; 3]) -> [f32; 3] {
+        [0, 1, 2].map(|row| BIAS[row] + (0..3).map(|column| RESPONSE[row][column] * g[column]).sum::<f32>())
+    }
+
+    fn correct(calibration: &CalibrationOffsets, raw: [f32; 3]) -> [f32; 3] {
+        let m: [[f32; 3]; 3] = calibration.accel_matrix;
+        [0, 1, 2].map(|row| (0..3).map(|column| m[row][column] * (raw[column] - calibration.accel_bias[column])).sum::<f32>() / ONE_G)
+    }
+
+    fn measure_all() -> AccelCalibration {
+        let mut calibration: AccelCalibration = AccelCalibration::new();
+        while let Some(orientation) = calibration.next_orientation() {
+            let mut g: [f32; 3] = [0.0; 3];
+            g[orientation.axis()] = if orientation.is_up() { 1.0 } else { -1.0 };
+
+            assert!(orientation.matches(reading(g)), "{orientation:?}");
+            calibration.add(orientation, reading(g));
+        }
+        calibration
+    }
+
+    #[test]
+    fn six_positions_solve_bias_scale_and_misalignment() {
+        let solved: CalibrationOffsets = measure_all().solve(ONE_G, true, &CalibrationOffsets::default()).expect("complete");
+
+        assert!(solved.accel_bias.iter().zip(BIAS).all(|(solved, bias)| (solved - bias).abs() < 1e-2), "{:?}", solved.accel_bias);
+
+        // Any direction of gravity is measured with 1g, also between the six positions
+        for g in [[0.0, 0.0, 1.0], [0.6, 0.0, -0.8], [0.48, -0.6, 0.64], [-0.36, 0.48, 0.8]] {
+            let corrected: [f32; 3] = correct(&solved, reading(g));
+            assert!((0..3).all(|axis| (corrected[axis] - g[axis]).abs() < 1e-4), "{g:?}: {corrected:?}");
+        }
+    }
+
+    #[test]
+    fn without_misalignment_only_the_scale_is_corrected() {
+        let solved: CalibrationOffsets = measure_all().solve(ONE_G, false, &CalibrationOffsets::default()).expect("complete");
+
+        for (row, values) in solved.accel_matrix.iter().enumerate() {
+            for (column, &value) in values.iter().enumerate() {
+                let expected: f32 = if row == column { ONE_G / RESPONSE[row][row] } else { 0.0 };
+                assert!((value - expected).abs() < 1e-5, "{:?}", solved.accel_matrix);
+            }
+        }
+
+        // The cross axis response remains, about 1% of the other axes
+        let corrected: [f32; 3] = correct(&solved, reading([0.0, 0.0, 1.0]));
+        assert!((corrected[2] - 1.0).abs() < 1e-4 && (corrected[0] + 0.005).abs() < 1e-4, "{corrected:?}");
+    }
+
+    #[test]
+    fn incomplete_or_degenerated_measurements_fail() {
+        let mut calibration: AccelCalibration = AccelCalibration::new();
+        calibration.add(Orientation::ZUp, reading([0.0, 0.0, 1.0]));
+        calibration.add(Orientation::ZDown, reading([0.0, 0.0, -1.0]));
+        assert!(!calibration.is_complete() && calibration.solve(ONE_G, true, &CalibrationOffsets::default()).is_none());
+
+        // A dead axis reads the same up and down
+        let mut calibration: AccelCalibration = measure_all();
+        calibration.add(Orientation::XUp, BIAS);
+        calibration.add(Orientation::XDown, BIAS);
+        assert!(calibration.solve(ONE_G, true, &CalibrationOffsets::default()).is_none());
+    }
+}
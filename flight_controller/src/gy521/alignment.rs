@@ -0,0 +1,123 @@
+// Rotation from the axes of the sensor to the axes of the drone (x forward, z up), for a breakout which isn't mounted
+// with its axes along the frame. The coarse part is one of the 24 right-angle orientations, given by the sensor axes
+// which point forward and up. The fine trim corrects the small tilt of the mounting on top of it.
+use core::f32::consts::PI;
+use libm::{cosf, sinf};
+
+// An axis of the sensor as printed on the breakout, with its direction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SensorAxis {
+    PosX,
+    NegX,
+    PosY,
+    NegY,
+    PosZ,
+    NegZ
+}
+
+impl SensorAxis {
+    // Unit vector in sensor coordinates
+    fn vector(&self) -> [f32; 3] {
+        match self {
+            SensorAxis::PosX => [1.0, 0.0, 0.0],
+            SensorAxis::NegX => [-1.0, 0.0, 0.0],
+            SensorAxis::PosY => [0.0, 1.0, 0.0],
+            SensorAxis::NegY => [0.0, -1.0, 0.0],
+            SensorAxis::PosZ => [0.0, 0.0, 1.0],
+            SensorAxis::NegZ => [0.0, 0.0, -1.0]
+        }
+    }
+}
+
+// body = matrix * sensor, applied to accel and gyro in `DataFrame::new` after the calibration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoardAlignment {
+    forward: SensorAxis,
+    up: SensorAxis,
+    trim: [f32; 3],
+    matrix: [[f32; 3]; 3]
+}
+
+impl BoardAlignment {
+    // forward is the sensor axis along the nose (body x), up the one pointing away from the ground (body z) while the
+    // drone is level. None if both lie on the same sensor axis.
+    pub fn new(forward: SensorAxis, up: SensorAxis) -> Option<Self> {
+        let (f, u) = (forward.vector(), up.vector());
+        if f[0] * u[0] + f[1] * u[1] + f[2] * u[2] != 0.0 {
+            return None;
+        }
+
+        let mut alignment: Self = Self { forward, up, trim: [0.0; 3], matrix: [[0.0; 3]; 3] };
+        alignment.update_matrix();
+        Some(alignment)
+    }
+
+    // Rotation of the sensor relative to the coarse orientation in degrees (roll around x, pitch around y, yaw around z
+    // of the drone), e.g. measured with the drone standing level
+    pub fn set_trim(mut self, roll: f32, pitch: f32, yaw: f32) -> Self {
+        self.trim = [roll, pitch, yaw];
+        self.update_matrix();
+        self
+    }
+
+    pub fn forward(&self) -> SensorAxis {
+        self.forward
+    }
+
+    pub fn up(&self) -> SensorAxis {
+        self.up
+    }
+
+    pub fn trim(&self) -> [f32; 3] {
+        self.trim
+    }
+
+    pub fn matrix(&self) -> [[f32; 3]; 3] {
+        self.matrix
+    }
+
+    // Sensor vector in body axes
+    pub fn rotate(&self, vector: [f32; 3]) -> [f32; 3] {
+        let m: &[[f32; 3]; 3] = &self.matrix;
+        [0, 1, 2].map(|row| m[row][0] * vector[0] + m[row][1] * vector[1] + m[row][2] * vector[2])
+    }
+
+    // Flags per sensor axis in body axes: a body axis is set if any sensor axis it is made of is. With a trim every
+    // sensor axis contributes a little to every body axis
+    pub fn rotate_flags(&self, flags: [bool; 3]) -> [bool; 3] {
+        let m: &[[f32; 3]; 3] = &self.matrix;
+        [0, 1, 2].map(|row| (0..3).any(|column| flags[column] && m[row][column] != 0.0))
+    }
+
+    fn update_matrix(&mut self) {
+        // Rows of the coarse rotation are the body axes in sensor coordinates, y completes the right handed system
+        let x: [f32; 3] = self.forward.vector();
+        let z: [f32; 3] = self.up.vector();
+        let y: [f32; 3] = [z[1] * x[2] - z[2] * x[1], z[2] * x[0] - z[0] * x[2], z[0] * x[1] - z[1] * x[0]];
+        let coarse: [[f32; 3]; 3] = [x, y, z];
+
+        // Trim = Rz(yaw) * Ry(pitch) * Rx(roll)
+        let [roll, pitch, yaw] = self.trim.map(|angle| angle * PI / 180.0);
+        let (sr, cr) = (sinf(roll), cosf(roll));
+        let (sp, cp) = (sinf(pitch), cosf(pitch));
+        let (sy, cy) = (sinf(yaw), cosf(yaw));
+        let trim: [[f32; 3]; 3] = [
+            [cy * cp, cy * sp * sr - sy * cr, cy * sp * cr + sy * sr],
+            [sy * cp, sy * sp * sr + cy * cr, sy * sp * cr - cy * sr],
+            [-sp, cp * sr, cp * cr]
+        ];
+
+        for (row, values) in self.matrix.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = (0..3).map(|k| trim[row][k] * coarse[k][column]).sum();
+            }
+        }
+    }
+}
+
+impl Default for BoardAlignment {
+    // Sensor axes equal the body axes
+    fn default() -> Self {
+        Self { forward: SensorAxis::PosX, up: SensorAxis::PosZ, trim: [0.0; 3], matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]] }
+    }
+}
@@ -0,0 +1,104 @@
+use heapless::Vec;
+
+use super::DataFrame;
+
+// Size of the FIFO buffer of the MPU-6050 in bytes
+pub(crate) const FIFO_SIZE: usize = 1024;
+
+// Bits of the FIFO_EN register (0x23)
+const TEMP_FIFO_EN: u8  = 1 << 7;
+const GYRO_FIFO_EN: u8  = 0b111 << 4; // XG, YG and ZG
+const ACCEL_FIFO_EN: u8 = 1 << 3;
+
+// Selects which sensors write into the FIFO. The chip always writes them in register order (accel, temperature, gyro),
+// so the less sensors are selected, the more samples fit into the FIFO. Without the temperature the gyro bias isn't
+// compensated for temperature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FifoSources {
+    pub(crate) accel: bool,
+    pub(crate) temperature: bool,
+    pub(crate) gyro: bool
+}
+
+impl FifoSources {
+    pub fn set_accel(mut self, enabled: bool) -> Self {
+        self.accel = enabled;
+        self
+    }
+
+    pub fn set_temperature(mut self, enabled: bool) -> Self {
+        self.temperature = enabled;
+        self
+    }
+
+    pub fn set_gyro(mut self, enabled: bool) -> Self {
+        self.gyro = enabled;
+        self
+    }
+
+    // Bytes one sample takes in the FIFO
+    pub fn frame_size(&self) -> usize {
+        (self.accel as usize) * 6 + (self.temperature as usize) * 2 + (self.gyro as usize) * 6
+    }
+
+    // Value of the FIFO_EN register
+    pub(crate) fn register(&self) -> u8 {
+        let mut register: u8 = 0;
+        if self.accel { register |= ACCEL_FIFO_EN; }
+        if self.temperature { register |= TEMP_FIFO_EN; }
+        if self.gyro { register |= GYRO_FIFO_EN; }
+        register
+    }
+}
+
+impl Default for FifoSources {
+    fn default() -> Self {
+        Self { accel: true, temperature: true, gyro: true }
+    }
+}
+
+// The frames of one `GY521::read_fifo`, oldest first. N limits how many samples are taken out of the FIFO at once,
+// the rest stays in the FIFO for the next call.
+pub struct FifoBatch<const N: usize> {
+    pub(crate) frames: Vec<DataFrame, N>,
+    pub(crate) overflow: bool
+}
+
+impl<const N: usize> FifoBatch<N> {
+    pub fn new() -> Self {
+        Self { frames: Vec::new(), overflow: false }
+    }
+
+    pub fn frames(&self) -> &[DataFrame] {
+        &self.frames
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, DataFrame> {
+        self.frames.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // True if the FIFO overflowed before it was read. Its content was discarded, so samples were lost
+    // and the time step to the next frame is larger than the sample period.
+    pub fn overflowed(&self) -> bool {
+        self.overflow
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.frames.clear();
+        self.overflow = false;
+    }
+}
+
+impl<const N: usize> Default for FifoBatch<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,89 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+// Bits of INT_PIN_CFG (0x37)
+const INT_LEVEL: u8 = 1 << 7;
+const INT_OPEN: u8  = 1 << 6;
+
+// Electrical behaviour of the INT pin. The chip pulses it for 50 µs on every new sample, so every sample is an edge
+// even if the previous one wasn't read yet. The default is active high push-pull, so the GPIO listens for rising edges.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct InterruptConfig {
+    pub(crate) active_low: bool,
+    pub(crate) open_drain: bool
+}
+
+impl InterruptConfig {
+    pub fn set_active_low(mut self, active_low: bool) -> Self {
+        self.active_low = active_low;
+        self
+    }
+
+    pub fn set_open_drain(mut self, open_drain: bool) -> Self {
+        self.open_drain = open_drain;
+        self
+    }
+
+    // Value of the upper four bits of INT_PIN_CFG
+    pub(crate) fn register(&self) -> u8 {
+        let mut register: u8 = 0;
+        if self.active_low { register |= INT_LEVEL; }
+        if self.open_drain { register |= INT_OPEN; }
+        register
+    }
+}
+
+// Connects the GPIO interrupt with the control loop. The interrupt handler calls `signal`, the loop `wait` or `take`.
+// Lives in a static, so it only uses atomics.
+pub struct DataReady {
+    pending: AtomicBool,
+    missed: AtomicU32
+}
+
+impl DataReady {
+    pub const fn new() -> Self {
+        Self { pending: AtomicBool::new(false), missed: AtomicU32::new(0) }
+    }
+
+    // Called from the interrupt handler
+    pub fn signal(&self) {
+        if self.pending.swap(true, Ordering::AcqRel) {
+            self.missed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // True if a sample arrived since the last call, doesn't block
+    pub fn take(&self) -> bool {
+        self.pending.swap(false, Ordering::AcqRel)
+    }
+
+    // Blocks until a new sample is ready
+    pub fn wait(&self) {
+        while !self.take() {
+            core::hint::spin_loop();
+        }
+    }
+
+    // Like `wait`, but gives up after timeout µs of clock (µs since boot) and returns false, e.g. when the INT pin
+    // is no longer connected
+    pub fn wait_timeout(&self, clock: fn() -> u64, timeout: u64) -> bool {
+        let start: u64 = clock();
+        while !self.take() {
+            if clock().wrapping_sub(start) >= timeout {
+                return false;
+            }
+            core::hint::spin_loop();
+        }
+        true
+    }
+
+    // Samples which arrived before the previous one was taken. Without the FIFO these are lost
+    pub fn missed(&self) -> u32 {
+        self.missed.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for DataReady {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,485 @@
+// A register level model of the MPU-6050 behind the embedded-hal `I2c` trait.
+//
+// Writes set the register pointer with the first byte and store the following bytes with auto increment, reads
+// continue at the register pointer, exactly like the chip does it. The sensor registers (0x3B - 0x48) are only
+// updated while the chip is awake (SLEEP bit of PWR_MGMT_1 cleared), after a reset it is sleeping like the real one.
+// The FIFO and the data ready interrupt are driven by `sample`, which stands for the end of one sample period.
+use core::{cell::{Ref, RefCell, RefMut}, convert::Infallible, sync::atomic::{AtomicU32, Ordering}};
+use super::FIFO_SIZE;
+use embedded_storage::{ReadStorage, Storage};
+use embedded_hal::{
+    delay::DelayNs,
+    digital::{self, InputPin, OutputPin},
+    i2c::{Error, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation, SevenBitAddress}
+};
+
+const REGISTER_COUNT: usize = 128;
+
+const SELF_TEST_ADDR: usize    = 0x0D;
+const GYRO_CONFIG_ADDR: usize  = 0x1B;
+const ACCEL_CONFIG_ADDR: usize = 0x1C;
+const FIFO_EN_ADDR: usize     = 0x23;
+const INT_ENABLE_ADDR: usize  = 0x38;
+const INT_STATUS_ADDR: usize  = 0x3A;
+const SENSOR_DATA_ADDR: usize = 0x3B;
+const USER_CTRL_ADDR: usize   = 0x6A;
+const PWR_MGMT_1_ADDR: usize  = 0x6B;
+const FIFO_COUNT_ADDR: usize  = 0x72;
+const FIFO_R_W_ADDR: usize    = 0x74;
+const WHO_AM_I_ADDR: usize    = 0x75;
+
+const SLEEP_BIT: u8      = 1 << 6;
+const X_ST: u8           = 1 << 7; // ACCEL_CONFIG and GYRO_CONFIG, followed by Y_ST and Z_ST
+const FIFO_OFLOW_INT: u8 = 1 << 4;
+const DATA_RDY_INT: u8   = 1 << 0;
+const USER_FIFO_EN: u8   = 1 << 6;
+const FIFO_RESET: u8     = 1 << 2;
+
+// FIFO_EN bits in the order the chip writes the registers into the FIFO
+const TEMP_FIFO_EN: u8  = 1 << 7;
+const XG_FIFO_EN: u8    = 1 << 6;
+const YG_FIFO_EN: u8    = 1 << 5;
+const ZG_FIFO_EN: u8    = 1 << 4;
+const ACCEL_FIFO_EN: u8 = 1 << 3;
+
+// SELF_TEST_X, _Y, _Z and _A of a healthy chip: all test values are 1, which is a factory trim of 1392.64 LSB for
+// the accelerometer and ±3275 LSB for the gyroscope. The self-test responses match them.
+const SELF_TEST_REGISTERS: [u8; 4] = [0x01, 0x01, 0x01, 0b0001_0101];
+const ACCEL_SELF_TEST_RESPONSE: [i16; 3] = [1393, 1393, 1393];
+const GYRO_SELF_TEST_RESPONSE: [i16; 3]  = [3275, -3275, 3275];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MockError(pub ErrorKind);
+
+impl Error for MockError {
+    fn kind(&self) -> ErrorKind {
+        self.0
+    }
+}
+
+pub struct MockMPU6050 {
+    address: u8,
+    registers: [u8; REGISTER_COUNT],
+    pointer: usize,
+
+    accel: [i16; 3],
+    temperature: i16,
+    gyro: [i16; 3],
+
+    accel_self_test: [i16; 3],
+    gyro_self_test: [i16; 3],
+
+    fifo: [u8; FIFO_SIZE],
+    fifo_start: usize,
+    fifo_len: usize,
+
+    connected: bool,
+    sda_stuck: u32,
+    transactions: u32,
+
+    // Bus lines driven by `MockScl` and `MockSda`
+    scl_high: bool,
+    sda_low: bool,
+    scl_clocks: u32,
+    stops: u32
+}
+
+impl MockMPU6050 {
+    pub fn new(address: super::Address) -> Self {
+        Self {
+            address: address as u8,
+            registers: Self::power_on_registers(),
+            pointer: 0,
+            accel: [0; 3],
+            temperature: 0,
+            gyro: [0; 3],
+            accel_self_test: ACCEL_SELF_TEST_RESPONSE,
+            gyro_self_test: GYRO_SELF_TEST_RESPONSE,
+            fifo: [0; FIFO_SIZE],
+            fifo_start: 0,
+            fifo_len: 0,
+            connected: true,
+            sda_stuck: 0,
+            transactions: 0,
+            scl_high: true,
+            sda_low: false,
+            scl_clocks: 0,
+            stops: 0
+        }
+    }
+
+    // A brown-out: every register and the FIFO are back in the state after power on, the chip sleeps
+    pub fn power_cycle(&mut self) {
+        self.registers = Self::power_on_registers();
+        self.pointer = 0;
+        self.fifo_start = 0;
+        self.fifo_len = 0;
+    }
+
+    // Raw register values (LSB), as they would be produced by the ADCs
+    pub fn set_accel(&mut self, accel: [i16; 3]) {
+        self.accel = accel;
+    }
+
+    pub fn set_temperature(&mut self, temperature: i16) {
+        self.temperature = temperature;
+    }
+
+    pub fn set_gyro(&mut self, gyro: [i16; 3]) {
+        self.gyro = gyro;
+    }
+
+    // Added to the outputs while the self-test of the axis is enabled, a broken axis responds too little
+    pub fn set_self_test_response(&mut self, accel: [i16; 3], gyro: [i16; 3]) {
+        self.accel_self_test = accel;
+        self.gyro_self_test = gyro;
+    }
+
+    pub fn register(&self, register: u8) -> u8 {
+        self.registers[register as usize % REGISTER_COUNT]
+    }
+
+    pub fn set_register(&mut self, register: u8, value: u8) {
+        self.registers[register as usize % REGISTER_COUNT] = value;
+    }
+
+    pub fn is_sleeping(&self) -> bool {
+        self.registers[PWR_MGMT_1_ADDR] & SLEEP_BIT != 0
+    }
+
+    // A disconnected chip doesn't acknowledge its address, like a loose wire
+    pub fn set_connected(&mut self, connected: bool) {
+        self.connected = connected;
+    }
+
+    // The chip holds SDA low as if the master stopped in the middle of a byte, every transaction fails until SCL
+    // was clocked the given number of times through `MockScl`
+    pub fn set_sda_stuck(&mut self, clocks: u32) {
+        self.sda_stuck = clocks;
+    }
+
+    // Number of transactions the chip acknowledged
+    pub fn transactions(&self) -> u32 {
+        self.transactions
+    }
+
+    // Rising edges of SCL driven through `MockScl`
+    pub fn scl_clocks(&self) -> u32 {
+        self.scl_clocks
+    }
+
+    // STOP conditions driven through `MockScl` and `MockSda`: SDA released while SCL is high
+    pub fn stops(&self) -> u32 {
+        self.stops
+    }
+
+    fn power_on_registers() -> [u8; REGISTER_COUNT] {
+        let mut registers: [u8; REGISTER_COUNT] = [0; REGISTER_COUNT];
+        registers[PWR_MGMT_1_ADDR] = SLEEP_BIT;
+        registers[WHO_AM_I_ADDR] = 0x68;
+        registers[SELF_TEST_ADDR..SELF_TEST_ADDR + 4].copy_from_slice(&SELF_TEST_REGISTERS);
+        registers
+    }
+
+    // End of a sample period: sets DATA_RDY_INT and writes the sensors selected in FIFO_EN into the FIFO, if it is
+    // enabled in USER_CTRL. A full FIFO overwrites its oldest bytes and sets FIFO_OFLOW_INT.
+    // Returns true if the INT pin pulsed, i.e. an enabled interrupt occurred.
+    pub fn sample(&mut self) -> bool {
+        if self.is_sleeping() {
+            return false;
+        }
+
+        let mut events: u8 = DATA_RDY_INT;
+        if self.registers[USER_CTRL_ADDR] & USER_FIFO_EN != 0 && self.fill_fifo() {
+            events |= FIFO_OFLOW_INT;
+        }
+
+        self.registers[INT_STATUS_ADDR] |= events;
+        events & self.registers[INT_ENABLE_ADDR] != 0
+    }
+
+    // Number of bytes in the FIFO
+    pub fn fifo_len(&self) -> usize {
+        self.fifo_len
+    }
+
+    // Returns true if the FIFO overflowed
+    fn fill_fifo(&mut self) -> bool {
+        let mut overflow: bool = false;
+        let enabled: u8 = self.registers[FIFO_EN_ADDR];
+        let sources: [(u8, i16); 7] = [
+            (ACCEL_FIFO_EN, self.accel[0]), (ACCEL_FIFO_EN, self.accel[1]), (ACCEL_FIFO_EN, self.accel[2]),
+            (TEMP_FIFO_EN, self.temperature),
+            (XG_FIFO_EN, self.gyro[0]), (YG_FIFO_EN, self.gyro[1]), (ZG_FIFO_EN, self.gyro[2])
+        ];
+
+        for (bit, value) in sources {
+            if enabled & bit != 0 {
+                for byte in value.to_be_bytes() {
+                    overflow |= self.push_fifo(byte);
+                }
+            }
+        }
+        self.update_fifo_count();
+        overflow
+    }
+
+    // Returns true if the oldest byte was overwritten
+    fn push_fifo(&mut self, byte: u8) -> bool {
+        let overflow: bool = self.fifo_len == FIFO_SIZE;
+        if overflow {
+            self.fifo_start = (self.fifo_start + 1) % FIFO_SIZE;
+            self.fifo_len -= 1;
+        }
+
+        self.fifo[(self.fifo_start + self.fifo_len) % FIFO_SIZE] = byte;
+        self.fifo_len += 1;
+        overflow
+    }
+
+    // An empty FIFO reads the last byte again, like the chip
+    fn pop_fifo(&mut self) -> u8 {
+        if self.fifo_len == 0 {
+            return self.fifo[(self.fifo_start + FIFO_SIZE - 1) % FIFO_SIZE];
+        }
+
+        let byte: u8 = self.fifo[self.fifo_start];
+        self.fifo_start = (self.fifo_start + 1) % FIFO_SIZE;
+        self.fifo_len -= 1;
+        byte
+    }
+
+    fn update_fifo_count(&mut self) {
+        self.registers[FIFO_COUNT_ADDR..FIFO_COUNT_ADDR + 2].copy_from_slice(&(self.fifo_len as u16).to_be_bytes());
+    }
+
+    // ACCEL_XOUT_H to GYRO_ZOUT_L, big endian
+    fn latch_sensor_data(&mut self) {
+        if self.is_sleeping() {
+            return;
+        }
+
+        let accel: [i16; 3] = Self::with_self_test(self.accel, self.accel_self_test, self.registers[ACCEL_CONFIG_ADDR]);
+        let gyro: [i16; 3] = Self::with_self_test(self.gyro, self.gyro_self_test, self.registers[GYRO_CONFIG_ADDR]);
+        let values: [i16; 7] = [accel[0], accel[1], accel[2], self.temperature, gyro[0], gyro[1], gyro[2]];
+
+        for (index, value) in values.iter().enumerate() {
+            let register: usize = SENSOR_DATA_ADDR + 2 * index;
+            self.registers[register..register + 2].copy_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    fn with_self_test(values: [i16; 3], response: [i16; 3], config: u8) -> [i16; 3] {
+        let mut values: [i16; 3] = values;
+        for axis in 0..3 {
+            if config & (X_ST >> axis) != 0 {
+                values[axis] = values[axis].saturating_add(response[axis]);
+            }
+        }
+        values
+    }
+
+    fn write_registers(&mut self, bytes: &[u8]) {
+        if let Some((&register, data)) = bytes.split_first() {
+            self.pointer = register as usize % REGISTER_COUNT;
+
+            for &byte in data {
+                match self.pointer {
+                    // WHO_AM_I is read only
+                    WHO_AM_I_ADDR => {},
+                    // FIFO_RESET only works while the FIFO is disabled and clears itself
+                    USER_CTRL_ADDR => {
+                        if byte & FIFO_RESET != 0 && byte & USER_FIFO_EN == 0 {
+                            self.fifo_start = 0;
+                            self.fifo_len = 0;
+                            self.update_fifo_count();
+                        }
+                        self.registers[USER_CTRL_ADDR] = byte & !FIFO_RESET;
+                    },
+                    FIFO_R_W_ADDR => {
+                        if self.push_fifo(byte) {
+                            self.registers[INT_STATUS_ADDR] |= FIFO_OFLOW_INT;
+                        }
+                    },
+                    _ => self.registers[self.pointer] = byte
+                }
+
+                // Burst accesses to FIFO_R_W stay at the FIFO
+                if self.pointer != FIFO_R_W_ADDR {
+                    self.pointer = (self.pointer + 1) % REGISTER_COUNT;
+                }
+            }
+        }
+    }
+
+    fn read_registers(&mut self, buffer: &mut [u8]) {
+        for byte in buffer.iter_mut() {
+            match self.pointer {
+                FIFO_R_W_ADDR => {
+                    *byte = self.pop_fifo();
+                    continue;
+                },
+                // INT_STATUS is cleared by reading it
+                INT_STATUS_ADDR => {
+                    *byte = self.registers[INT_STATUS_ADDR];
+                    self.registers[INT_STATUS_ADDR] = 0;
+                },
+                _ => *byte = self.registers[self.pointer]
+            }
+            self.pointer = (self.pointer + 1) % REGISTER_COUNT;
+        }
+        self.update_fifo_count();
+    }
+}
+
+impl ErrorType for MockMPU6050 {
+    type Error = MockError;
+}
+
+impl I2c<SevenBitAddress> for MockMPU6050 {
+    fn transaction(&mut self, address: SevenBitAddress, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        if self.sda_stuck > 0 {
+            return Err(MockError(ErrorKind::Bus));
+        }
+        if !self.connected || address != self.address {
+            return Err(MockError(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)));
+        }
+
+        // The chip copies the ADC values into the output registers at the start of a transaction,
+        // so a burst read always returns values of the same sample
+        self.latch_sensor_data();
+        self.transactions += 1;
+
+        for operation in operations.iter_mut() {
+            match operation {
+                Operation::Write(bytes) => self.write_registers(bytes),
+                Operation::Read(buffer) => self.read_registers(buffer)
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// SCL and SDA of the chip as GPIOs, for `clear_bus`. Every rising edge of SCL clocks out one bit of a stuck SDA,
+// also the one of a STOP
+pub struct MockScl<'a>(pub &'a RefCell<MockMPU6050>);
+
+pub struct MockSda<'a>(pub &'a RefCell<MockMPU6050>);
+
+impl digital::ErrorType for MockScl<'_> {
+    type Error = Infallible;
+}
+
+impl OutputPin for MockScl<'_> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.borrow_mut().scl_high = false;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        let mut chip: RefMut<MockMPU6050> = self.0.borrow_mut();
+        if !chip.scl_high {
+            chip.scl_clocks += 1;
+            chip.sda_stuck = chip.sda_stuck.saturating_sub(1);
+        }
+        chip.scl_high = true;
+        Ok(())
+    }
+}
+
+impl digital::ErrorType for MockSda<'_> {
+    type Error = Infallible;
+}
+
+impl OutputPin for MockSda<'_> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.borrow_mut().sda_low = true;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        let mut chip: RefMut<MockMPU6050> = self.0.borrow_mut();
+        if chip.sda_low && chip.scl_high && chip.sda_stuck == 0 {
+            chip.stops += 1;
+        }
+        chip.sda_low = false;
+        Ok(())
+    }
+}
+
+// Open drain: the line is low while the master or the chip holds it
+impl InputPin for MockSda<'_> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        let chip: Ref<MockMPU6050> = self.0.borrow();
+        Ok(!chip.sda_low && chip.sda_stuck == 0)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+static MOCK_TIME: AtomicU32 = AtomicU32::new(0);
+
+// Time in µs since the start of the program, only advanced by `MockDelay`. Can be passed as clock to `GY521::new`
+pub fn mock_clock() -> u64 {
+    MOCK_TIME.load(Ordering::Relaxed) as u64
+}
+
+// Doesn't wait, it only advances the time of `mock_clock`
+pub struct MockDelay;
+
+impl DelayNs for MockDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        MOCK_TIME.fetch_add(ns.div_ceil(1000), Ordering::Relaxed);
+    }
+}
+
+// Flash in RAM for the calibration storage, erased like a new chip (0xFF)
+pub struct MockFlash<const N: usize> {
+    bytes: [u8; N]
+}
+
+impl<const N: usize> MockFlash<N> {
+    pub fn new() -> Self {
+        Self { bytes: [0xFF; N] }
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8; N] {
+        &mut self.bytes
+    }
+}
+
+impl<const N: usize> Default for MockFlash<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Reading or writing beyond the end
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutOfBounds;
+
+impl<const N: usize> ReadStorage for MockFlash<N> {
+    type Error = OutOfBounds;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let start: usize = offset as usize;
+        let source: &[u8] = self.bytes.get(start..start + bytes.len()).ok_or(OutOfBounds)?;
+        bytes.copy_from_slice(source);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<const N: usize> Storage for MockFlash<N> {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let start: usize = offset as usize;
+        self.bytes.get_mut(start..start + bytes.len()).ok_or(OutOfBounds)?.copy_from_slice(bytes);
+        Ok(())
+    }
+}
@@ -0,0 +1,1129 @@
+// The driver only depends on the embedded-hal traits. On the ESP32 it runs on `esp_hal::i2c::master::I2c`,
+// on the host on the register level mock in `mock`.
+use embedded_hal::{delay::DelayNs, i2c::I2c};
+
+pub(crate) use sensor_data::{ScalingFactor, FRAME_SIZE};
+pub(crate) use fifo::FIFO_SIZE;
+
+// Re-export
+pub use sensor_data::{AccelometerData, GyroscopeData, DataFrame, CalibrationOffsets};
+pub use mpu_configuration::{Config as MPUConfig, Dlpf, AFullRangeScale, GFullRangeScale, ClockSource, PowerMode, WakeFrequency};
+pub use error_handling::GY521Error;
+pub use fifo::{FifoBatch, FifoSources};
+pub use interrupt::{DataReady, InterruptConfig};
+pub use self_test::{SelfTestResult, SELF_TEST_TOLERANCE};
+pub use accel_calibration::{AccelCalibration, Orientation};
+pub use alignment::{BoardAlignment, SensorAxis};
+pub use temperature::{GyroTemperatureModel, TemperatureSweep, MIN_TEMPERATURE_SPAN};
+pub use recovery::{clear_bus, LinkMonitor};
+pub use storage::{load_calibration, store_calibration, StorageError, CALIBRATION_SIZE};
+
+
+// Follow chip specification: MPU-6050-Register-Mapping.pdf
+const PWR_MGMT_1_ADDR: u8 = 0x6B;
+const PWR_MGMT_2_ADDR: u8 = 0x6C;
+const WHO_AM_I_ADDR: u8   = 0x75;
+const SELF_TEST_ADDR: u8  = 0x0D; // SELF_TEST_X, _Y, _Z and _A
+
+// Content of WHO_AM_I, independent of AD0
+const WHO_AM_I: u8 = 0x68;
+
+// Start of the sensor data, accel, temperature and gyro follow each other
+const SENSOR_READ_ADDR: u8 = 0x3B;
+
+// Configuration Register Adress
+const SMPLRT_DIV_ADDR: u8    = 0x19;
+const DLPF_CONFIG_ADDR: u8   = 0x1A;
+const GYRO_CONFIG_ADDR: u8   = 0x1B;
+const ACCELO_CONFIG_ADDR: u8 = 0x1C;
+
+// FIFO and interrupt registers
+const FIFO_EN_ADDR: u8       = 0x23;
+const INT_PIN_CFG_ADDR: u8   = 0x37;
+const INT_ENABLE_ADDR: u8    = 0x38;
+const INT_STATUS_ADDR: u8    = 0x3A;
+const USER_CTRL_ADDR: u8     = 0x6A;
+const FIFO_COUNT_ADDR: u8    = 0x72; // FIFO_COUNT_H, followed by FIFO_COUNT_L
+const FIFO_R_W_ADDR: u8      = 0x74;
+
+const FIFO_OFLOW_INT: u8 = 1 << 4; // INT_ENABLE and INT_STATUS
+const DATA_RDY_INT: u8   = 1 << 0; // INT_ENABLE and INT_STATUS
+const INT_PIN_MASK: u8   = 0xF0;   // INT_PIN_CFG bits of the INT pin, the lower ones belong to the auxiliary bus
+const USER_FIFO_EN: u8   = 1 << 6; // USER_CTRL
+const I2C_MST_EN: u8     = 1 << 5; // USER_CTRL
+const I2C_BYPASS_EN: u8  = 1 << 1; // INT_PIN_CFG
+const FIFO_RESET: u8     = 1 << 2; // USER_CTRL
+const POWER_MODE_MASK: u8 = 0b0110_0000; // SLEEP and CYCLE of PWR_MGMT_1
+const LP_WAKE_MASK: u8    = 0b1100_0000; // LP_WAKE_CTRL of PWR_MGMT_2
+const SELF_TEST_EN: u8    = 0b1110_0000; // XA_ST, YA_ST, ZA_ST of ACCEL_CONFIG and XG_ST, YG_ST, ZG_ST of GYRO_CONFIG
+
+// Samples which are averaged for each half of the self-test and the time the outputs get to settle before
+const SELF_TEST_SAMPLES: u16 = 50;
+const SELF_TEST_SETTLE_MS: u32 = 100;
+
+mod error_handling {
+    use core::fmt::Debug;
+
+    // E is the error of the I2C bus
+    use super::Orientation;
+
+    pub enum GY521Error<E> {
+        I2C(E),
+        // WHO_AM_I didn't read 0x68, there is no MPU-6050 at the address
+        UnknownDevice(u8),
+        // `init` has to be called first
+        NotInitialized,
+        // `enable_fifo` has to be called first
+        FifoDisabled,
+        // The register holds a reserved value
+        InvalidRegister { register: u8, value: u8 },
+        // The board doesn't rest in the orientation that is measured
+        WrongOrientation(Orientation),
+        // An orientation of the six position calibration is missing or the measurements are degenerated
+        CalibrationFailed,
+        // A calibration or measurement was asked to average 0 samples
+        NoSamples,
+        // The chip didn't keep the value written to the register
+        Verification { register: u8, written: u8, read: u8 }
+    }
+
+    impl<E> From<E> for GY521Error<E> {
+        fn from(err: E) -> Self {
+            GY521Error::I2C(err)
+        }
+    }
+
+    impl<E: Debug> Debug for GY521Error<E> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match self {
+                GY521Error::I2C(err) => write!(f, "I2C transaction with the MPU-6050 failed with: {err:?}")?,
+                GY521Error::UnknownDevice(who_am_i) => write!(f, "Expected an MPU-6050 (WHO_AM_I {:#04x}), found {who_am_i:#04x}", super::WHO_AM_I)?,
+                GY521Error::NotInitialized => write!(f, "Initialize the sensor with init first")?,
+                GY521Error::FifoDisabled => write!(f, "Enable the FIFO with enable_fifo first")?,
+                GY521Error::WrongOrientation(orientation) => write!(f, "The board doesn't rest in the orientation {orientation:?}")?,
+                GY521Error::CalibrationFailed => write!(f, "The six position calibration is incomplete or its measurements are degenerated")?,
+                GY521Error::NoSamples => write!(f, "At least one sample is needed for the average")?,
+                GY521Error::InvalidRegister { register, value } => write!(f, "Register {register:#04x} holds the reserved value {value:#04x}")?,
+                GY521Error::Verification { register, written, read } => write!(f, "Register {register:#04x} reads {read:#04x} after writing {written:#04x}")?
+            }
+            Ok(())
+        }
+    }
+}
+
+// This modul serves with configu
+mod mpu_configuration;
+
+// This module serves data interpretation
+mod sensor_data;
+
+// Configuration and result of the FIFO mode
+mod fifo;
+
+// INT pin configuration and the data ready signal for the interrupt handler
+mod interrupt;
+
+// Factory trim and evaluation of the self-test
+mod self_test;
+
+// Six position calibration of the accelerometer
+mod accel_calibration;
+
+// Temperature model of the gyro bias
+mod temperature;
+
+// Mounting of the breakout on the frame
+mod alignment;
+
+// Flash format of the calibration
+mod storage;
+
+// Bus clear and supervision of the I2C link
+mod recovery;
+
+// Register level MPU-6050 for running the driver without hardware
+pub mod mock;
+
+// Mean of the raw outputs while the sensor rests
+struct RawAverage {
+    accel: [f32; 3], // LSB
+    gyro: [f32; 3],  // LSB
+    temperature: f32 // °C
+}
+
+// Output with the self-test enabled minus the output without, in LSB of ±8g and ±250°/s
+struct SelfTestResponse {
+    accel: [f32; 3],
+    gyro: [f32; 3]
+}
+
+// The I2C address is selected with the AD0 pin of the breakout
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Address {
+    AD0Low  = 0x68,
+    AD0High = 0x69
+}
+
+// I2C is the bus master, D waits between samples and clock returns the time since boot in µs,
+// which is attached to every `DataFrame` (on the ESP32: `esp_hal::time::now`).
+pub struct GY521<I2C, D> {
+    master: I2C,
+    address: u8,
+    delay: D,
+    clock: fn() -> u64,
+    scaling_factor: Option<ScalingFactor>,
+    config: Option<MPUConfig>,
+    interrupt: Option<InterruptConfig>,
+    bypass: bool,
+    calibration_offsets: CalibrationOffsets,
+    alignment: BoardAlignment,
+    fifo: Option<FifoSources>,
+    sample_period_µs: u32
+}
+
+impl <I2C: I2c, D: DelayNs> GY521<I2C, D> {
+    pub fn new(master: I2C, address: Address, delay: D, clock: fn() -> u64) -> Self {
+        Self { master, address: address as u8, delay, clock, scaling_factor: None, config: None, interrupt: None, bypass: false, calibration_offsets: CalibrationOffsets::default(), alignment: BoardAlignment::default(), fifo: None, sample_period_µs: 0 }
+    }
+
+    // Gives the bus back, e.g. to share it with another sensor
+    pub fn release(self) -> (I2C, D) {
+        (self.master, self.delay)
+    }
+
+    // Checks that an MPU-6050 answers at the address
+    pub fn probe(&mut self) -> Result<(), GY521Error<I2C::Error>> {
+        let mut who_am_i: [u8; 1] = [0];
+        self.master.write_read(self.address, &[WHO_AM_I_ADDR], &mut who_am_i)?;
+
+        if who_am_i[0] != WHO_AM_I {
+            return Err(GY521Error::UnknownDevice(who_am_i[0]));
+        }
+        Ok(())
+    }
+
+    // Probes the chip, writes the whole configuration and reads every register back, so a chip that didn't take it
+    // is noticed here and not by wrong measurements
+    pub fn init(&mut self, config: MPUConfig) -> Result<(), GY521Error<I2C::Error>> {
+        self.probe()?;
+
+        self.scaling_factor = Some(config.get_scaling_factor());
+        self.config = Some(config);
+        let pwr_mgmt_1: u8 = config.pwr_mgmt_1();
+
+        let registers: [(u8, u8); 6] = [
+            (PWR_MGMT_1_ADDR, config.clock_source as u8), // Power on chip with the selected clock
+            (SMPLRT_DIV_ADDR, config.sample_rate_divider), // Set Sample Rate
+            (DLPF_CONFIG_ADDR, config.dlpf as u8), // Set DLPF
+            (GYRO_CONFIG_ADDR, config.g_fs as u8), // Set Gyro Full-Scale Range
+            (ACCELO_CONFIG_ADDR, config.a_fs as u8), // Set Accelorometer Full-Scale Range
+            (PWR_MGMT_2_ADDR, config.power_mode.pwr_mgmt_2()) // Wake up frequency of the cycle mode
+        ];
+
+        for (register, value) in registers {
+            self.master.write(self.address, &[register, value])?;
+        }
+
+        // Sleep and cycle mode are entered last, the chip is configured awake
+        self.master.write(self.address, &[PWR_MGMT_1_ADDR, pwr_mgmt_1])?;
+
+        for (register, value) in registers[1..].iter().copied() {
+            self.verify_register(register, value)?;
+        }
+        self.verify_register(PWR_MGMT_1_ADDR, pwr_mgmt_1)
+    }
+
+    // Switches between normal, sleep and cycle mode, without touching the rest of the configuration
+    pub fn set_power_mode(&mut self, power_mode: PowerMode) -> Result<(), GY521Error<I2C::Error>> {
+        self.modify_register(PWR_MGMT_2_ADDR, LP_WAKE_MASK, power_mode.pwr_mgmt_2())?;
+        self.modify_register(PWR_MGMT_1_ADDR, POWER_MODE_MASK, power_mode.pwr_mgmt_1())?;
+
+        if let Some(config) = self.config.as_mut() {
+            config.power_mode = power_mode;
+        }
+        Ok(())
+    }
+
+    // After the chip lost its registers (brown-out) or the bus was cleared: runs `init` with the last configuration
+    // and enables FIFO, data ready interrupt and bypass again if they were enabled. Calibration and alignment are kept,
+    // there is no self-test, so this only takes a few register writes.
+    pub fn reinit(&mut self) -> Result<(), GY521Error<I2C::Error>> {
+        let config: MPUConfig = self.config.ok_or(GY521Error::NotInitialized)?;
+        self.init(config)?;
+
+        if let Some(sources) = self.fifo {
+            self.enable_fifo(sources)?;
+        }
+        if let Some(interrupt) = self.interrupt {
+            self.enable_data_ready(interrupt)?;
+        }
+        if self.bypass {
+            self.set_bypass(true)?;
+        }
+        Ok(())
+    }
+
+    // Runs the self-test of the datasheet, the sensor must not move during it. It temporarily switches to ±8g and
+    // ±250°/s, afterwards the configuration of `init` is restored, also if the test failed on the way. Takes about
+    // half a second
+    pub fn self_test(&mut self) -> Result<SelfTestResult, GY521Error<I2C::Error>> {
+        let mut configs: [u8; 2] = [0; 2];
+        self.master.write_read(self.address, &[GYRO_CONFIG_ADDR], &mut configs)?;
+        let [gyro_config, accel_config] = configs;
+
+        // Left in the self-test, the chip would measure with the wrong range and the self-test offset
+        let response: Result<SelfTestResponse, GY521Error<I2C::Error>> = self.self_test_response();
+        let restored: Result<(), I2C::Error> = self.master.write(self.address, &[GYRO_CONFIG_ADDR, gyro_config, accel_config]);
+        let SelfTestResponse { accel: accel_response, gyro: gyro_response } = response?;
+        restored?;
+
+        let mut registers: [u8; 4] = [0; 4];
+        self.master.write_read(self.address, &[SELF_TEST_ADDR], &mut registers)?;
+        let (accel_test, gyro_test) = self_test::test_values(registers);
+
+        let mut result: SelfTestResult = SelfTestResult { accel: [0.0; 3], gyro: [0.0; 3] };
+        for axis in 0..3 {
+            result.accel[axis] = self_test::deviation(accel_response[axis], self_test::accel_factory_trim(accel_test[axis]));
+            result.gyro[axis] = self_test::deviation(gyro_response[axis], self_test::gyro_factory_trim(gyro_test[axis], axis));
+        }
+
+        Ok(result)
+    }
+
+    // Leaves the chip in the self-test configuration
+    fn self_test_response(&mut self) -> Result<SelfTestResponse, GY521Error<I2C::Error>> {
+        self.master.write(self.address, &[GYRO_CONFIG_ADDR, GFullRangeScale::Sel_250 as u8, AFullRangeScale::Sel_8g as u8])?;
+        self.delay.delay_ms(SELF_TEST_SETTLE_MS);
+        let RawAverage { accel, gyro, .. } = self.average_raw(SELF_TEST_SAMPLES)?;
+
+        self.master.write(self.address, &[
+            GYRO_CONFIG_ADDR, 
+            GFullRangeScale::Sel_250 as u8 | SELF_TEST_EN, 
+            AFullRangeScale::Sel_8g as u8 | SELF_TEST_EN
+        ])?;
+        self.delay.delay_ms(SELF_TEST_SETTLE_MS);
+        let RawAverage { accel: accel_st, gyro: gyro_st, .. } = self.average_raw(SELF_TEST_SAMPLES)?;
+
+        Ok(SelfTestResponse {
+            accel: [0, 1, 2].map(|axis| accel_st[axis] - accel[axis]),
+            gyro: [0, 1, 2].map(|axis| gyro_st[axis] - gyro[axis])
+        })
+    }
+
+    fn average_raw(&mut self, samples: u16) -> Result<RawAverage, GY521Error<I2C::Error>> {
+        if samples == 0 {
+            return Err(GY521Error::NoSamples);
+        }
+
+        let mut registers: [u8; FRAME_SIZE] = [0; FRAME_SIZE];
+        let mut accel: [f32; 3] = [0.0; 3];
+        let mut gyro: [f32; 3] = [0.0; 3];
+        let mut temperature: f32 = 0.0;
+
+        for _ in 0..samples {
+            self.master.write_read(self.address, &[SENSOR_READ_ADDR], &mut registers)?;
+
+            for axis in 0..3 {
+                accel[axis] += i16::from_be_bytes([registers[2 * axis], registers[2 * axis + 1]]) as f32;
+                gyro[axis] += i16::from_be_bytes([registers[8 + 2 * axis], registers[8 + 2 * axis + 1]]) as f32;
+            }
+            temperature += DataFrame::get_temperature_data(&registers[6..8]);
+            self.delay.delay_ms(1);
+        }
+
+        Ok(RawAverage {
+            accel: accel.map(|sum| sum / samples as f32),
+            gyro: gyro.map(|sum| sum / samples as f32),
+            temperature: temperature / samples as f32
+        })
+    }
+
+    // This function is to be used AFTER the sensor has been set into a level possition. Iterations defines how many samples
+    // it takes before performing calibration. The higher the DLPF, the more iteration you need to acount for the extra noise
+    //
+    // Level means the breakout lies with its z axis up (`Orientation::ZUp`), independent of `BoardAlignment`. The scale
+    // and misalignment of a six position calibration stay, the bias is chosen so that the corrected reading is 1g on z.
+    pub fn calibrate(&mut self, iteration: u16) -> Result<(), GY521Error<I2C::Error>> {
+        if iteration == 0 {
+            return Err(GY521Error::NoSamples);
+        }
+
+        let mut ax_offset: i32 = 0;
+        let mut ay_offset: i32 = 0;
+        let mut az_offset: i32 = 0;
+
+        let mut gx_offset: i32 = 0;
+        let mut gy_offset: i32 = 0;
+        let mut gz_offset: i32 = 0;
+
+        let mut temperature: f32 = 0.0;
+
+        let extract_values: fn(&[u8]) -> (i16, i16, i16) = |bytes: &[u8]| -> (i16, i16, i16) {
+            let x: i16 = i16::from_be_bytes([bytes[0], bytes[1]]);
+            let y: i16 = i16::from_be_bytes([bytes[2], bytes[3]]); 
+            let z: i16 = i16::from_be_bytes([bytes[4], bytes[5]]);
+
+            (x, y, z)
+        };
+
+        let mut registers: [u8; FRAME_SIZE] = [0; FRAME_SIZE];
+
+        let one_g: f32 = self.scaling_factor.as_ref().ok_or(GY521Error::NotInitialized)?.a;
+        let delay_µs: u32 = self.get_delay()?;
+
+        for _ in 0..iteration {
+            self.master.write_read(self.address, &[SENSOR_READ_ADDR], &mut registers)?;
+
+            let (ax, ay, az) = extract_values(&registers[0..6]);
+            let (gx, gy, gz) = extract_values(&registers[8..14]);
+            
+            ax_offset += ax as i32;
+            ay_offset += ay as i32;
+            az_offset += az as i32;
+
+            gx_offset += gx as i32;
+            gy_offset += gy as i32;
+            gz_offset += gz as i32;
+
+            temperature += DataFrame::get_temperature_data(&registers[6..8]);
+
+            self.delay.delay_us(delay_µs);
+        }
+
+        // To acount for earth gravitational pull: the raw reading of 1g on z is the inverse of the accel matrix applied to
+        // it, not simply 1g in LSB. A scale or misalignment correction would otherwise turn the z bias into a tilt
+        let inverse: [[f32; 3]; 3] = accel_calibration::invert(self.calibration_offsets.accel_matrix).ok_or(GY521Error::CalibrationFailed)?;
+        self.calibration_offsets.accel_bias = [
+            ax_offset as f32 / iteration as f32 - inverse[0][2] * one_g,
+            ay_offset as f32 / iteration as f32 - inverse[1][2] * one_g,
+            az_offset as f32 / iteration as f32 - inverse[2][2] * one_g
+        ];
+        // The temperature model keeps its shape, only the bias at its reference temperature moves
+        let drift: [f32; 3] = self.calibration_offsets.gyro_temperature.drift(temperature / iteration as f32);
+        self.calibration_offsets.gyro_bias = [
+            gx_offset as f32 / iteration as f32 - drift[0],
+            gy_offset as f32 / iteration as f32 - drift[1],
+            gz_offset as f32 / iteration as f32 - drift[2]
+        ];
+        Ok(())
+    }
+    
+    pub fn read(&mut self) -> Result<DataFrame, GY521Error<I2C::Error>> {
+        // One transaction, so accel, temperature and gyro are from the same sample
+        let scaling_factor: &ScalingFactor = self.scaling_factor.as_ref().ok_or(GY521Error::NotInitialized)?;
+        let mut registers: [u8; FRAME_SIZE] = [0; FRAME_SIZE];
+        let timestamp: u64 = (self.clock)();
+
+        self.master.write_read(self.address, &[SENSOR_READ_ADDR], &mut registers)?;
+
+        Ok(
+            DataFrame::new(
+                registers,
+                scaling_factor,
+                &self.calibration_offsets,
+                &self.alignment,
+                timestamp
+            )
+        )
+    }
+
+    // Returns the delay in µs
+    pub fn get_delay(&mut self) -> Result<u32, GY521Error<I2C::Error>> {
+        let mut dlpf_register: [u8; 1] = [0];
+        self.master.write_read(self.address, &[DLPF_CONFIG_ADDR], &mut dlpf_register)?;
+        
+        let delay_µs: u32 = Dlpf::from_register(dlpf_register[0])
+            .ok_or(GY521Error::InvalidRegister { register: DLPF_CONFIG_ADDR, value: dlpf_register[0] })?
+            .get_delay();
+        Ok(delay_µs)
+    }
+
+    // From now on the chip writes every sample of the selected sensors into its 1024 byte FIFO, which is read with
+    // `read_fifo`. Has to be called after `init`, the sample rate is read from the chip.
+    pub fn enable_fifo(&mut self, sources: FifoSources) -> Result<(), GY521Error<I2C::Error>> {
+        assert!(sources.frame_size() > 0, "Select at least one sensor for the FIFO");
+
+        // SMPLRT_DIV and CONFIG: Sample Rate = Gyroscope Output Rate / (1 + SMPLRT_DIV)
+        let mut registers: [u8; 2] = [0; 2];
+        self.master.write_read(self.address, &[SMPLRT_DIV_ADDR], &mut registers)?;
+
+        let output_rate: u32 = Dlpf::from_register(registers[1])
+            .ok_or(GY521Error::InvalidRegister { register: DLPF_CONFIG_ADDR, value: registers[1] })?
+            .get_output_rate();
+        self.sample_period_µs = (1 + registers[0] as u32) * 1_000_000 / output_rate;
+
+        self.master.write(self.address, &[FIFO_EN_ADDR, sources.register()])?;
+        self.modify_register(INT_ENABLE_ADDR, FIFO_OFLOW_INT, FIFO_OFLOW_INT)?;
+        self.fifo = Some(sources);
+
+        self.reset_fifo()
+    }
+
+    pub fn disable_fifo(&mut self) -> Result<(), GY521Error<I2C::Error>> {
+        self.fifo = None;
+
+        self.modify_register(USER_CTRL_ADDR, USER_FIFO_EN, 0)?;
+        self.modify_register(INT_ENABLE_ADDR, FIFO_OFLOW_INT, 0)?;
+        self.master.write(self.address, &[FIFO_EN_ADDR, 0])?;
+        Ok(())
+    }
+
+    // Discards the content of the FIFO. The chip only resets the FIFO while it is disabled
+    pub fn reset_fifo(&mut self) -> Result<(), GY521Error<I2C::Error>> {
+        self.modify_register(USER_CTRL_ADDR, USER_FIFO_EN | FIFO_RESET, FIFO_RESET)?;
+        self.modify_register(USER_CTRL_ADDR, USER_FIFO_EN | FIFO_RESET, USER_FIFO_EN)
+    }
+
+    // Number of bytes in the FIFO
+    pub fn fifo_count(&mut self) -> Result<u16, GY521Error<I2C::Error>> {
+        let mut count: [u8; 2] = [0; 2];
+        self.master.write_read(self.address, &[FIFO_COUNT_ADDR], &mut count)?;
+        Ok(u16::from_be_bytes(count))
+    }
+
+    // One step of the six position calibration: averages the accelerometer while the board rests in orientation.
+    // Ask for `AccelCalibration::next_orientation`, wait until the board rests and call this, until the calibration
+    // is complete.
+    pub fn measure_orientation(&mut self, calibration: &mut AccelCalibration, orientation: Orientation, samples: u16) -> Result<(), GY521Error<I2C::Error>> {
+        let RawAverage { accel, .. } = self.average_raw(samples)?;
+
+        if !orientation.matches(accel) {
+            return Err(GY521Error::WrongOrientation(orientation));
+        }
+
+        calibration.add(orientation, accel);
+        Ok(())
+    }
+
+    // Replaces bias, scale and (with misalignment) the cross axis correction of the accelerometer. Has to be measured
+    // with the range of `init`. A later `calibrate` only replaces the bias again.
+    pub fn apply_accel_calibration(&mut self, calibration: &AccelCalibration, misalignment: bool) -> Result<(), GY521Error<I2C::Error>> {
+        let one_g: f32 = self.scaling_factor.as_ref().ok_or(GY521Error::NotInitialized)?.a;
+
+        self.calibration_offsets = calibration
+            .solve(one_g, misalignment, &self.calibration_offsets)
+            .ok_or(GY521Error::CalibrationFailed)?;
+        Ok(())
+    }
+
+    // One point of the temperature sweep: averages gyro and die temperature. The board has to rest while it warms up
+    // (e.g. after a cold start), call this every few seconds until the sweep spans enough temperature.
+    pub fn measure_gyro_temperature(&mut self, sweep: &mut TemperatureSweep, samples: u16) -> Result<(), GY521Error<I2C::Error>> {
+        let RawAverage { gyro, temperature, .. } = self.average_raw(samples)?;
+        sweep.add(temperature, gyro);
+        Ok(())
+    }
+
+    // Replaces gyro bias and temperature model by the fit of the sweep, order is 1 (linear) or 2 (quadratic).
+    // The model is in LSB, it is only valid for the gyro range of the sweep.
+    pub fn apply_gyro_temperature_calibration(&mut self, sweep: &TemperatureSweep, order: usize) -> Result<(), GY521Error<I2C::Error>> {
+        let (bias, model) = sweep.fit(order).ok_or(GY521Error::CalibrationFailed)?;
+
+        self.calibration_offsets.gyro_bias = bias;
+        self.calibration_offsets.gyro_temperature = model;
+        Ok(())
+    }
+
+    pub fn calibration_offsets(&self) -> &CalibrationOffsets {
+        &self.calibration_offsets
+    }
+
+    // E.g. a calibration loaded from flash, instead of calibrating again
+    pub fn set_calibration_offsets(&mut self, calibration_offsets: CalibrationOffsets) {
+        self.calibration_offsets = calibration_offsets;
+    }
+
+    pub fn alignment(&self) -> &BoardAlignment {
+        &self.alignment
+    }
+
+    // How the breakout is mounted on the frame, every following frame is in body axes
+    pub fn set_alignment(&mut self, alignment: BoardAlignment) {
+        self.alignment = alignment;
+    }
+
+    // Takes up to N samples out of the FIFO in a single transaction. The newest sample in the FIFO is stamped with the
+    // current time, the older ones one sample period apart. After an overflow the chip has overwritten the oldest bytes,
+    // so the frame boundaries are lost: the FIFO is reset and the batch is empty and marked as overflowed.
+    pub fn read_fifo<const N: usize>(&mut self, batch: &mut FifoBatch<N>) -> Result<(), GY521Error<I2C::Error>> {
+        batch.clear();
+
+        let sources: FifoSources = self.fifo.ok_or(GY521Error::FifoDisabled)?;
+        if self.scaling_factor.is_none() {
+            return Err(GY521Error::NotInitialized);
+        }
+        let timestamp: u64 = (self.clock)();
+
+        // Reading INT_STATUS clears it
+        let mut int_status: [u8; 1] = [0];
+        self.master.write_read(self.address, &[INT_STATUS_ADDR], &mut int_status)?;
+        let count: usize = self.fifo_count()? as usize;
+
+        if int_status[0] & FIFO_OFLOW_INT != 0 || count >= FIFO_SIZE {
+            batch.overflow = true;
+            return self.reset_fifo();
+        }
+
+        let frame_size: usize = sources.frame_size();
+        let available: usize = count / frame_size;
+        let frames: usize = available.min(N);
+
+        if frames == 0 {
+            return Ok(());
+        }
+
+        let mut buffer: [u8; FIFO_SIZE] = [0; FIFO_SIZE];
+        self.master.write_read(self.address, &[FIFO_R_W_ADDR], &mut buffer[..frames * frame_size])?;
+
+        for (index, bytes) in buffer[..frames * frame_size].chunks_exact(frame_size).enumerate() {
+            let age: u64 = (available - 1 - index) as u64 * self.sample_period_µs as u64;
+            let frame: DataFrame = self.fifo_frame(&sources, bytes, timestamp.saturating_sub(age))?;
+
+            // Can't fail, at most N frames are read
+            let _ = batch.frames.push(frame);
+        }
+
+        Ok(())
+    }
+
+    // Places the bytes of one FIFO sample where they are in the register burst of `read`.
+    // Sensors which are not in the FIFO read as 0, the temperature as 36.53 °C
+    fn fifo_frame(&self, sources: &FifoSources, bytes: &[u8], timestamp: u64) -> Result<DataFrame, GY521Error<I2C::Error>> {
+        let mut registers: [u8; FRAME_SIZE] = [0; FRAME_SIZE];
+        let mut offset: usize = 0;
+
+        if sources.accel {
+            registers[0..6].copy_from_slice(&bytes[offset..offset + 6]);
+            offset += 6;
+        }
+        if sources.temperature {
+            registers[6..8].copy_from_slice(&bytes[offset..offset + 2]);
+            offset += 2;
+        }
+        if sources.gyro {
+            registers[8..14].copy_from_slice(&bytes[offset..offset + 6]);
+        }
+
+        let mut calibration: CalibrationOffsets = self.calibration_offsets;
+        if !sources.accel {
+            calibration.accel_bias = [0.0; 3];
+        }
+        if !sources.gyro {
+            calibration.gyro_bias = [0.0; 3];
+        }
+        // Without the die temperature the drift can't be compensated
+        if !sources.gyro || !sources.temperature {
+            calibration.gyro_temperature = GyroTemperatureModel::default();
+        }
+
+        Ok(
+            DataFrame::new(
+                registers,
+                self.scaling_factor.as_ref().ok_or(GY521Error::NotInitialized)?,
+                &calibration,
+                &self.alignment,
+                timestamp
+            )
+        )
+    }
+
+    // Pulses the INT pin whenever a new sample is in the sensor registers (and the FIFO). Bind a GPIO interrupt to
+    // the pin, which calls `DataReady::signal`, and pace the loop with `DataReady::wait`.
+    pub fn enable_data_ready(&mut self, config: InterruptConfig) -> Result<(), GY521Error<I2C::Error>> {
+        self.modify_register(INT_PIN_CFG_ADDR, INT_PIN_MASK, config.register())?;
+        self.modify_register(INT_ENABLE_ADDR, DATA_RDY_INT, DATA_RDY_INT)?;
+        self.interrupt = Some(config);
+        Ok(())
+    }
+
+    pub fn disable_data_ready(&mut self) -> Result<(), GY521Error<I2C::Error>> {
+        self.interrupt = None;
+        self.modify_register(INT_ENABLE_ADDR, DATA_RDY_INT, 0)
+    }
+
+    // Connects the auxiliary bus (XDA, XCL) to the main bus, so a sensor behind the MPU-6050 (e.g. the magnetometer
+    // of a GY-87) answers on the bus of the driver under its own address. The I2C master of the chip is switched
+    // off for it, it would drive the auxiliary bus as well.
+    pub fn set_bypass(&mut self, enable: bool) -> Result<(), GY521Error<I2C::Error>> {
+        if enable {
+            self.modify_register(USER_CTRL_ADDR, I2C_MST_EN, 0)?;
+        }
+        self.modify_register(INT_PIN_CFG_ADDR, I2C_BYPASS_EN, if enable { I2C_BYPASS_EN } else { 0 })?;
+        self.bypass = enable;
+        Ok(())
+    }
+
+    // Polls the data ready flag instead of using the pin. Reading INT_STATUS clears all its flags,
+    // `read_fifo` then only notices an overflow by the full FIFO.
+    pub fn data_ready(&mut self) -> Result<bool, GY521Error<I2C::Error>> {
+        let mut int_status: [u8; 1] = [0];
+        self.master.write_read(self.address, &[INT_STATUS_ADDR], &mut int_status)?;
+        Ok(int_status[0] & DATA_RDY_INT != 0)
+    }
+
+    fn verify_register(&mut self, register: u8, written: u8) -> Result<(), GY521Error<I2C::Error>> {
+        let mut read: [u8; 1] = [0];
+        self.master.write_read(self.address, &[register], &mut read)?;
+
+        if read[0] != written {
+            return Err(GY521Error::Verification { register, written, read: read[0] });
+        }
+        Ok(())
+    }
+
+    // Only changes the bits of mask
+    fn modify_register(&mut self, register: u8, mask: u8, value: u8) -> Result<(), GY521Error<I2C::Error>> {
+        let mut current: [u8; 1] = [0];
+        self.master.write_read(self.address, &[register], &mut current)?;
+        self.master.write(self.address, &[register, (current[0] & !mask) | (value & mask)])?;
+        Ok(())
+    }
+}
+
+// The driver against the register level mock, run on the host by `cargo test` in the simulator
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::{Cell, Ref, RefCell};
+    use embedded_hal_bus::i2c::RefCellDevice;
+    use mock::{MockDelay, MockMPU6050};
+
+    const TIMESTAMP: u64 = 1_000_000;
+
+    fn clock() -> u64 {
+        TIMESTAMP
+    }
+
+    fn gy521(mock: &mut MockMPU6050) -> GY521<&mut MockMPU6050, MockDelay> {
+        GY521::new(mock, Address::AD0Low, MockDelay, clock)
+    }
+
+    #[test]
+    fn init_writes_the_configuration() {
+        let mut mock: MockMPU6050 = MockMPU6050::new(Address::AD0Low);
+        let config: MPUConfig = MPUConfig::default()
+            .set_dlpf(Dlpf::Hz_42)
+            .set_afs(AFullRangeScale::Sel_4g)
+            .set_gfs(GFullRangeScale::Sel_500)
+            .set_sample_rate_divider(4)
+            .set_clock_source(ClockSource::PllGyroZ);
+
+        gy521(&mut mock).init(config).expect("mock MPU-6050");
+
+        assert!(!mock.is_sleeping());
+        assert_eq!(mock.register(PWR_MGMT_1_ADDR), ClockSource::PllGyroZ as u8);
+        assert_eq!(mock.register(SMPLRT_DIV_ADDR), 4);
+        assert_eq!(mock.register(DLPF_CONFIG_ADDR), Dlpf::Hz_42 as u8);
+        assert_eq!(mock.register(GYRO_CONFIG_ADDR), GFullRangeScale::Sel_500 as u8);
+        assert_eq!(mock.register(ACCELO_CONFIG_ADDR), AFullRangeScale::Sel_4g as u8);
+        assert_eq!(mock.register(PWR_MGMT_2_ADDR), 0);
+    }
+
+    #[test]
+    fn init_enters_sleep_mode_last() {
+        let mut mock: MockMPU6050 = MockMPU6050::new(Address::AD0Low);
+        let config: MPUConfig = MPUConfig::default().set_power_mode(PowerMode::Cycle(WakeFrequency::Hz_20));
+
+        gy521(&mut mock).init(config).expect("mock MPU-6050");
+
+        assert_eq!(mock.register(PWR_MGMT_1_ADDR), ClockSource::PllGyroX as u8 | PowerMode::Cycle(WakeFrequency::Hz_20).pwr_mgmt_1());
+        assert_eq!(mock.register(PWR_MGMT_2_ADDR), WakeFrequency::Hz_20 as u8);
+    }
+
+    #[test]
+    fn init_rejects_other_chips() {
+        let mut mock: MockMPU6050 = MockMPU6050::new(Address::AD0Low);
+        mock.set_register(WHO_AM_I_ADDR, 0x70);
+        assert!(matches!(gy521(&mut mock).init(MPUConfig::default()), Err(GY521Error::UnknownDevice(0x70))));
+        assert!(mock.is_sleeping());
+
+        let mut mock: MockMPU6050 = MockMPU6050::new(Address::AD0High);
+        assert!(matches!(gy521(&mut mock).init(MPUConfig::default()), Err(GY521Error::I2C(_))));
+    }
+
+    #[test]
+    fn read_scales_the_frame() {
+        let mut mock: MockMPU6050 = MockMPU6050::new(Address::AD0Low);
+        mock.set_accel([4096, -2048, 8192]);
+        mock.set_gyro([328, -164, 0]);
+        mock.set_temperature(340);
+
+        let mut gy521: GY521<&mut MockMPU6050, MockDelay> = gy521(&mut mock);
+        assert!(matches!(gy521.read(), Err(GY521Error::NotInitialized)));
+
+        // ±8g and ±1000°/s: 4096 LSB/g and 32.8 LSB/(°/s)
+        gy521.init(MPUConfig::default()).expect("mock MPU-6050");
+        let frame: DataFrame = gy521.read().expect("mock MPU-6050");
+
+        let (accel, gyro) = (frame.get_accel(), frame.get_gyro());
+        assert_eq!([accel.x, accel.y, accel.z], [1.0, -0.5, 2.0]);
+        assert!((gyro.x - 10.0).abs() < 1e-4 && (gyro.y + 5.0).abs() < 1e-4 && gyro.z == 0.0, "{gyro:?}");
+        assert!((frame.get_temperature() - 37.53).abs() < 1e-4);
+        assert_eq!(frame.get_timestamp(), TIMESTAMP);
+    }
+
+    #[test]
+    fn read_applies_the_alignment() {
+        let mut mock: MockMPU6050 = MockMPU6050::new(Address::AD0Low);
+        mock.set_accel([4096, 0, 0]);
+
+        // Chip x points backwards in the body frame
+        let mut gy521: GY521<&mut MockMPU6050, MockDelay> = gy521(&mut mock);
+        gy521.set_alignment(BoardAlignment::new(SensorAxis::NegX, SensorAxis::PosZ).expect("perpendicular axes"));
+        gy521.init(MPUConfig::default()).expect("mock MPU-6050");
+
+        let frame: DataFrame = gy521.read().expect("mock MPU-6050");
+        let accel: &AccelometerData = frame.get_accel();
+        assert!((accel.x + 1.0).abs() < 1e-6 && accel.y.abs() < 1e-6 && accel.z.abs() < 1e-6, "{accel:?}");
+    }
+
+    #[test]
+    fn read_flags_raw_saturation() {
+        let mut mock: MockMPU6050 = MockMPU6050::new(Address::AD0Low);
+        mock.set_accel([i16::MAX, 4096, i16::MIN]);
+        mock.set_gyro([0, -i16::MAX, 32766]);
+
+        // Chip y points forward, chip z up: body x is chip y, body y is -chip x
+        let mut gy521: GY521<&mut MockMPU6050, MockDelay> = gy521(&mut mock);
+        gy521.set_alignment(BoardAlignment::new(SensorAxis::PosY, SensorAxis::PosZ).expect("perpendicular axes"));
+        gy521.init(MPUConfig::default()).expect("mock MPU-6050");
+
+        let frame: DataFrame = gy521.read().expect("mock MPU-6050");
+        assert_eq!(frame.get_accel_saturated(), [false, true, true]);
+        assert_eq!(frame.get_gyro_saturated(), [true, false, false]);
+    }
+
+    #[test]
+    fn calibrate_removes_the_bias() {
+        let mut mock: MockMPU6050 = MockMPU6050::new(Address::AD0Low);
+        mock.set_accel([100, -50, 4096 + 30]);
+        mock.set_gyro([10, -20, 5]);
+
+        let mut gy521: GY521<&mut MockMPU6050, MockDelay> = gy521(&mut mock);
+        assert!(matches!(gy521.calibrate(10), Err(GY521Error::NotInitialized)));
+
+        gy521.init(MPUConfig::default()).expect("mock MPU-6050");
+        gy521.calibrate(10).expect("mock MPU-6050");
+
+        // Gravity stays on z
+        assert_eq!(gy521.calibration_offsets().accel_bias(), [100.0, -50.0, 30.0]);
+        assert_eq!(gy521.calibration_offsets().gyro_bias(), [10.0, -20.0, 5.0]);
+
+        let frame: DataFrame = gy521.read().expect("mock MPU-6050");
+        let (accel, gyro) = (frame.get_accel(), frame.get_gyro());
+        assert_eq!([accel.x, accel.y, accel.z, gyro.x, gyro.y, gyro.z], [0.0, 0.0, 1.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn get_delay_follows_the_dlpf() {
+        let dlpfs: [Dlpf; 7] = [Dlpf::Hz_256, Dlpf::Hz_188, Dlpf::Hz_98, Dlpf::Hz_42, Dlpf::Hz_20, Dlpf::Hz_10, Dlpf::Hz_5];
+
+        for dlpf in dlpfs {
+            let mut mock: MockMPU6050 = MockMPU6050::new(Address::AD0Low);
+            let mut gy521: GY521<&mut MockMPU6050, MockDelay> = gy521(&mut mock);
+            gy521.init(MPUConfig::default().set_dlpf(dlpf)).expect("mock MPU-6050");
+            assert_eq!(gy521.get_delay().expect("mock MPU-6050"), dlpf.get_delay());
+        }
+
+        // DLPF_CFG 7 is reserved
+        let mut mock: MockMPU6050 = MockMPU6050::new(Address::AD0Low);
+        mock.set_register(DLPF_CONFIG_ADDR, 7);
+        let result: Result<u32, GY521Error<mock::MockError>> = gy521(&mut mock).get_delay();
+        assert!(matches!(result, Err(GY521Error::InvalidRegister { register: DLPF_CONFIG_ADDR, value: 7 })));
+    }
+
+    type SharedGY521<'a> = GY521<RefCellDevice<'a, MockMPU6050>, MockDelay>;
+
+    // Initialized at 1 kHz with the FIFO of sources enabled, the mock stays accessible to produce samples
+    fn fifo_gy521(bus: &RefCell<MockMPU6050>, sources: FifoSources) -> SharedGY521<'_> {
+        let mut gy521: SharedGY521 = GY521::new(RefCellDevice::new(bus), Address::AD0Low, MockDelay, clock);
+        gy521.init(MPUConfig::default()).expect("mock MPU-6050");
+        gy521.enable_fifo(sources).expect("mock MPU-6050");
+        gy521
+    }
+
+    // Sample index has a roll rate of 10 * index °/s
+    fn sample(bus: &RefCell<MockMPU6050>, index: i16) {
+        let mut mock = bus.borrow_mut();
+        mock.set_accel([0, 0, 4096]);
+        mock.set_gyro([328 * index, 0, 0]);
+        mock.sample();
+    }
+
+    fn roll_rates<const N: usize>(batch: &FifoBatch<N>) -> heapless::Vec<f32, N> {
+        batch.iter().map(|frame| libm::roundf(frame.get_gyro().x)).collect()
+    }
+
+    #[test]
+    fn reinit_restores_the_chip_after_a_power_loss() {
+        let bus: RefCell<MockMPU6050> = RefCell::new(MockMPU6050::new(Address::AD0Low));
+        let mut gy521: SharedGY521 = GY521::new(RefCellDevice::new(&bus), Address::AD0Low, MockDelay, clock);
+        assert!(matches!(gy521.reinit(), Err(GY521Error::NotInitialized)));
+
+        let config: MPUConfig = MPUConfig::default().set_dlpf(Dlpf::Hz_42).set_sample_rate_divider(4);
+        let interrupt: InterruptConfig = InterruptConfig::default();
+        gy521.init(config).expect("mock MPU-6050");
+        gy521.enable_fifo(FifoSources::default()).expect("mock MPU-6050");
+        gy521.enable_data_ready(interrupt).expect("mock MPU-6050");
+        gy521.set_bypass(true).expect("mock MPU-6050");
+
+        let registers = || [SMPLRT_DIV_ADDR, DLPF_CONFIG_ADDR, FIFO_EN_ADDR, INT_PIN_CFG_ADDR, INT_ENABLE_ADDR, USER_CTRL_ADDR, PWR_MGMT_1_ADDR]
+            .map(|register| bus.borrow().register(register));
+        let configured: [u8; 7] = registers();
+
+        // While the chip is gone the reads fail, afterwards it is back in its power on state
+        bus.borrow_mut().set_connected(false);
+        assert!(matches!(gy521.read(), Err(GY521Error::I2C(_))));
+        assert!(matches!(gy521.reinit(), Err(GY521Error::I2C(_))));
+        bus.borrow_mut().set_connected(true);
+        bus.borrow_mut().power_cycle();
+        assert!(bus.borrow().is_sleeping());
+
+        gy521.reinit().expect("mock MPU-6050");
+        assert_eq!(registers(), configured);
+
+        let mock: Ref<MockMPU6050> = bus.borrow();
+        assert!(!mock.is_sleeping());
+        assert_eq!(mock.register(SMPLRT_DIV_ADDR), 4);
+        assert_eq!(mock.register(DLPF_CONFIG_ADDR), Dlpf::Hz_42 as u8);
+        assert_eq!(mock.register(FIFO_EN_ADDR), FifoSources::default().register());
+        assert_eq!(mock.register(USER_CTRL_ADDR) & (USER_FIFO_EN | I2C_MST_EN), USER_FIFO_EN);
+        assert_eq!(mock.register(INT_ENABLE_ADDR), FIFO_OFLOW_INT | DATA_RDY_INT);
+        assert_eq!(mock.register(INT_PIN_CFG_ADDR), interrupt.register() | I2C_BYPASS_EN);
+    }
+
+    #[test]
+    fn enable_fifo_configures_the_chip() {
+        let bus: RefCell<MockMPU6050> = RefCell::new(MockMPU6050::new(Address::AD0Low));
+        let mut gy521: SharedGY521 = GY521::new(RefCellDevice::new(&bus), Address::AD0Low, MockDelay, clock);
+        gy521.init(MPUConfig::default()).expect("mock MPU-6050");
+
+        let mut batch: FifoBatch<4> = FifoBatch::new();
+        assert!(matches!(gy521.read_fifo(&mut batch), Err(GY521Error::FifoDisabled)));
+
+        gy521.enable_fifo(FifoSources::default().set_temperature(false)).expect("mock MPU-6050");
+        assert_eq!(bus.borrow().register(FIFO_EN_ADDR), 0b0111_1000);
+        assert_eq!(bus.borrow().register(USER_CTRL_ADDR) & USER_FIFO_EN, USER_FIFO_EN);
+        assert_eq!(bus.borrow().register(INT_ENABLE_ADDR) & FIFO_OFLOW_INT, FIFO_OFLOW_INT);
+
+        gy521.disable_fifo().expect("mock MPU-6050");
+        assert_eq!(bus.borrow().register(FIFO_EN_ADDR), 0);
+        assert_eq!(bus.borrow().register(USER_CTRL_ADDR) & USER_FIFO_EN, 0);
+    }
+
+    #[test]
+    fn read_fifo_drains_oldest_first() {
+        let bus: RefCell<MockMPU6050> = RefCell::new(MockMPU6050::new(Address::AD0Low));
+        let mut gy521: SharedGY521 = fifo_gy521(&bus, FifoSources::default());
+
+        for index in 0..10 {
+            sample(&bus, index);
+        }
+        assert_eq!(gy521.fifo_count().expect("mock MPU-6050"), 140);
+
+        // At most N per call, the rest stays for the next one
+        let mut batch: FifoBatch<4> = FifoBatch::new();
+        let mut rates: [f32; 10] = [0.0; 10];
+        let mut read: usize = 0;
+        for expected in [4, 4, 2, 0] {
+            gy521.read_fifo(&mut batch).expect("mock MPU-6050");
+            assert_eq!((batch.len(), batch.overflowed()), (expected, false));
+
+            // The newest sample in the FIFO is stamped with now, the ones before 1 ms apart
+            let remaining: usize = 10 - read;
+            for (index, frame) in batch.iter().enumerate() {
+                assert_eq!(frame.get_timestamp(), TIMESTAMP - (remaining - 1 - index) as u64 * 1000);
+                assert!((frame.get_accel().z - 1.0).abs() < 1e-6);
+            }
+
+            rates[read..read + expected].copy_from_slice(&roll_rates(&batch));
+            read += expected;
+        }
+
+        assert_eq!(rates, [0.0, 10.0, 20.0, 30.0, 40.0, 50.0, 60.0, 70.0, 80.0, 90.0]);
+        assert_eq!(bus.borrow().fifo_len(), 0);
+    }
+
+    #[test]
+    fn read_fifo_leaves_a_partial_frame() {
+        let bus: RefCell<MockMPU6050> = RefCell::new(MockMPU6050::new(Address::AD0Low));
+        let mut gy521: SharedGY521 = fifo_gy521(&bus, FifoSources::default());
+        let mut batch: FifoBatch<8> = FifoBatch::new();
+
+        for index in 0..3 {
+            sample(&bus, index);
+        }
+
+        // The read catches the chip in the middle of writing the fourth sample, only its first 5 bytes are there
+        let mut frame: [u8; FRAME_SIZE] = [0; FRAME_SIZE];
+        frame[4..6].copy_from_slice(&4096i16.to_be_bytes());
+        frame[8..10].copy_from_slice(&(328 * 3i16).to_be_bytes());
+        let write = |bytes: &[u8]| {
+            let mut data: heapless::Vec<u8, { FRAME_SIZE + 1 }> = heapless::Vec::new();
+            let _ = data.push(FIFO_R_W_ADDR);
+            let _ = data.extend_from_slice(bytes);
+            bus.borrow_mut().write(Address::AD0Low as u8, &data).expect("mock MPU-6050");
+        };
+        write(&frame[..5]);
+
+        gy521.read_fifo(&mut batch).expect("mock MPU-6050");
+        assert_eq!(roll_rates(&batch), [0.0, 10.0, 20.0]);
+        assert_eq!(bus.borrow().fifo_len(), 5);
+
+        // The rest arrives, the frame is read whole and aligned
+        write(&frame[5..]);
+        gy521.read_fifo(&mut batch).expect("mock MPU-6050");
+        assert_eq!(roll_rates(&batch), [30.0]);
+        assert!((batch.frames()[0].get_accel().z - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn read_fifo_recovers_from_an_overflow() {
+        let bus: RefCell<MockMPU6050> = RefCell::new(MockMPU6050::new(Address::AD0Low));
+        let mut gy521: SharedGY521 = fifo_gy521(&bus, FifoSources::default());
+        let mut batch: FifoBatch<8> = FifoBatch::new();
+
+        // 1024 bytes hold 73 frames, the oldest bytes were overwritten and the frame boundaries are lost
+        for index in 0..80 {
+            sample(&bus, index);
+        }
+        gy521.read_fifo(&mut batch).expect("mock MPU-6050");
+        assert!(batch.overflowed() && batch.is_empty());
+        assert_eq!(bus.borrow().fifo_len(), 0);
+        assert_eq!(bus.borrow().register(USER_CTRL_ADDR) & USER_FIFO_EN, USER_FIFO_EN);
+
+        // The FIFO runs again after the reset
+        for index in 0..2 {
+            sample(&bus, index);
+        }
+        gy521.read_fifo(&mut batch).expect("mock MPU-6050");
+        assert!(!batch.overflowed());
+        assert_eq!(roll_rates(&batch), [0.0, 10.0]);
+    }
+
+    #[test]
+    fn read_fifo_fills_missing_sensors() {
+        let bus: RefCell<MockMPU6050> = RefCell::new(MockMPU6050::new(Address::AD0Low));
+        let mut gy521: SharedGY521 = fifo_gy521(&bus, FifoSources::default().set_accel(false).set_temperature(false));
+        let mut batch: FifoBatch<8> = FifoBatch::new();
+
+        for index in 0..3 {
+            sample(&bus, index);
+        }
+        assert_eq!(bus.borrow().fifo_len(), 18);
+
+        gy521.read_fifo(&mut batch).expect("mock MPU-6050");
+        assert_eq!(roll_rates(&batch), [0.0, 10.0, 20.0]);
+        for frame in batch.iter() {
+            assert_eq!(frame.get_accel().z, 0.0);
+            assert_eq!(frame.get_temperature(), 36.53);
+        }
+    }
+
+    // Passes every transaction to the mock, except the one the countdown ends on, which fails like a glitch on the bus.
+    // A countdown of 0 never fails
+    struct GlitchingBus<'a> {
+        mock: &'a RefCell<MockMPU6050>,
+        countdown: &'a Cell<u32>
+    }
+
+    impl embedded_hal::i2c::ErrorType for GlitchingBus<'_> {
+        type Error = mock::MockError;
+    }
+
+    impl I2c for GlitchingBus<'_> {
+        fn transaction(&mut self, address: u8, operations: &mut [embedded_hal::i2c::Operation<'_>]) -> Result<(), Self::Error> {
+            let countdown: u32 = self.countdown.get();
+            self.countdown.set(countdown.saturating_sub(1));
+
+            if countdown == 1 {
+                return Err(mock::MockError(embedded_hal::i2c::ErrorKind::Bus));
+            }
+            self.mock.borrow_mut().transaction(address, operations)
+        }
+    }
+
+    #[test]
+    fn self_test_passes_and_restores_the_configuration() {
+        let bus: RefCell<MockMPU6050> = RefCell::new(MockMPU6050::new(Address::AD0Low));
+        let mut gy521: SharedGY521 = GY521::new(RefCellDevice::new(&bus), Address::AD0Low, MockDelay, clock);
+        gy521.init(MPUConfig::default().set_afs(AFullRangeScale::Sel_2g).set_gfs(GFullRangeScale::Sel_2000)).expect("mock MPU-6050");
+
+        let result: SelfTestResult = gy521.self_test().expect("mock MPU-6050");
+        assert!(result.passed(), "{result:?}");
+
+        assert_eq!(bus.borrow().register(GYRO_CONFIG_ADDR), GFullRangeScale::Sel_2000 as u8);
+        assert_eq!(bus.borrow().register(ACCELO_CONFIG_ADDR), AFullRangeScale::Sel_2g as u8);
+    }
+
+    #[test]
+    fn self_test_finds_a_failing_axis() {
+        let bus: RefCell<MockMPU6050> = RefCell::new(MockMPU6050::new(Address::AD0Low));
+        let mut gy521: SharedGY521 = GY521::new(RefCellDevice::new(&bus), Address::AD0Low, MockDelay, clock);
+        gy521.init(MPUConfig::default()).expect("mock MPU-6050");
+
+        // Accel y responds with half of the trim, gyro z not at all
+        bus.borrow_mut().set_self_test_response([1393, 696, 1393], [3275, -3275, 0]);
+        let result: SelfTestResult = gy521.self_test().expect("mock MPU-6050");
+
+        assert_eq!(result.accel_passed(), [true, false, true]);
+        assert_eq!(result.gyro_passed(), [true, true, false]);
+        assert!(!result.passed());
+        assert!((result.accel[1] + 0.5).abs() < 0.01 && (result.gyro[2] + 1.0).abs() < 0.01, "{result:?}");
+    }
+
+    #[test]
+    fn self_test_restores_the_configuration_after_an_error() {
+        let bus: RefCell<MockMPU6050> = RefCell::new(MockMPU6050::new(Address::AD0Low));
+        let countdown: Cell<u32> = Cell::new(0);
+        let mut gy521: GY521<GlitchingBus, MockDelay> = GY521::new(GlitchingBus { mock: &bus, countdown: &countdown }, Address::AD0Low, MockDelay, clock);
+        gy521.init(MPUConfig::default()).expect("mock MPU-6050");
+
+        // Reading the configuration, switching the range, 50 samples, enabling the self-test and 10 samples with it
+        countdown.set(1 + 1 + SELF_TEST_SAMPLES as u32 + 1 + 10);
+        assert!(matches!(gy521.self_test(), Err(GY521Error::I2C(_))));
+        assert_eq!(bus.borrow().register(GYRO_CONFIG_ADDR), GFullRangeScale::Sel_1000 as u8);
+        assert_eq!(bus.borrow().register(ACCELO_CONFIG_ADDR), AFullRangeScale::Sel_8g as u8);
+
+        // The configuration couldn't be read, nothing was changed
+        countdown.set(1);
+        assert!(matches!(gy521.self_test(), Err(GY521Error::I2C(_))));
+        assert_eq!(bus.borrow().register(GYRO_CONFIG_ADDR), GFullRangeScale::Sel_1000 as u8);
+
+        // The bus works again
+        assert!(gy521.self_test().expect("mock MPU-6050").passed());
+    }
+
+    #[test]
+    fn averages_need_samples() {
+        let mut mock: MockMPU6050 = MockMPU6050::new(Address::AD0Low);
+        let mut gy521: GY521<&mut MockMPU6050, MockDelay> = gy521(&mut mock);
+        gy521.init(MPUConfig::default()).expect("mock MPU-6050");
+
+        assert!(matches!(gy521.calibrate(0), Err(GY521Error::NoSamples)));
+        assert!(matches!(gy521.measure_orientation(&mut AccelCalibration::new(), Orientation::ZUp, 0), Err(GY521Error::NoSamples)));
+        assert!(matches!(gy521.measure_gyro_temperature(&mut TemperatureSweep::new(35.0), 0), Err(GY521Error::NoSamples)));
+        assert_eq!(gy521.calibration_offsets(), &CalibrationOffsets::default());
+    }
+
+    #[test]
+    fn calibrate_keeps_the_six_position_scale() {
+        let mut mock: MockMPU6050 = MockMPU6050::new(Address::AD0Low);
+        mock.set_accel([100, -50, 4000 + 30]);
+
+        // z responds with 4000 LSB to 1g and sees a little of x, the six position calibration corrected that
+        let mut gy521: GY521<&mut MockMPU6050, MockDelay> = gy521(&mut mock);
+        gy521.init(MPUConfig::default()).expect("mock MPU-6050");
+        gy521.set_calibration_offsets(CalibrationOffsets {
+            accel_matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [-0.01, 0.0, 4096.0 / 4000.0]],
+            ..CalibrationOffsets::default()
+        });
+        gy521.calibrate(10).expect("mock MPU-6050");
+
+        let frame: DataFrame = gy521.read().expect("mock MPU-6050");
+        let accel: &AccelometerData = frame.get_accel();
+        assert!(accel.x.abs() < 1e-5 && accel.y.abs() < 1e-5 && (accel.z - 1.0).abs() < 1e-5, "{accel:?}");
+        assert_eq!(gy521.calibration_offsets().accel_matrix()[2][2], 4096.0 / 4000.0);
+    }
+}
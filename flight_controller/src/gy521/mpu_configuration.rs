@@ -0,0 +1,235 @@
+use super::sensor_data::ScalingFactor;
+
+// https://en.wikipedia.org/wiki/Low-pass_filter
+#[repr(u8)]
+#[allow(non_camel_case_types, dead_code)]
+#[derive(Clone, Copy)]
+pub enum Dlpf {
+    Hz_256 = 0,
+    Hz_188 = 1,
+    Hz_98  = 2,
+    Hz_42  = 3,
+    Hz_20  = 4,
+    Hz_10  = 5,
+    Hz_5   = 6
+}
+
+impl Dlpf {
+    // Decodes the DLPF_CFG bits of the CONFIG register (0x1A)
+    pub(crate) fn from_register(register: u8) -> Option<Self> {
+        match register & 7 {
+            0 => Some(Dlpf::Hz_256),
+            1 => Some(Dlpf::Hz_188),
+            2 => Some(Dlpf::Hz_98),
+            3 => Some(Dlpf::Hz_42),
+            4 => Some(Dlpf::Hz_20),
+            5 => Some(Dlpf::Hz_10),
+            6 => Some(Dlpf::Hz_5),
+            _ => None
+        }
+    }
+
+    // Delay of the filter in µs
+    pub fn get_delay(&self) -> u32 {
+        match self {
+            Dlpf::Hz_256 => 0,
+            Dlpf::Hz_188 => 2000,
+            Dlpf::Hz_98  => 3000,
+            Dlpf::Hz_42  => 4900,
+            Dlpf::Hz_20  => 8500,
+            Dlpf::Hz_10  => 13800,
+            Dlpf::Hz_5   => 19000
+        }
+    }
+
+    // Gyroscope output rate in Hz, the sample rate is derived from it
+    pub fn get_output_rate(&self) -> u32 {
+        match self {
+            Dlpf::Hz_256 => 8000,
+            _            => 1000
+        }
+    }
+}
+
+#[repr(u8)]
+#[allow(non_camel_case_types, dead_code)]
+#[derive(Clone, Copy)]
+pub enum GFullRangeScale {
+    Sel_250  = 0 << 3,
+    Sel_500  = 1 << 3,
+    Sel_1000 = 2 << 3,
+    Sel_2000 = 3 << 3
+}
+
+#[repr(u8)]
+#[allow(non_camel_case_types, dead_code)]
+#[derive(Clone, Copy)]
+pub enum AFullRangeScale {
+    Sel_2g  = 0 << 3,
+    Sel_4g  = 1 << 3,
+    Sel_8g  = 2 << 3,
+    Sel_16g = 3 << 3
+}
+
+// CLKSEL bits of PWR_MGMT_1 (0x6B). The datasheet recommends a gyro PLL, it is much more stable than the
+// internal oscillator
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClockSource {
+    Internal8MHz     = 0,
+    PllGyroX         = 1,
+    PllGyroY         = 2,
+    PllGyroZ         = 3,
+    PllExternal32kHz = 4,
+    PllExternal19MHz = 5,
+    Stopped          = 7
+}
+
+// LP_WAKE_CTRL bits of PWR_MGMT_2 (0x6C), how often the chip wakes up in cycle mode
+#[repr(u8)]
+#[allow(non_camel_case_types, dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WakeFrequency {
+    Hz_1_25 = 0 << 6,
+    Hz_5    = 1 << 6,
+    Hz_20   = 2 << 6,
+    Hz_40   = 3 << 6
+}
+
+// Normal: all sensors run continuously. Sleep: nothing is sampled, the registers stay accessible.
+// Cycle: the chip sleeps and wakes up to take a single accelerometer sample, the gyro isn't usable in this mode.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerMode {
+    Normal,
+    Sleep,
+    Cycle(WakeFrequency)
+}
+
+// Bits of PWR_MGMT_1
+const SLEEP: u8 = 1 << 6;
+const CYCLE: u8 = 1 << 5;
+
+impl PowerMode {
+    // SLEEP and CYCLE bits of PWR_MGMT_1
+    pub(crate) fn pwr_mgmt_1(&self) -> u8 {
+        match self {
+            PowerMode::Normal   => 0,
+            PowerMode::Sleep    => SLEEP,
+            PowerMode::Cycle(_) => CYCLE
+        }
+    }
+
+    // LP_WAKE_CTRL bits of PWR_MGMT_2
+    pub(crate) fn pwr_mgmt_2(&self) -> u8 {
+        match self {
+            PowerMode::Cycle(frequency) => *frequency as u8,
+            _ => 0
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Config {
+    pub(crate) dlpf: Dlpf,        
+    pub(crate) a_fs: AFullRangeScale,
+    pub(crate) g_fs: GFullRangeScale,
+    pub(crate) sample_rate_divider: u8,
+    pub(crate) clock_source: ClockSource,
+    pub(crate) power_mode: PowerMode
+}
+
+impl Config {
+    pub (crate) fn get_scaling_factor(&self) -> ScalingFactor {
+        let a: f32 = match self.a_fs {
+            AFullRangeScale::Sel_2g => 16384.0,
+            AFullRangeScale::Sel_4g => 8192.0,
+            AFullRangeScale::Sel_8g => 4096.0,
+            AFullRangeScale::Sel_16g => 2048.0
+        };
+
+        let g: f32 = match self.g_fs {
+            GFullRangeScale::Sel_250 => 131.0,
+            GFullRangeScale::Sel_500 => 65.5,
+            GFullRangeScale::Sel_1000 => 32.8,
+            GFullRangeScale::Sel_2000 => 16.4
+        };
+
+        ScalingFactor {a, g}
+    }
+
+    pub fn set_afs(mut self, afs: AFullRangeScale) -> Self {
+        self.a_fs = afs;
+        self
+    }
+
+    pub fn set_gfs(mut self, gfs: GFullRangeScale) -> Self {
+        self.g_fs = gfs;
+        self
+    }
+
+    pub fn set_dlpf(mut  self, dlpf: Dlpf) -> Self {
+        self.dlpf = dlpf;
+        self
+    }
+
+    // Sample Rate = Gyroscope Output Rate / (1 + divider), the output rate is 8 kHz without DLPF and 1 kHz with it
+    pub fn set_sample_rate_divider(mut self, divider: u8) -> Self {
+        self.sample_rate_divider = divider;
+        self
+    }
+
+    pub fn set_clock_source(mut self, clock_source: ClockSource) -> Self {
+        self.clock_source = clock_source;
+        self
+    }
+
+    pub fn set_power_mode(mut self, power_mode: PowerMode) -> Self {
+        self.power_mode = power_mode;
+        self
+    }
+
+    // Full scale of the accelerometer in g
+    pub fn get_accel_range(&self) -> f32 {
+        match self.a_fs {
+            AFullRangeScale::Sel_2g => 2.0,
+            AFullRangeScale::Sel_4g => 4.0,
+            AFullRangeScale::Sel_8g => 8.0,
+            AFullRangeScale::Sel_16g => 16.0
+        }
+    }
+
+    // Full scale of the gyroscope in °/s
+    pub fn get_gyro_range(&self) -> f32 {
+        match self.g_fs {
+            GFullRangeScale::Sel_250 => 250.0,
+            GFullRangeScale::Sel_500 => 500.0,
+            GFullRangeScale::Sel_1000 => 1000.0,
+            GFullRangeScale::Sel_2000 => 2000.0
+        }
+    }
+
+    // Rate of the sensor registers, the FIFO and the data ready interrupt in Hz
+    pub fn get_sample_rate(&self) -> u32 {
+        self.dlpf.get_output_rate() / (1 + self.sample_rate_divider as u32)
+    }
+
+    // PWR_MGMT_1 without the reset bit
+    pub(crate) fn pwr_mgmt_1(&self) -> u8 {
+        self.power_mode.pwr_mgmt_1() | self.clock_source as u8
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self { 
+            dlpf: Dlpf::Hz_20, 
+            a_fs: AFullRangeScale::Sel_8g, 
+            g_fs: GFullRangeScale::Sel_1000, 
+            sample_rate_divider: 0, 
+            clock_source: ClockSource::PllGyroX,
+            power_mode: PowerMode::Normal
+        }
+    }
+}
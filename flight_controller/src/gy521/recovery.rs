@@ -0,0 +1,201 @@
+// Recovery of a hung I2C bus. If the master stops in the middle of a byte (brown-out, a glitch on SCL), the MPU-6050
+// still waits for the clocks of the byte it was sending and may hold SDA low while doing so. No transaction gets
+// through anymore until the slave is clocked out of that byte.
+//
+// `LinkMonitor` decides when to recover and paces the attempts, `clear_bus` frees SDA. Reinitializing the master
+// is specific to the HAL and left to the caller, `GY521::reinit` restores the chip afterwards.
+use embedded_hal::{
+    delay::DelayNs,
+    digital::{InputPin, OutputPin}
+};
+
+// Half of a clock period at 100 kHz in µs
+const HALF_PERIOD: u32 = 5;
+
+// The slave has at most 8 bits of a byte and the acknowledge bit left
+const CLEAR_CLOCKS: u8 = 9;
+
+// Bus clear, NXP UM10204 3.1.16: clocks SCL until the slave releases SDA, at most nine times, then sends a STOP so
+// every slave on the bus is idle again. Both lines have to be open drain outputs of the GPIOs (the I2C peripheral
+// detached) and sda has to read the level of the line. Takes at most 105 µs, nine clocks and the STOP. Returns whether
+// SDA is high afterwards
+pub fn clear_bus<SCL: OutputPin, SDA: OutputPin + InputPin, D: DelayNs>(scl: &mut SCL, sda: &mut SDA, delay: &mut D) -> bool {
+    let mut clock = || -> Result<bool, ()> {
+        sda.set_high().map_err(|_| ())?;
+
+        for _ in 0..CLEAR_CLOCKS {
+            if sda.is_high().map_err(|_| ())? {
+                break;
+            }
+            scl.set_low().map_err(|_| ())?;
+            delay.delay_us(HALF_PERIOD);
+            scl.set_high().map_err(|_| ())?;
+            delay.delay_us(HALF_PERIOD);
+        }
+
+        // STOP: SDA rises while SCL is high
+        scl.set_low().map_err(|_| ())?;
+        sda.set_low().map_err(|_| ())?;
+        delay.delay_us(HALF_PERIOD);
+        scl.set_high().map_err(|_| ())?;
+        delay.delay_us(HALF_PERIOD);
+        sda.set_high().map_err(|_| ())?;
+        delay.delay_us(HALF_PERIOD);
+
+        sda.is_high().map_err(|_| ())
+    };
+
+    clock().unwrap_or(false)
+}
+
+// Watches the results of the transactions with one sensor. After error_limit failures in a row the link counts as
+// lost, then a recovery is due at most once per retry interval. One attempt (bus clear, master reset, `reinit`)
+// takes a few milliseconds, so the control loop keeps running, on the other IMU or the last estimate.
+#[derive(Debug)]
+pub struct LinkMonitor {
+    error_limit: u32,
+    retry_interval: u64,
+    errors: u32,
+    next_attempt: u64,
+    recoveries: u32
+}
+
+impl LinkMonitor {
+    pub const fn new() -> Self {
+        Self { error_limit: 5, retry_interval: 100_000, errors: 0, next_attempt: 0, recoveries: 0 }
+    }
+
+    // Failed transactions in a row after which the link is lost. A single error (e.g. a NACK while the chip is busy)
+    // doesn't need a recovery
+    pub fn set_error_limit(mut self, error_limit: u32) -> Self {
+        assert!(error_limit > 0, "The error limit must be positive");
+        self.error_limit = error_limit;
+        self
+    }
+
+    // Least time between two recoveries in µs
+    pub fn set_retry_interval(mut self, retry_interval: u64) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    // Has to see the result of every transaction (e.g. of `GY521::read`)
+    pub fn report<T, E>(&mut self, result: &Result<T, E>) {
+        self.errors = if result.is_ok() { 0 } else { self.errors.saturating_add(1) };
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.errors >= self.error_limit
+    }
+
+    // now in µs since boot. True if a recovery should be run now, the next one is only due after the retry interval
+    pub fn should_recover(&mut self, now: u64) -> bool {
+        if !self.is_lost() || now < self.next_attempt {
+            return false;
+        }
+        self.next_attempt = now.saturating_add(self.retry_interval);
+        true
+    }
+
+    // Result of the recovery, after a successful one the link is good until the next errors
+    pub fn recovered(&mut self, success: bool) {
+        if success {
+            self.errors = 0;
+            self.recoveries += 1;
+        }
+    }
+
+    // Failed transactions in a row
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+
+    // Successful recoveries since boot
+    pub fn recoveries(&self) -> u32 {
+        self.recoveries
+    }
+}
+
+impl Default for LinkMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Run on the host by `cargo test` in the simulator, against the bus lines of the mock MPU-6050
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use embedded_hal::i2c::{ErrorKind, I2c};
+    use crate::gy521::{mock::{MockDelay, MockError, MockMPU6050, MockScl, MockSda}, Address};
+
+    // Bus clear on a chip which holds SDA for the given number of clocks. Returns the result and the chip afterwards
+    fn clear(stuck: u32) -> (bool, MockMPU6050) {
+        let bus: RefCell<MockMPU6050> = RefCell::new(MockMPU6050::new(Address::AD0Low));
+        bus.borrow_mut().set_sda_stuck(stuck);
+
+        let released: bool = clear_bus(&mut MockScl(&bus), &mut MockSda(&bus), &mut MockDelay);
+        (released, bus.into_inner())
+    }
+
+    #[test]
+    fn clear_bus_clocks_until_sda_is_released() {
+        for stuck in 0..9 {
+            let (released, mut mock) = clear(stuck);
+            assert!(released, "{stuck} clocks");
+
+            // One clock per stuck bit, the last rising edge belongs to the STOP
+            assert_eq!(mock.scl_clocks(), stuck + 1, "{stuck} clocks");
+            assert_eq!(mock.stops(), 1, "{stuck} clocks");
+
+            // The next transaction gets through
+            assert_eq!(mock.write(Address::AD0Low as u8, &[0x6B, 0]), Ok(()));
+            assert_eq!(mock.transactions(), 1);
+        }
+    }
+
+    #[test]
+    fn clear_bus_gives_up_after_nine_clocks() {
+        // The rising edge of the STOP is the tenth clock the chip sees
+        for stuck in [11, 100, u32::MAX] {
+            let (released, mut mock) = clear(stuck);
+            assert!(!released, "{stuck} clocks");
+            assert_eq!(mock.scl_clocks(), CLEAR_CLOCKS as u32 + 1, "{stuck} clocks");
+            assert_eq!(mock.stops(), 0, "{stuck} clocks");
+
+            assert_eq!(mock.write(Address::AD0Low as u8, &[0x6B, 0]), Err(MockError(ErrorKind::Bus)));
+            assert_eq!(mock.transactions(), 0);
+        }
+    }
+
+    #[test]
+    fn link_monitor_waits_for_the_error_limit_and_the_retry_interval() {
+        let mut link: LinkMonitor = LinkMonitor::new().set_error_limit(3).set_retry_interval(1000);
+        let (ok, failed): (Result<(), ()>, Result<(), ()>) = (Ok(()), Err(()));
+
+        // A success in between starts the count again
+        link.report(&failed);
+        link.report(&failed);
+        link.report(&ok);
+        link.report(&failed);
+        link.report(&failed);
+        assert_eq!(link.errors(), 2);
+        assert!(!link.is_lost() && !link.should_recover(0));
+
+        link.report(&failed);
+        assert!(link.is_lost());
+        assert!(link.should_recover(0));
+
+        // Failed attempts are repeated after the retry interval
+        link.recovered(false);
+        assert!(link.is_lost() && link.recoveries() == 0);
+        assert!(!link.should_recover(999));
+        assert!(link.should_recover(1000));
+
+        // A successful one resets the errors
+        link.recovered(true);
+        assert_eq!((link.errors(), link.recoveries()), (0, 1));
+        assert!(!link.is_lost() && !link.should_recover(5000));
+    }
+}
@@ -0,0 +1,69 @@
+// Datasheet self-test, MPU-6000/MPU-6050 Register Map 4.1: the self-test response (output with self-test enabled
+// minus output without) has to be within ±14% of the factory trim, which is derived from the SELF_TEST registers.
+use libm::powf;
+
+// Allowed deviation of the self-test response from the factory trim
+pub const SELF_TEST_TOLERANCE: f32 = 0.14;
+
+// Deviation of the self-test response from the factory trim per axis (x, y, z), 0.1 = 10%
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SelfTestResult {
+    pub accel: [f32; 3],
+    pub gyro: [f32; 3]
+}
+
+impl SelfTestResult {
+    pub fn accel_passed(&self) -> [bool; 3] {
+        self.accel.map(Self::within_tolerance)
+    }
+
+    pub fn gyro_passed(&self) -> [bool; 3] {
+        self.gyro.map(Self::within_tolerance)
+    }
+
+    pub fn passed(&self) -> bool {
+        self.accel_passed().iter().chain(self.gyro_passed().iter()).all(|&passed| passed)
+    }
+
+    // NaN (no factory trim) fails
+    fn within_tolerance(deviation: f32) -> bool {
+        (-SELF_TEST_TOLERANCE..=SELF_TEST_TOLERANCE).contains(&deviation)
+    }
+}
+
+// registers are SELF_TEST_X, _Y, _Z and _A (0x0D - 0x10), returns the 5 bit test values (accel, gyro)
+pub(crate) fn test_values(registers: [u8; 4]) -> ([u8; 3], [u8; 3]) {
+    let accel: [u8; 3] = [
+        (registers[0] >> 3) & 0b11100 | (registers[3] >> 4) & 0b11,
+        (registers[1] >> 3) & 0b11100 | (registers[3] >> 2) & 0b11,
+        (registers[2] >> 3) & 0b11100 | registers[3] & 0b11
+    ];
+    let gyro: [u8; 3] = [registers[0] & 0b11111, registers[1] & 0b11111, registers[2] & 0b11111];
+
+    (accel, gyro)
+}
+
+// Factory trim in LSB of the ±8g range
+pub(crate) fn accel_factory_trim(test: u8) -> f32 {
+    if test == 0 {
+        return 0.0;
+    }
+    4096.0 * 0.34 * powf(0.92 / 0.34, (test as f32 - 1.0) / 30.0)
+}
+
+// Factory trim in LSB of the ±250°/s range, the y axis is negative
+pub(crate) fn gyro_factory_trim(test: u8, axis: usize) -> f32 {
+    if test == 0 {
+        return 0.0;
+    }
+    let trim: f32 = 25.0 * 131.0 * powf(1.046, test as f32 - 1.0);
+    if axis == 1 { -trim } else { trim }
+}
+
+// Without a factory trim (0) the deviation is NaN and the axis fails
+pub(crate) fn deviation(response: f32, factory_trim: f32) -> f32 {
+    if factory_trim == 0.0 {
+        return f32::NAN;
+    }
+    (response - factory_trim) / factory_trim
+}
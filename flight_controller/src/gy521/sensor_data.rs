@@ -0,0 +1,228 @@
+use super::{BoardAlignment, GyroTemperatureModel};
+
+// Corrections of the raw values (LSB): corrected accel = accel_matrix * (raw - accel_bias),
+// corrected gyro = raw - gyro_bias - gyro_temperature.drift(T).
+// The diagonal of accel_matrix holds the scale of each axis, the rest the cross axis misalignment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationOffsets {
+    pub(crate) accel_bias: [f32; 3],
+    pub(crate) accel_matrix: [[f32; 3]; 3],
+    pub(crate) gyro_bias: [f32; 3],
+    pub(crate) gyro_temperature: GyroTemperatureModel
+}
+
+impl CalibrationOffsets {
+    pub fn accel_bias(&self) -> [f32; 3] {
+        self.accel_bias
+    }
+
+    pub fn accel_matrix(&self) -> [[f32; 3]; 3] {
+        self.accel_matrix
+    }
+
+    pub fn gyro_bias(&self) -> [f32; 3] {
+        self.gyro_bias
+    }
+
+    pub fn gyro_temperature(&self) -> &GyroTemperatureModel {
+        &self.gyro_temperature
+    }
+
+    // Gyro bias (LSB) at temperature (°C)
+    pub fn gyro_bias_at(&self, temperature: f32) -> [f32; 3] {
+        let drift: [f32; 3] = self.gyro_temperature.drift(temperature);
+        [0, 1, 2].map(|axis| self.gyro_bias[axis] + drift[axis])
+    }
+}
+
+impl Default for CalibrationOffsets {
+    // No correction
+    fn default() -> Self {
+        Self { 
+            accel_bias: [0.0; 3], 
+            accel_matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]], 
+            gyro_bias: [0.0; 3],
+            gyro_temperature: GyroTemperatureModel::default()
+        }
+    }
+}
+
+pub (crate) struct ScalingFactor {
+    pub(crate) a: f32,
+    pub(crate) g: f32,
+}
+
+#[derive(Debug)]
+pub struct AccelometerData {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32
+}
+
+#[derive(Debug)]
+pub struct GyroscopeData {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32
+}
+
+#[derive(Debug)]
+pub struct DataFrame {
+    accel: AccelometerData,
+    gyro: GyroscopeData,
+    // Body axes whose value contains a raw reading at the end of the 16 bit range
+    accel_saturated: [bool; 3],
+    gyro_saturated: [bool; 3],
+    temperature: f32,
+    timestamp: u64
+}
+
+// Burst from ACCEL_XOUT_H (0x3B) up to GYRO_ZOUT_L (0x48): accel (6), temperature (2), gyro (6)
+pub (crate) const FRAME_SIZE: usize = 14;
+
+// Temperature in °C = TEMP_OUT / 340 + 36.53 (MPU-6050-Register-Mapping.pdf, 4.18)
+const TEMPERATURE_SENSITIVITY: f32 = 340.0;
+const TEMPERATURE_OFFSET: f32 = 36.53;
+
+impl DataFrame {
+    // timestamp is the time of the reading in µs since boot
+    // bytes is one burst of FRAME_SIZE registers, so all values belong to the same sample period.
+    // The calibration is in sensor axes, the alignment turns the calibrated values into body axes
+    pub (crate) fn new(
+        bytes: [u8; FRAME_SIZE],
+        scaling_factor: &ScalingFactor,
+        calibration: &CalibrationOffsets,
+        alignment: &BoardAlignment,
+        timestamp: u64
+    ) -> Self {
+        let accel: [f32; 3] = Self::get_sensor_data(&bytes[0..6], calibration.accel_bias);
+        let temperature: f32 = Self::get_temperature_data(&bytes[6..8]);
+        let gyro: [f32; 3] = Self::get_sensor_data(&bytes[8..14], calibration.gyro_bias_at(temperature));
+
+        let m: &[[f32; 3]; 3] = &calibration.accel_matrix;
+        let accel: [f32; 3] = [
+            m[0][0] * accel[0] + m[0][1] * accel[1] + m[0][2] * accel[2],
+            m[1][0] * accel[0] + m[1][1] * accel[1] + m[1][2] * accel[2],
+            m[2][0] * accel[0] + m[2][1] * accel[1] + m[2][2] * accel[2]
+        ];
+
+        let [a_x, a_y, a_z] = alignment.rotate(accel);
+        let [g_x, g_y, g_z] = alignment.rotate(gyro);
+        
+        Self { 
+            accel: AccelometerData { x: a_x / scaling_factor.a, y: a_y / scaling_factor.a, z: a_z / scaling_factor.a }, 
+            gyro: GyroscopeData { x: g_x / scaling_factor.g, y: g_y / scaling_factor.g, z: g_z / scaling_factor.g }, 
+            accel_saturated: alignment.rotate_flags(Self::get_saturation(&bytes[0..6])),
+            gyro_saturated: alignment.rotate_flags(Self::get_saturation(&bytes[8..14])),
+            temperature, 
+            timestamp 
+        }
+    }
+
+    // Frame of already calibrated values in g and °/s at 25 °C, for host tests of the estimators
+    #[cfg(test)]
+    pub(crate) fn from_values(accel: [f32; 3], gyro: [f32; 3], timestamp: u64) -> Self {
+        let [x, y, z] = accel;
+        let [g_x, g_y, g_z] = gyro;
+        Self {
+            accel: AccelometerData { x, y, z },
+            gyro: GyroscopeData { x: g_x, y: g_y, z: g_z },
+            accel_saturated: [false; 3],
+            gyro_saturated: [false; 3],
+            temperature: 25.0,
+            timestamp
+        }
+    }
+
+    // Frame number step at 1 kHz of a resting IMU with z at accel_z. The last bits change every frame like sensor
+    // noise, so the frames never count as frozen
+    #[cfg(test)]
+    pub(crate) fn resting(step: u64, accel_z: f32) -> Self {
+        let noise: f32 = (step % 7) as f32 * 0.001;
+        Self::from_values([noise, -noise, accel_z - noise], [noise, noise, -noise], step * 1000)
+    }
+
+    // Marks axes as saturated, like a raw reading at the end of the range would in `new`
+    #[cfg(test)]
+    pub(crate) fn set_saturated(mut self, accel: [bool; 3], gyro: [bool; 3]) -> Self {
+        self.accel_saturated = accel;
+        self.gyro_saturated = gyro;
+        self
+    }
+
+    // TEMP_OUT_H and TEMP_OUT_L in °C
+    pub (crate) fn get_temperature_data(bytes: &[u8]) -> f32 {
+        i16::from_be_bytes([bytes[0], bytes[1]]) as f32 / TEMPERATURE_SENSITIVITY + TEMPERATURE_OFFSET
+    }
+
+    // Raw values minus the bias, in LSB
+    fn get_sensor_data(bytes: &[u8], bias: [f32; 3]) -> [f32; 3] {
+        let x: f32 = i16::from_be_bytes([bytes[0], bytes[1]]) as f32 - bias[0];
+        let y: f32 = i16::from_be_bytes([bytes[2], bytes[3]]) as f32 - bias[1];
+        let z: f32 = i16::from_be_bytes([bytes[4], bytes[5]]) as f32 - bias[2];
+
+        [x, y, z]
+    }
+
+    // Raw values at either end of the 16 bit range (±32767 and beyond), where the sensor clamps
+    fn get_saturation(bytes: &[u8]) -> [bool; 3] {
+        [0, 2, 4].map(|i| i16::from_be_bytes([bytes[i], bytes[i + 1]]).unsigned_abs() >= i16::MAX as u16)
+    }
+
+    // Copy of the frame with bias (°/s) subtracted from the gyro, e.g. the estimate of `GyroBiasEstimator`
+    pub fn remove_gyro_bias(&self, bias: [f32; 3]) -> Self {
+        let AccelometerData { x, y, z } = self.accel;
+
+        Self {
+            accel: AccelometerData { x, y, z },
+            gyro: GyroscopeData { x: self.gyro.x - bias[0], y: self.gyro.y - bias[1], z: self.gyro.z - bias[2] },
+            accel_saturated: self.accel_saturated,
+            gyro_saturated: self.gyro_saturated,
+            temperature: self.temperature,
+            timestamp: self.timestamp
+        }
+    }
+
+    // Mean of two frames of the same instant, e.g. of two IMUs. The timestamp is the one of self, an axis is saturated
+    // if it is in either frame
+    pub fn average(&self, other: &DataFrame) -> Self {
+        let mean = |a: f32, b: f32| (a + b) / 2.0;
+        let either = |a: [bool; 3], b: [bool; 3]| [0, 1, 2].map(|axis| a[axis] || b[axis]);
+
+        Self {
+            accel: AccelometerData { x: mean(self.accel.x, other.accel.x), y: mean(self.accel.y, other.accel.y), z: mean(self.accel.z, other.accel.z) },
+            gyro: GyroscopeData { x: mean(self.gyro.x, other.gyro.x), y: mean(self.gyro.y, other.gyro.y), z: mean(self.gyro.z, other.gyro.z) },
+            accel_saturated: either(self.accel_saturated, other.accel_saturated),
+            gyro_saturated: either(self.gyro_saturated, other.gyro_saturated),
+            temperature: mean(self.temperature, other.temperature),
+            timestamp: self.timestamp
+        }
+    }
+
+    pub fn get_accel(&self) -> &AccelometerData {
+        &self.accel
+    }
+
+    pub fn get_gyro(&self) -> &GyroscopeData {
+        &self.gyro
+    }
+
+    // Per body axis, true if the raw reading hit the end of its range, so the real value is unknown
+    pub fn get_accel_saturated(&self) -> [bool; 3] {
+        self.accel_saturated
+    }
+
+    pub fn get_gyro_saturated(&self) -> [bool; 3] {
+        self.gyro_saturated
+    }
+
+    // Die temperature in °C
+    pub fn get_temperature(&self) -> f32 {
+        self.temperature
+    }
+
+    // Time of the reading in µs since boot
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
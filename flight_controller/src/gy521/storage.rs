@@ -0,0 +1,235 @@
+// Persistent format of `CalibrationOffsets`, stored in its own flash partition (see partitions.csv).
+//
+// Layout, little endian:
+//   0  magic "IMUC"
+//   4  version (u16)
+//   6  payload length (u16)
+//   8  payload: accel_bias (3 f32), accel_matrix (9 f32, row by row), gyro_bias (3 f32),
+//      gyro temperature model: reference (f32), linear (3 f32), quadratic (3 f32)
+//  96  CRC-32 (IEEE) of everything before
+use embedded_storage::{ReadStorage, Storage};
+
+use super::{CalibrationOffsets, GyroTemperatureModel};
+
+pub use error_handling::StorageError;
+
+const MAGIC: [u8; 4] = *b"IMUC";
+// 2: gyro temperature model
+const VERSION: u16 = 2;
+
+const HEADER_SIZE: usize = 8;
+const PAYLOAD_VALUES: usize = 22;
+const PAYLOAD_SIZE: usize = PAYLOAD_VALUES * 4;
+
+// Bytes one calibration takes in flash
+pub const CALIBRATION_SIZE: usize = HEADER_SIZE + PAYLOAD_SIZE + 4;
+
+mod error_handling {
+    use core::fmt::Debug;
+
+    // E is the error of the flash
+    pub enum StorageError<E> {
+        Storage(E),
+        // Erased flash, nothing was stored yet
+        Missing,
+        // Written by a firmware with another format
+        Version(u16),
+        // Wrong magic, length or CRC
+        Corrupt,
+        // A value is NaN or infinite, e.g. calculated from broken measurements. It isn't stored or loaded
+        InvalidValue
+    }
+
+    impl<E: Debug> Debug for StorageError<E> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match self {
+                StorageError::Storage(err) => write!(f, "Accessing the flash failed with: {err:?}")?,
+                StorageError::Missing => write!(f, "No calibration is stored")?,
+                StorageError::Version(version) => write!(f, "The stored calibration has version {version}, expected {}", super::VERSION)?,
+                StorageError::Corrupt => write!(f, "The stored calibration is corrupt")?,
+                StorageError::InvalidValue => write!(f, "The calibration contains a value which is NaN or infinite")?
+            }
+            Ok(())
+        }
+    }
+}
+
+impl CalibrationOffsets {
+    pub fn to_bytes(&self) -> [u8; CALIBRATION_SIZE] {
+        let mut bytes: [u8; CALIBRATION_SIZE] = [0; CALIBRATION_SIZE];
+
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        bytes[6..8].copy_from_slice(&(PAYLOAD_SIZE as u16).to_le_bytes());
+
+        for (chunk, value) in bytes[HEADER_SIZE..HEADER_SIZE + PAYLOAD_SIZE].chunks_exact_mut(4).zip(self.values()) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+
+        let crc: u32 = crc32(&bytes[..HEADER_SIZE + PAYLOAD_SIZE]);
+        bytes[HEADER_SIZE + PAYLOAD_SIZE..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes<E>(bytes: &[u8; CALIBRATION_SIZE]) -> Result<Self, StorageError<E>> {
+        if bytes.iter().all(|&byte| byte == 0xFF) {
+            return Err(StorageError::Missing);
+        }
+        if bytes[0..4] != MAGIC {
+            return Err(StorageError::Corrupt);
+        }
+
+        let version: u16 = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != VERSION {
+            return Err(StorageError::Version(version));
+        }
+
+        let length: u16 = u16::from_le_bytes([bytes[6], bytes[7]]);
+        let crc_bytes: &[u8] = &bytes[HEADER_SIZE + PAYLOAD_SIZE..];
+        let crc: u32 = u32::from_le_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]]);
+        if length as usize != PAYLOAD_SIZE || crc != crc32(&bytes[..HEADER_SIZE + PAYLOAD_SIZE]) {
+            return Err(StorageError::Corrupt);
+        }
+
+        let mut values: [f32; PAYLOAD_VALUES] = [0.0; PAYLOAD_VALUES];
+        for (value, chunk) in values.iter_mut().zip(bytes[HEADER_SIZE..HEADER_SIZE + PAYLOAD_SIZE].chunks_exact(4)) {
+            *value = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+
+        if values.iter().any(|value| !value.is_finite()) {
+            return Err(StorageError::InvalidValue);
+        }
+
+        Ok(Self {
+            accel_bias: [values[0], values[1], values[2]],
+            accel_matrix: [
+                [values[3], values[4], values[5]],
+                [values[6], values[7], values[8]],
+                [values[9], values[10], values[11]]
+            ],
+            gyro_bias: [values[12], values[13], values[14]],
+            gyro_temperature: GyroTemperatureModel {
+                reference: values[15],
+                linear: [values[16], values[17], values[18]],
+                quadratic: [values[19], values[20], values[21]]
+            }
+        })
+    }
+
+    // All values in the order of the payload
+    fn values(&self) -> impl Iterator<Item = &f32> {
+        let model: &GyroTemperatureModel = &self.gyro_temperature;
+
+        self.accel_bias.iter()
+            .chain(self.accel_matrix.iter().flatten())
+            .chain(self.gyro_bias.iter())
+            .chain(core::iter::once(&model.reference))
+            .chain(model.linear.iter())
+            .chain(model.quadratic.iter())
+    }
+}
+
+// offset is the start of the calibration partition
+pub fn load_calibration<S: ReadStorage>(storage: &mut S, offset: u32) -> Result<CalibrationOffsets, StorageError<S::Error>> {
+    let mut bytes: [u8; CALIBRATION_SIZE] = [0; CALIBRATION_SIZE];
+    storage.read(offset, &mut bytes).map_err(StorageError::Storage)?;
+
+    CalibrationOffsets::from_bytes(&bytes)
+}
+
+// A calibration with a NaN or infinite value is refused, it would fail every load
+pub fn store_calibration<S: Storage>(storage: &mut S, offset: u32, calibration: &CalibrationOffsets) -> Result<(), StorageError<S::Error>> {
+    if calibration.values().any(|value| !value.is_finite()) {
+        return Err(StorageError::InvalidValue);
+    }
+    storage.write(offset, &calibration.to_bytes()).map_err(StorageError::Storage)
+}
+
+// CRC-32/ISO-HDLC, the one of zlib and Ethernet
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+// Run on the host by `cargo test` in the simulator, against the flash in RAM of `mock`
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gy521::mock::{MockFlash, OutOfBounds};
+
+    const OFFSET: u32 = 100;
+    type Flash = MockFlash<256>;
+
+    fn calibration() -> CalibrationOffsets {
+        CalibrationOffsets {
+            accel_bias: [12.5, -3.0, 40.25],
+            accel_matrix: [[1.02, 0.01, -0.003], [0.0, 0.98, 0.02], [-0.01, 0.005, 1.01]],
+            gyro_bias: [-20.0, 7.5, 3.0],
+            gyro_temperature: GyroTemperatureModel { reference: 35.0, linear: [0.5, -0.25, 0.1], quadratic: [0.01, 0.0, -0.02] }
+        }
+    }
+
+    // Flash with the calibration stored at OFFSET, after modify changed its bytes
+    fn stored(modify: impl FnOnce(&mut [u8])) -> Flash {
+        let mut flash: Flash = MockFlash::new();
+        store_calibration(&mut flash, OFFSET, &calibration()).expect("in bounds");
+        modify(&mut flash.bytes_mut()[OFFSET as usize..OFFSET as usize + CALIBRATION_SIZE]);
+        flash
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(load_calibration(&mut stored(|_| {}), OFFSET).ok(), Some(calibration()));
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn erased_flash_is_missing() {
+        assert!(matches!(load_calibration(&mut Flash::new(), OFFSET), Err(StorageError::Missing)));
+    }
+
+    #[test]
+    fn damaged_bytes_are_corrupt() {
+        // A flipped bit in the payload, the CRC or the magic
+        for index in [HEADER_SIZE + 5, CALIBRATION_SIZE - 1, 0] {
+            let mut flash: Flash = stored(|bytes| bytes[index] ^= 0x10);
+            assert!(matches!(load_calibration(&mut flash, OFFSET), Err(StorageError::Corrupt)), "byte {index}");
+        }
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut flash: Flash = stored(|bytes| bytes[4..6].copy_from_slice(&1u16.to_le_bytes()));
+        assert!(matches!(load_calibration(&mut flash, OFFSET), Err(StorageError::Version(1))));
+    }
+
+    #[test]
+    fn non_finite_values_are_neither_stored_nor_loaded() {
+        let mut broken: CalibrationOffsets = calibration();
+        broken.accel_bias[2] = f32::NAN;
+        let mut flash: Flash = MockFlash::new();
+        assert!(matches!(store_calibration(&mut flash, OFFSET, &broken), Err(StorageError::InvalidValue)));
+        assert!(matches!(load_calibration(&mut flash, OFFSET), Err(StorageError::Missing)));
+
+        // E.g. written by a firmware without the check, the CRC is valid
+        broken.accel_bias[2] = 0.0;
+        broken.gyro_temperature.quadratic[1] = f32::INFINITY;
+        let bytes: [u8; CALIBRATION_SIZE] = broken.to_bytes();
+        flash.bytes_mut()[OFFSET as usize..OFFSET as usize + CALIBRATION_SIZE].copy_from_slice(&bytes);
+        assert!(matches!(load_calibration(&mut flash, OFFSET), Err(StorageError::InvalidValue)));
+    }
+
+    #[test]
+    fn flash_errors_are_passed_on() {
+        assert!(matches!(load_calibration(&mut Flash::new(), 200), Err(StorageError::Storage(OutOfBounds))));
+        assert!(matches!(store_calibration(&mut Flash::new(), 200, &calibration()), Err(StorageError::Storage(OutOfBounds))));
+    }
+}
@@ -0,0 +1,150 @@
+// Temperature compensation of the gyro bias. The bias of the MPU-6050 follows the die temperature while the board
+// warms up, per axis it is modelled as a polynomial of up to second order around a reference temperature:
+//   bias(T) = gyro_bias + linear * (T - reference) + quadratic * (T - reference)²
+// gyro_bias stays in `CalibrationOffsets`, so `GY521::calibrate` can shift the curve without losing its shape.
+use libm::fabs;
+
+// Below this temperature span a sweep can't tell the drift from noise
+pub const MIN_TEMPERATURE_SPAN: f32 = 5.0;
+
+// Drift of the gyro bias relative to the reference temperature, in LSB of the gyro range it was fitted with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GyroTemperatureModel {
+    pub(crate) reference: f32,
+    pub(crate) linear: [f32; 3],
+    pub(crate) quadratic: [f32; 3]
+}
+
+impl GyroTemperatureModel {
+    // LSB per axis at temperature (°C)
+    pub fn drift(&self, temperature: f32) -> [f32; 3] {
+        let x: f32 = temperature - self.reference;
+        [0, 1, 2].map(|axis| self.linear[axis] * x + self.quadratic[axis] * x * x)
+    }
+
+    pub fn reference(&self) -> f32 {
+        self.reference
+    }
+}
+
+impl Default for GyroTemperatureModel {
+    // No drift
+    fn default() -> Self {
+        Self { reference: 25.0, linear: [0.0; 3], quadratic: [0.0; 3] }
+    }
+}
+
+// Collects gyro bias over temperature while the board rests and warms up. Only the sums of the least squares
+// problem are kept, so the sweep can run for any time without memory.
+#[derive(Debug, Clone)]
+pub struct TemperatureSweep {
+    reference: f32,
+    min_temperature: f32,
+    max_temperature: f32,
+    x_sums: [f64; 5],        // Σ xᵏ for k = 0..=4, x = T - reference
+    xy_sums: [[f64; 3]; 3]   // [axis][k]: Σ bias · xᵏ for k = 0..=2
+}
+
+impl TemperatureSweep {
+    // reference should lie in the middle of the sweep, it keeps the sums well conditioned
+    pub fn new(reference: f32) -> Self {
+        Self {
+            reference,
+            min_temperature: f32::INFINITY,
+            max_temperature: f32::NEG_INFINITY,
+            x_sums: [0.0; 5],
+            xy_sums: [[0.0; 3]; 3]
+        }
+    }
+
+    // bias is the raw gyro output at rest (LSB), temperature in °C
+    pub fn add(&mut self, temperature: f32, bias: [f32; 3]) {
+        self.min_temperature = self.min_temperature.min(temperature);
+        self.max_temperature = self.max_temperature.max(temperature);
+
+        let x: f64 = (temperature - self.reference) as f64;
+        let mut power: f64 = 1.0;
+
+        for k in 0..5 {
+            self.x_sums[k] += power;
+            if k < 3 {
+                for (sums, value) in self.xy_sums.iter_mut().zip(bias) {
+                    sums[k] += value as f64 * power;
+                }
+            }
+            power *= x;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.x_sums[0] as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Difference between the highest and lowest temperature in °C
+    pub fn span(&self) -> f32 {
+        if self.is_empty() { 0.0 } else { self.max_temperature - self.min_temperature }
+    }
+
+    // Least squares fit of order 0 to 2. Returns the bias at the reference temperature (LSB) and the drift model.
+    // None if there are too few points or, for order 1 and 2, the span is below MIN_TEMPERATURE_SPAN
+    pub fn fit(&self, order: usize) -> Option<([f32; 3], GyroTemperatureModel)> {
+        if order > 2 || self.len() <= order || (order > 0 && self.span() < MIN_TEMPERATURE_SPAN) {
+            return None;
+        }
+
+        // Normal equations, the rows above the order are the identity and keep their coefficient 0
+        let mut matrix: [[f64; 3]; 3] = [[0.0; 3]; 3];
+        for (row, values) in matrix.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = if row <= order && column <= order { self.x_sums[row + column] } else if row == column { 1.0 } else { 0.0 };
+            }
+        }
+
+        let mut bias: [f32; 3] = [0.0; 3];
+        let mut model: GyroTemperatureModel = GyroTemperatureModel { reference: self.reference, ..GyroTemperatureModel::default() };
+
+        for (axis, sums) in self.xy_sums.iter().enumerate() {
+            let mut rhs: [f64; 3] = [0.0; 3];
+            rhs[..=order].copy_from_slice(&sums[..=order]);
+
+            let coefficients: [f64; 3] = solve(matrix, rhs)?;
+            bias[axis] = coefficients[0] as f32;
+            model.linear[axis] = coefficients[1] as f32;
+            model.quadratic[axis] = coefficients[2] as f32;
+        }
+
+        Some((bias, model))
+    }
+}
+
+// Gaussian elimination with partial pivoting, None for a singular matrix
+fn solve(mut matrix: [[f64; 3]; 3], mut rhs: [f64; 3]) -> Option<[f64; 3]> {
+    for column in 0..3 {
+        let pivot: usize = (column..3).max_by(|&a, &b| fabs(matrix[a][column]).total_cmp(&fabs(matrix[b][column])))?;
+        if fabs(matrix[pivot][column]) < 1e-12 {
+            return None;
+        }
+        matrix.swap(column, pivot);
+        rhs.swap(column, pivot);
+
+        for row in column + 1..3 {
+            let factor: f64 = matrix[row][column] / matrix[column][column];
+            let pivot_row: [f64; 3] = matrix[column];
+            for (value, pivot_value) in matrix[row].iter_mut().zip(pivot_row).skip(column) {
+                *value -= factor * pivot_value;
+            }
+            rhs[row] -= factor * rhs[column];
+        }
+    }
+
+    let mut solution: [f64; 3] = [0.0; 3];
+    for row in (0..3).rev() {
+        let known: f64 = (row + 1..3).map(|k| matrix[row][k] * solution[k]).sum();
+        solution[row] = (rhs[row] - known) / matrix[row][row];
+    }
+    Some(solution)
+}
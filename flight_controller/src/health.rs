@@ -0,0 +1,323 @@
+// Plausibility of the sensor data, before the estimator consumes it.
+//
+// Every axis of accelerometer and gyro is watched for
+// - clipping: the raw reading is at the end of its 16 bit range, so the real motion is unknown. Counted since boot and
+//   as the part of the recent frames
+// - freezing: the value didn't change for a number of frames, a real sensor always has noise in the last bits. A
+//   clipped axis doesn't count as frozen
+// - vibration: RMS of the axis around its slow motion (above ~3 Hz), mostly from the motors
+// and the frames for gaps in their timestamps.
+//
+// The result is a `HealthState` for the failsafe and `HealthIssues` plus the counters for telemetry. Unlike
+// `ImuVoter` this monitors the frame the estimator gets, whichever unit it came from.
+use libm::sqrtf;
+
+use crate::gy521::{DataFrame, MPUConfig};
+
+// Time constant in seconds of the mean the vibration is measured around, the high-pass is at 1 / (2π * 0.05) ≈ 3 Hz
+const VIBRATION_MEAN: f32 = 0.05;
+
+// Time constant in seconds of the clip ratio and the vibration RMS
+const WINDOW: f32 = 0.5;
+
+// Seconds a gap keeps the state degraded
+const GAP_HOLD: f32 = 1.0;
+
+// Ordered by severity
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum HealthState {
+    Healthy,
+    // The data is usable but worse than it should be: clipping, vibration or gaps
+    Degraded,
+    // The data is wrong or missing: a frozen axis or no frame for the timeout
+    Failed
+}
+
+// What is wrong right now
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HealthIssues {
+    pub clipping: bool,
+    pub frozen: bool,
+    pub vibration: bool,
+    pub gaps: bool,
+    pub stale: bool
+}
+
+impl HealthIssues {
+    pub fn state(&self) -> HealthState {
+        if self.frozen || self.stale {
+            HealthState::Failed
+        } else if self.clipping || self.vibration || self.gaps {
+            HealthState::Degraded
+        } else {
+            HealthState::Healthy
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AxisMonitor {
+    clips: u32,
+    clip_ratio: f32,
+    last: f32,
+    repeats: u32,
+    mean: f32,
+    mean_square: f32
+}
+
+impl AxisMonitor {
+    const fn new() -> Self {
+        Self { clips: 0, clip_ratio: 0.0, last: f32::NAN, repeats: 0, mean: 0.0, mean_square: 0.0 }
+    }
+
+    // alpha of the window and of the vibration mean
+    fn update(&mut self, value: f32, clipped: bool, alpha: f32, mean_alpha: f32) {
+        // The mean starts at the first value, not at 0, which would look like a big vibration
+        if self.last.is_nan() {
+            self.mean = value;
+        }
+
+        if clipped {
+            self.clips = self.clips.saturating_add(1);
+        }
+        self.clip_ratio += alpha * (if clipped { 1.0 } else { 0.0 } - self.clip_ratio);
+
+        self.repeats = if value == self.last && !clipped { self.repeats.saturating_add(1) } else { 0 };
+        self.last = value;
+
+        let difference: f32 = value - self.mean;
+        self.mean += mean_alpha * difference;
+        self.mean_square += alpha * (difference * difference - self.mean_square);
+    }
+
+    fn rms(&self) -> f32 {
+        sqrtf(self.mean_square)
+    }
+}
+
+#[derive(Debug)]
+pub struct SensorHealth {
+    clip_limit: f32,
+    frozen_limit: u32,
+    gap_limit: u64,
+    timeout: u64,
+    accel_vibration_limit: f32,
+    gyro_vibration_limit: f32,
+    accel: [AxisMonitor; 3],
+    gyro: [AxisMonitor; 3],
+    last_timestamp: Option<u64>,
+    gaps: u32,
+    longest_gap: u64,
+    since_gap: f32,
+    issues: HealthIssues
+}
+
+impl SensorHealth {
+    // config is the one of the sensor, the gap limit starts at three sample periods
+    pub fn new(config: &MPUConfig) -> Self {
+        Self {
+            clip_limit: 0.01,
+            frozen_limit: 50,
+            gap_limit: 3 * 1_000_000 / config.get_sample_rate() as u64,
+            timeout: 50_000,
+            accel_vibration_limit: 0.5,
+            gyro_vibration_limit: 20.0,
+            accel: [AxisMonitor::new(); 3],
+            gyro: [AxisMonitor::new(); 3],
+            last_timestamp: None,
+            gaps: 0,
+            longest_gap: 0,
+            since_gap: GAP_HOLD,
+            issues: HealthIssues { stale: true, ..HealthIssues::default() }
+        }
+    }
+
+    // Part of the recent frames (0 - 1) an axis may clip before the data counts as degraded
+    pub fn set_clip_limit(mut self, clip_limit: f32) -> Self {
+        assert!((0.0..1.0).contains(&clip_limit), "The clip limit must be between 0 and 1");
+        self.clip_limit = clip_limit;
+        self
+    }
+
+    // Number of identical values after which an axis counts as frozen
+    pub fn set_frozen_limit(mut self, frames: u32) -> Self {
+        assert!(frames > 0, "The frozen limit must be positive");
+        self.frozen_limit = frames;
+        self
+    }
+
+    // Longest time between two frames in µs that isn't a gap, e.g. if frames are read slower than the sample rate
+    pub fn set_gap_limit(mut self, gap_limit: u64) -> Self {
+        assert!(gap_limit > 0, "The gap limit must be positive");
+        self.gap_limit = gap_limit;
+        self
+    }
+
+    // µs without a frame after which the data is stale, see `check`
+    pub fn set_timeout(mut self, timeout: u64) -> Self {
+        assert!(timeout > 0, "The timeout must be positive");
+        self.timeout = timeout;
+        self
+    }
+
+    // Largest vibration RMS per axis in g and °/s
+    pub fn set_vibration_limit(mut self, accel: f32, gyro: f32) -> Self {
+        assert!(accel > 0.0 && gyro > 0.0, "The vibration limits must be positive");
+        self.accel_vibration_limit = accel;
+        self.gyro_vibration_limit = gyro;
+        self
+    }
+
+    // Frames are expected in the order of their timestamps. Returns the state after this frame
+    pub fn update(&mut self, frame: &DataFrame) -> HealthState {
+        let timestamp: u64 = frame.get_timestamp();
+
+        // The first frame has no time step, it only fills the monitors
+        let dt: f32 = match self.last_timestamp {
+            Some(last) => {
+                let gap: u64 = timestamp.saturating_sub(last);
+                if gap > self.gap_limit {
+                    self.gaps = self.gaps.saturating_add(1);
+                    self.longest_gap = self.longest_gap.max(gap);
+                    self.since_gap = 0.0;
+                } else {
+                    self.since_gap += gap as f32 / 1_000_000.0;
+                }
+                gap.min(self.gap_limit) as f32 / 1_000_000.0
+            },
+            None => 0.0
+        };
+        self.last_timestamp = Some(timestamp);
+
+        let (alpha, mean_alpha): (f32, f32) = (dt / (WINDOW + dt), dt / (VIBRATION_MEAN + dt));
+        let (accel, gyro) = (frame.get_accel(), frame.get_gyro());
+
+        for ((monitor, value), clipped) in self.accel.iter_mut().zip([accel.x, accel.y, accel.z]).zip(frame.get_accel_saturated()) {
+            monitor.update(value, clipped, alpha, mean_alpha);
+        }
+        for ((monitor, value), clipped) in self.gyro.iter_mut().zip([gyro.x, gyro.y, gyro.z]).zip(frame.get_gyro_saturated()) {
+            monitor.update(value, clipped, alpha, mean_alpha);
+        }
+
+        let axes = || self.accel.iter().chain(self.gyro.iter());
+        self.issues = HealthIssues {
+            clipping: axes().any(|monitor| monitor.clip_ratio > self.clip_limit),
+            frozen: axes().any(|monitor| monitor.repeats >= self.frozen_limit),
+            vibration: self.accel.iter().any(|monitor| monitor.rms() > self.accel_vibration_limit)
+                || self.gyro.iter().any(|monitor| monitor.rms() > self.gyro_vibration_limit),
+            gaps: self.since_gap < GAP_HOLD,
+            stale: false
+        };
+        self.issues.state()
+    }
+
+    // State at now (µs since boot, like the timestamps), which is failed if there was no frame for the timeout.
+    // Has to be called when a read failed, since there is no frame for `update` then
+    pub fn check(&mut self, now: u64) -> HealthState {
+        self.issues.stale = self.last_timestamp.is_none_or(|last| now.saturating_sub(last) > self.timeout);
+        self.issues.state()
+    }
+
+    // State of the last `update` or `check`
+    pub fn state(&self) -> HealthState {
+        self.issues.state()
+    }
+
+    pub fn issues(&self) -> HealthIssues {
+        self.issues
+    }
+
+    // Clipped frames per axis since boot
+    pub fn accel_clips(&self) -> [u32; 3] {
+        self.accel.map(|monitor| monitor.clips)
+    }
+
+    pub fn gyro_clips(&self) -> [u32; 3] {
+        self.gyro.map(|monitor| monitor.clips)
+    }
+
+    pub fn accel_frozen(&self) -> [bool; 3] {
+        self.accel.map(|monitor| monitor.repeats >= self.frozen_limit)
+    }
+
+    pub fn gyro_frozen(&self) -> [bool; 3] {
+        self.gyro.map(|monitor| monitor.repeats >= self.frozen_limit)
+    }
+
+    // RMS per axis in g
+    pub fn accel_vibration(&self) -> [f32; 3] {
+        self.accel.map(|monitor| monitor.rms())
+    }
+
+    // RMS per axis in °/s
+    pub fn gyro_vibration(&self) -> [f32; 3] {
+        self.gyro.map(|monitor| monitor.rms())
+    }
+
+    // Number of gaps since boot
+    pub fn gaps(&self) -> u32 {
+        self.gaps
+    }
+
+    // Longest time between two frames in µs, 0 without gaps
+    pub fn longest_gap(&self) -> u64 {
+        self.longest_gap
+    }
+}
+
+// Run on the host with `cargo test` in the simulator, on frames built from physical values
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clipped_frames_are_counted_per_axis() {
+        let config: MPUConfig = MPUConfig::default();
+        let mut health: SensorHealth = SensorHealth::new(&config);
+
+        for step in 0..2000 {
+            health.update(&DataFrame::resting(step, 1.0));
+        }
+        assert_eq!(health.state(), HealthState::Healthy);
+        assert_eq!(health.accel_clips(), [0; 3]);
+
+        // A calibrated value at the full range whose raw reading isn't clamped doesn't clip
+        for step in 2000..2100 {
+            health.update(&DataFrame::resting(step, config.get_accel_range()));
+        }
+        assert_eq!(health.accel_clips(), [0; 3]);
+
+        // A clamped raw reading does, also after the calibration moved the value away from the end of the range
+        for step in 2100..4100 {
+            health.update(&DataFrame::resting(step, 0.9 * config.get_accel_range()).set_saturated([false, false, true], [false; 3]));
+        }
+        assert_eq!(health.accel_clips(), [0, 0, 2000]);
+        assert_eq!(health.gyro_clips(), [0; 3]);
+        assert!(health.issues().clipping && !health.issues().frozen);
+        assert_eq!(health.state(), HealthState::Degraded);
+    }
+
+    #[test]
+    fn gaps_are_counted_and_held() {
+        let mut health: SensorHealth = SensorHealth::new(&MPUConfig::default());
+
+        // No frames from 1.000 s to 1.020 s, the frame after the gap is 21 ms after the last one
+        for step in (0..2100).filter(|step| !(1000..1020).contains(step)) {
+            // The hold ends after about 1 s, the summed time steps decide the exact frame
+            let state: HealthState = health.update(&DataFrame::resting(step, 1.0));
+            let expected: Option<HealthState> = match step {
+                1020..2015 => Some(HealthState::Degraded),
+                2015..2025 => None,
+                _ => Some(HealthState::Healthy)
+            };
+            assert!(expected.is_none_or(|expected| expected == state), "frame {step}: {state:?}");
+        }
+
+        assert_eq!(health.gaps(), 1);
+        assert_eq!(health.longest_gap(), 21_000);
+
+        // Without a frame for the timeout the data is stale
+        assert_eq!(health.check(2_100_000), HealthState::Healthy);
+        assert_eq!(health.check(2_200_000), HealthState::Failed);
+    }
+}
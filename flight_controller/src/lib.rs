@@ -3,8 +3,16 @@
 #![allow(uncommon_codepoints)]
 
 pub mod gy521;
+pub mod compass;
+pub mod barometer;
 pub mod math;
+pub mod filter;
+pub mod redundancy;
+pub mod health;
+pub mod stationary;
 pub mod esc;
+pub mod mixer;
+pub mod controller;
 pub mod mem;
 pub mod sync;
 
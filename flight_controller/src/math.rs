@@ -1,19 +1,229 @@
 use core::{f32::consts::PI, ops::Mul};
-use libm::{atan2f, sqrtf};
+use libm::{asinf, atan2f, cosf, floorf, sinf, sqrtf};
 
-use crate::gy521::{AccelometerData, GyroscopeData};
+use crate::gy521::{AccelometerData, GyroscopeData, DataFrame};
 
-const ITERATION_LENGTH: f32 = 0.004; // In seconds
-pub struct PID {
+// Time steps above this are treated as a stall of the loop (e.g. a debugger pause) instead of flight time. In seconds
+pub const MAX_TIME_STEP: f32 = 0.1;
+
+// Measures the time between two consecutive `DataFrame`s.
+//
+// Integrating over a step that is zero, negative (timer wrapped or frames out of order) or absurdly large would
+// corrupt every estimator and controller, so such steps yield `None`. The offending timestamp becomes the new
+// reference, therefore the following step is measured normally again.
+#[derive(Debug)]
+pub struct TimeStep {
+    last_timestamp: Option<u64>,
+    max_step: f32
 }
 
+impl TimeStep {
+    pub const fn new() -> Self {
+        Self { last_timestamp: None, max_step: MAX_TIME_STEP }
+    }
+
+    // Largest accepted step in seconds
+    pub fn set_max_step(mut self, max_step: f32) -> Self {
+        assert!(max_step > 0.0, "The maximal time step must be positive");
+        self.max_step = max_step;
+        self
+    }
+
+    // Forgets the last timestamp, the next call returns `None`
+    pub fn reset(&mut self) {
+        self.last_timestamp = None;
+    }
+
+    // Takes the timestamp of the new frame in µs and returns the elapsed time in seconds
+    pub fn step(&mut self, timestamp: u64) -> Option<f32> {
+        let last_timestamp: Option<u64> = self.last_timestamp.replace(timestamp);
+
+        let dt: f32 = timestamp.checked_sub(last_timestamp?)? as f32 / 1_000_000.0;
+
+        if dt > 0.0 && dt <= self.max_step {
+            Some(dt)
+        } else {
+            None
+        }
+    }
+
+    pub fn step_frame(&mut self, frame: &DataFrame) -> Option<f32> {
+        self.step(frame.get_timestamp())
+    }
+}
+
+impl Default for TimeStep {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Strategy used to stop the integral term from growing while the output is saturated.
+//
+// Clamping:            the integrator is frozen whenever integrating would drive the output further into saturation.
+// BackCalculation(kb): the difference between the saturated and the unsaturated output is fed back into the
+//                      integrator with the gain kb. A good starting point is kb = ki / kp (or 1 / Tᵢ).
+#[derive(Debug, Clone, Copy)]
+pub enum AntiWindup {
+    Clamping,
+    BackCalculation(f32)
+}
+
+// A single axis PID controller.
+//
+// u(t) = Kp * e(t) + Ki * ∫ e(t) dt - Kd * d/dt y(t)
+//
+// The derivative is taken on the measurement y instead of the error e, so a step in the setpoint doesn't produce a
+// spike (derivative kick). Because differentiating amplifies sensor noise, the D term is passed through a first
+// order low-pass filter:
+//
+// D(t) = D(t - 1) + α * (D_raw - D(t - 1)),   α = dt / (τ + dt),   τ = 1 / (2π * f_c)
+//
+// Every call to `update` takes the elapsed time dt in seconds, nothing is assumed about the loop rate.
 #[derive(Debug)]
+pub struct PID {
+    kp: f32,
+    ki: f32,
+    kd: f32,
+
+    output_min: f32,
+    output_max: f32,
+    integral_limit: f32,
+    anti_windup: AntiWindup,
+    d_tau: f32,
+
+    integral: f32,
+    derivative: f32,
+    prev_measurement: Option<f32>
+}
+
+impl PID {
+    // Creates a PID without output limits and without filtering of the D term
+    pub const fn new(kp: f32, ki: f32, kd: f32) -> Self {
+        Self {
+            kp,
+            ki,
+            kd,
+            output_min: f32::NEG_INFINITY,
+            output_max: f32::INFINITY,
+            integral_limit: f32::INFINITY,
+            anti_windup: AntiWindup::Clamping,
+            d_tau: 0.0,
+            integral: 0.0,
+            derivative: 0.0,
+            prev_measurement: None
+        }
+    }
+
+    pub fn set_output_limits(mut self, min: f32, max: f32) -> Self {
+        assert!(min < max, "The lower output limit must be smaller than the upper one");
+        self.output_min = min;
+        self.output_max = max;
+        self
+    }
+
+    // Hard limit for the absolute value of the integral term, independent of the anti windup strategy
+    pub fn set_integral_limit(mut self, limit: f32) -> Self {
+        assert!(limit >= 0.0, "The integral limit cannot be negative");
+        self.integral_limit = limit;
+        self
+    }
+
+    pub fn set_anti_windup(mut self, anti_windup: AntiWindup) -> Self {
+        self.anti_windup = anti_windup;
+        self
+    }
+
+    // Cutoff frequency of the low-pass filter on the D term in Hz. A cutoff of 0 disables the filter.
+    pub fn set_d_cutoff(mut self, cutoff_hz: f32) -> Self {
+        assert!(cutoff_hz >= 0.0, "The cutoff frequency cannot be negative");
+        self.d_tau = if cutoff_hz > 0.0 { 1.0 / (2.0 * PI * cutoff_hz) } else { 0.0 };
+        self
+    }
+
+    // Allows retuning while flying. The integral term stores Ki * ∫ e dt, so changing Ki doesn't cause a jump.
+    pub fn set_gains(&mut self, kp: f32, ki: f32, kd: f32) {
+        self.kp = kp;
+        self.ki = ki;
+        self.kd = kd;
+    }
+
+    pub fn gains(&self) -> (f32, f32, f32) {
+        (self.kp, self.ki, self.kd)
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    // Has to be called on arming and disarming, otherwise the integral collected on the ground is released on takeoff
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.derivative = 0.0;
+        self.prev_measurement = None;
+    }
+
+    // Computes the next controller output. dt is the time in seconds since the last call.
+    // A dt that isn't strictly positive leaves the state untouched, only the proportional part follows the new error.
+    pub fn update(&mut self, setpoint: f32, measurement: f32, dt: f32) -> f32 {
+        let error: f32 = setpoint - measurement;
+        let p: f32 = self.kp * error;
+
+        if dt.is_nan() || dt <= 0.0 {
+            return (p + self.integral + self.kd * self.derivative).clamp(self.output_min, self.output_max);
+        }
+
+        // Derivative on measurement. The very first sample has no predecessor and therefore no slope.
+        let raw_derivative: f32 = match self.prev_measurement {
+            Some(prev_measurement) => -(measurement - prev_measurement) / dt,
+            None => 0.0
+        };
+        self.prev_measurement = Some(measurement);
+
+        let alpha: f32 = dt / (self.d_tau + dt);
+        self.derivative += alpha * (raw_derivative - self.derivative);
+        let d: f32 = self.kd * self.derivative;
+
+        let integral: f32 = self.integral + self.ki * error * dt;
+        let unsaturated: f32 = p + integral + d;
+        let output: f32 = unsaturated.clamp(self.output_min, self.output_max);
+
+        self.integral = match self.anti_windup {
+            AntiWindup::Clamping => {
+                let pushes_up: bool = unsaturated > self.output_max && error > 0.0;
+                let pushes_down: bool = unsaturated < self.output_min && error < 0.0;
+
+                if pushes_up || pushes_down { self.integral } else { integral }
+            },
+            AntiWindup::BackCalculation(kb) => integral + kb * (output - unsaturated) * dt
+        }.clamp(-self.integral_limit, self.integral_limit);
+
+        output
+    }
+}
+
+// Angles in degrees. x = roll, y = pitch, z = yaw
+#[derive(Debug, Clone, Copy)]
 pub struct Angle {
     x: f32,
     y: f32,
     z: f32
 }
 
+impl Angle {
+    pub fn roll(&self) -> f32 {
+        self.x
+    }
+
+    pub fn pitch(&self) -> f32 {
+        self.y
+    }
+
+    pub fn yaw(&self) -> f32 {
+        self.z
+    }
+}
+
 impl Default for Angle {
     fn default() -> Self {
         Self { x: 0.0, y: 0.0, z: 0.0 }
@@ -34,27 +244,780 @@ impl Mul<f32> for Angle {
 // 
 // Basierend auf der Stammfunktion F, kann man iterativ den nächsten Wert bestimmen, da °/s die Veränderung beschreibt.
 //
-// Tₛ = Iterationslänge, die Vergangenezeit zwischen den einzelnen Lesungen
+// Tₛ = Iterationslänge, die Vergangenezeit zwischen den einzelnen Lesungen. Sie wird gemessen (siehe `TimeStep`)
+// und nicht als fest angenommen, da die Schleife nicht mit einer garantierten Frequenz läuft.
 //
 // F(t + 1) = F(t) + f(t) * Tₛ 
-pub fn compute_angle_integration(gyro: &GyroscopeData, prev_angle: Angle) -> Angle {
+pub fn compute_angle_integration(gyro: &GyroscopeData, prev_angle: Angle, dt: f32) -> Angle {
     let GyroscopeData { x, y, z } = *gyro;
     let Angle { x: prev_x, y: prev_y, z: prev_z } = prev_angle;
 
     Angle { 
-        x: prev_x + x * ITERATION_LENGTH, 
-        y: prev_y + y * ITERATION_LENGTH, 
-        z: prev_z + z * ITERATION_LENGTH
+        x: prev_x + x * dt, 
+        y: prev_y + y * dt, 
+        z: prev_z + z * dt
      }
 }
 
 
 // https://www.researchgate.net/figure/Drones-pitch-roll-and-yaw_fig2_329521700
+//
+// The signs follow the right hand rule of the gyroscope axes: A positive rotation around y lifts the z axis towards -x,
+// so gravity shows up as a negative x acceleration. Otherwise the result couldn't be fused with the gyro angles. A
+// positive pitch is a lowered nose, like for the gyro and the attitude controller.
 pub fn compute_angle_acceleration(accel: &AccelometerData) -> Angle {
     let AccelometerData { x, y, z } = *accel;
 
     let roll: f32 = 180.0 * atan2f(y, sqrtf(x * x + z * z)) / PI;
-    let pitch: f32 = 180.0 * atan2f(x, sqrtf(y * y + z * z)) / PI;
+    let pitch: f32 = 180.0 * atan2f(-x, sqrtf(y * y + z * z)) / PI;
 
     Angle { x: roll, y: pitch, z: 0.0 } 
-}
\ No newline at end of file
+}
+
+// Roll, pitch and yaw from the accelerometer and the magnetometer (`compass::Compass::read`).
+//
+// The field is rotated back into the horizontal plane with roll and pitch (tilt compensation), yaw is the angle from
+// magnetic north to the nose, positive counterclockwise like the gyro. The declination isn't corrected. Unlike
+// `compute_angle_acceleration`, roll is the Euler angle of the yaw, pitch, roll sequence (atan2(a_y, a_z)), which
+// the compensation needs once the drone is pitched as well.
+//
+// X_h = m_x cos θ + (m_y sin φ + m_z cos φ) sin θ,   Y_h = m_y cos φ - m_z sin φ,   ψ = atan2(-Y_h, X_h)
+pub fn compute_angle_magnetometer(accel: &AccelometerData, mag: &MagnetometerData) -> Angle {
+    let AccelometerData { x: a_x, y: a_y, z: a_z } = *accel;
+    let MagnetometerData { x, y, z } = *mag;
+
+    let roll: f32 = atan2f(a_y, a_z);
+    let pitch: f32 = atan2f(-a_x, sqrtf(a_y * a_y + a_z * a_z));
+
+    let x_h: f32 = x * cosf(pitch) + (y * sinf(roll) + z * cosf(roll)) * sinf(pitch);
+    let y_h: f32 = y * cosf(roll) - z * sinf(roll);
+
+    Angle { x: 180.0 * roll / PI, y: 180.0 * pitch / PI, z: 180.0 * atan2f(-y_h, x_h) / PI }
+}
+
+// Keeps an angle in the range of (-180°, 180°]
+fn wrap_degrees(angle: f32) -> f32 {
+    let wrapped: f32 = angle - 360.0 * floorf((angle + 180.0) / 360.0);
+    if wrapped == -180.0 { 180.0 } else { wrapped }
+}
+
+// Fuses the gyro integration with the accelerometer angles.
+//
+// The gyro is precise over short periods but its integral drifts without bound, the accelerometer is noisy
+// and disturbed by vibration but never drifts. Blending both gives a high-pass on the gyro and a low-pass on the accelerometer:
+//
+// θ(t) = α * (θ(t - 1) + ω * dt) + (1 - α) * θ_accel,   α = τ / (τ + dt)
+//
+// τ is the time constant in seconds: disturbances faster than τ are rejected, slower ones are tracked by the accelerometer.
+// The accelerometer has no notion of heading, therefore yaw is only integrated and drifts unless a magnetometer
+// heading is fused with `fuse_heading`.
+#[derive(Debug)]
+pub struct ComplementaryFilter {
+    time_constant: f32,
+    angle: Angle,
+    initialized: bool
+}
+
+impl ComplementaryFilter {
+    pub const fn new(time_constant: f32) -> Self {
+        Self { time_constant, angle: Angle { x: 0.0, y: 0.0, z: 0.0 }, initialized: false }
+    }
+
+    pub fn set_time_constant(&mut self, time_constant: f32) {
+        assert!(time_constant >= 0.0, "The time constant cannot be negative");
+        self.time_constant = time_constant;
+    }
+
+    pub fn angle(&self) -> Angle {
+        self.angle
+    }
+
+    // The next update starts again from the accelerometer angles
+    pub fn reset(&mut self) {
+        self.angle = Angle::default();
+        self.initialized = false;
+    }
+
+    // dt is the time in seconds since the previous frame
+    pub fn update(&mut self, frame: &DataFrame, dt: f32) -> Angle {
+        let accel_angle: Angle = compute_angle_acceleration(frame.get_accel());
+
+        // Instead of slowly converging from 0, the first estimate is taken from the accelerometer
+        if !self.initialized {
+            self.angle = Angle { x: accel_angle.x, y: accel_angle.y, z: 0.0 };
+            self.initialized = true;
+            return self.angle;
+        }
+
+        if dt.is_nan() || dt <= 0.0 {
+            return self.angle;
+        }
+
+        let GyroscopeData { x, y, z } = *frame.get_gyro();
+        let alpha: f32 = self.time_constant / (self.time_constant + dt);
+
+        self.angle = Angle {
+            x: alpha * (self.angle.x + x * dt) + (1.0 - alpha) * accel_angle.x,
+            y: alpha * (self.angle.y + y * dt) + (1.0 - alpha) * accel_angle.y,
+            z: wrap_degrees(self.angle.z + z * dt)
+        };
+
+        self.angle
+    }
+
+    // Pulls yaw towards heading (degrees, e.g. the yaw of `compute_angle_magnetometer`) like the accelerometer pulls
+    // roll and pitch. dt is the time in seconds since the previous heading
+    pub fn fuse_heading(&mut self, heading: f32, dt: f32) -> Angle {
+        if !self.initialized || dt.is_nan() || dt <= 0.0 {
+            return self.angle;
+        }
+
+        let alpha: f32 = self.time_constant / (self.time_constant + dt);
+        self.angle.z = wrap_degrees(self.angle.z + (1.0 - alpha) * wrap_degrees(heading - self.angle.z));
+        self.angle
+    }
+}
+
+// Magnetic field in any unit, only the direction is used. `compass::Compass` measures it in gauss.
+#[derive(Debug, Clone, Copy)]
+pub struct MagnetometerData {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32
+}
+
+fn normalize(v: [f32; 3]) -> Option<[f32; 3]> {
+    let norm: f32 = sqrtf(v[0] * v[0] + v[1] * v[1] + v[2] * v[2]);
+
+    if norm > 0.0 && norm.is_finite() {
+        Some([v[0] / norm, v[1] / norm, v[2] / norm])
+    } else {
+        None
+    }
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0]
+    ]
+}
+
+// Rotation from the body frame into the earth frame: v_earth = q ⊗ v_body ⊗ q*
+//
+// Unlike Euler angles a quaternion has no singularity at ±90° pitch, so the filters below work on quaternions
+// and only convert to `Angle` for the outside world.
+#[derive(Debug, Clone, Copy)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32
+}
+
+impl Quaternion {
+    pub const IDENTITY: Self = Self { w: 1.0, x: 0.0, y: 0.0, z: 0.0 };
+
+    pub const fn new(w: f32, x: f32, y: f32, z: f32) -> Self {
+        Self { w, x, y, z }
+    }
+
+    // Aerospace sequence: yaw around z, then pitch around y, then roll around x
+    pub fn from_euler(angle: &Angle) -> Self {
+        let (sr, cr) = (sinf(angle.x * PI / 360.0), cosf(angle.x * PI / 360.0));
+        let (sp, cp) = (sinf(angle.y * PI / 360.0), cosf(angle.y * PI / 360.0));
+        let (sy, cy) = (sinf(angle.z * PI / 360.0), cosf(angle.z * PI / 360.0));
+
+        Self {
+            w: cr * cp * cy + sr * sp * sy,
+            x: sr * cp * cy - cr * sp * sy,
+            y: cr * sp * cy + sr * cp * sy,
+            z: cr * cp * sy - sr * sp * cy
+        }
+    }
+
+    // Euler angles in degrees. At exactly ±90° pitch roll and yaw are no longer distinguishable, only their sum is defined.
+    pub fn to_euler(&self) -> Angle {
+        let Self { w, x, y, z } = *self;
+
+        let roll: f32 = atan2f(2.0 * (w * x + y * z), 1.0 - 2.0 * (x * x + y * y));
+        let pitch: f32 = asinf((2.0 * (w * y - z * x)).clamp(-1.0, 1.0));
+        let yaw: f32 = atan2f(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z));
+
+        Angle { x: roll * 180.0 / PI, y: pitch * 180.0 / PI, z: yaw * 180.0 / PI }
+    }
+
+    pub fn norm(&self) -> f32 {
+        sqrtf(self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z)
+    }
+
+    pub fn normalize(self) -> Self {
+        let norm: f32 = self.norm();
+
+        if norm > 0.0 && norm.is_finite() {
+            self * (1.0 / norm)
+        } else {
+            Self::IDENTITY
+        }
+    }
+
+    pub fn conjugate(self) -> Self {
+        Self { w: self.w, x: -self.x, y: -self.y, z: -self.z }
+    }
+
+    // R(q) * v
+    pub fn rotate_to_earth(&self, v: [f32; 3]) -> [f32; 3] {
+        let Self { w, x, y, z } = *self;
+
+        [
+            (1.0 - 2.0 * (y * y + z * z)) * v[0] + 2.0 * (x * y - w * z) * v[1] + 2.0 * (x * z + w * y) * v[2],
+            2.0 * (x * y + w * z) * v[0] + (1.0 - 2.0 * (x * x + z * z)) * v[1] + 2.0 * (y * z - w * x) * v[2],
+            2.0 * (x * z - w * y) * v[0] + 2.0 * (y * z + w * x) * v[1] + (1.0 - 2.0 * (x * x + y * y)) * v[2]
+        ]
+    }
+
+    // R(q)ᵀ * v
+    pub fn rotate_to_body(&self, v: [f32; 3]) -> [f32; 3] {
+        self.conjugate().rotate_to_earth(v)
+    }
+
+    // q(t + dt) = q(t) + 1/2 * q(t) ⊗ (0, ω) * dt,   ω in rad/s
+    fn integrate(self, omega: [f32; 3], dt: f32) -> Self {
+        let q_dot: Quaternion = self * Quaternion::new(0.0, omega[0], omega[1], omega[2]) * 0.5;
+
+        Self {
+            w: self.w + q_dot.w * dt,
+            x: self.x + q_dot.x * dt,
+            y: self.y + q_dot.y * dt,
+            z: self.z + q_dot.z * dt
+        }.normalize()
+    }
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+// Hamilton product
+impl Mul<Quaternion> for Quaternion {
+    type Output = Self;
+    fn mul(self, rhs: Quaternion) -> Self::Output {
+        Self {
+            w: self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            x: self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            y: self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            z: self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w
+        }
+    }
+}
+
+impl Mul<f32> for Quaternion {
+    type Output = Self;
+    fn mul(self, rhs: f32) -> Self::Output {
+        Self { w: self.w * rhs, x: self.x * rhs, y: self.y * rhs, z: self.z * rhs }
+    }
+}
+
+fn gyro_to_radians(gyro: &GyroscopeData) -> [f32; 3] {
+    [gyro.x * PI / 180.0, gyro.y * PI / 180.0, gyro.z * PI / 180.0]
+}
+
+// Initial orientation taken from the accelerometer, so the filters don't have to converge from a level attitude
+fn initial_orientation(accel: &AccelometerData) -> Quaternion {
+    Quaternion::from_euler(&compute_angle_acceleration(accel))
+}
+
+// Madgwick, "An efficient orientation filter for inertial and inertial/magnetic sensor arrays" (2010)
+//
+// The gyro rate is integrated and, every step, corrected by a gradient descent step that rotates the estimate
+// towards the orientation in which gravity (and the earth magnetic field) is measured:
+//
+// q̇ = 1/2 * q ⊗ ω - β * ∇f / |∇f|,   ∇f = Jᵀ(q) * f(q)
+//
+// β is the gain of the correction, roughly the mean gyro error in rad/s. Larger values converge faster but let more
+// accelerometer noise through.
+#[derive(Debug)]
+pub struct Madgwick {
+    beta: f32,
+    q: Quaternion,
+    initialized: bool
+}
+
+impl Madgwick {
+    pub const fn new(beta: f32) -> Self {
+        Self { beta, q: Quaternion::IDENTITY, initialized: false }
+    }
+
+    pub fn set_beta(&mut self, beta: f32) {
+        assert!(beta >= 0.0, "Beta cannot be negative");
+        self.beta = beta;
+    }
+
+    pub fn beta(&self) -> f32 {
+        self.beta
+    }
+
+    pub fn quaternion(&self) -> Quaternion {
+        self.q
+    }
+
+    pub fn angle(&self) -> Angle {
+        self.q.to_euler()
+    }
+
+    pub fn reset(&mut self) {
+        self.q = Quaternion::IDENTITY;
+        self.initialized = false;
+    }
+
+    // 6-DOF update, dt in seconds
+    pub fn update_imu(&mut self, accel: &AccelometerData, gyro: &GyroscopeData, dt: f32) -> Quaternion {
+        self.update_marg(accel, gyro, None, dt)
+    }
+
+    // 9-DOF update. Without magnetometer data this falls back to the 6-DOF update, in which yaw is only integrated.
+    pub fn update_marg(&mut self, accel: &AccelometerData, gyro: &GyroscopeData, mag: Option<&MagnetometerData>, dt: f32) -> Quaternion {
+        if !self.initialized {
+            self.q = initial_orientation(accel);
+            self.initialized = true;
+            return self.q;
+        }
+
+        if dt.is_nan() || dt <= 0.0 {
+            return self.q;
+        }
+
+        let Quaternion { w: q0, x: q1, y: q2, z: q3 } = self.q;
+        let mut gradient: [f32; 4] = [0.0; 4];
+
+        // In free fall (or with a broken sensor) gravity cannot be used as a reference
+        if let Some(a) = normalize([accel.x, accel.y, accel.z]) {
+            // Difference between the gravity predicted by q and the measured one
+            let f: [f32; 3] = [
+                2.0 * (q1 * q3 - q0 * q2) - a[0],
+                2.0 * (q0 * q1 + q2 * q3) - a[1],
+                2.0 * (0.5 - q1 * q1 - q2 * q2) - a[2]
+            ];
+
+            let jacobian: [[f32; 4]; 3] = [
+                [-2.0 * q2, 2.0 * q3, -2.0 * q0, 2.0 * q1],
+                [2.0 * q1, 2.0 * q0, 2.0 * q3, 2.0 * q2],
+                [0.0, -4.0 * q1, -4.0 * q2, 0.0]
+            ];
+
+            accumulate_gradient(&mut gradient, &jacobian, &f);
+
+            if let Some(m) = mag.and_then(|mag| normalize([mag.x, mag.y, mag.z])) {
+                // The earth field only has a north and a vertical component: b = [bx, 0, bz]
+                let h: [f32; 3] = self.q.rotate_to_earth(m);
+                let bx: f32 = sqrtf(h[0] * h[0] + h[1] * h[1]);
+                let bz: f32 = h[2];
+
+                let f: [f32; 3] = [
+                    2.0 * bx * (0.5 - q2 * q2 - q3 * q3) + 2.0 * bz * (q1 * q3 - q0 * q2) - m[0],
+                    2.0 * bx * (q1 * q2 - q0 * q3) + 2.0 * bz * (q0 * q1 + q2 * q3) - m[1],
+                    2.0 * bx * (q0 * q2 + q1 * q3) + 2.0 * bz * (0.5 - q1 * q1 - q2 * q2) - m[2]
+                ];
+
+                let jacobian: [[f32; 4]; 3] = [
+                    [-2.0 * bz * q2, 2.0 * bz * q3, -4.0 * bx * q2 - 2.0 * bz * q0, -4.0 * bx * q3 + 2.0 * bz * q1],
+                    [-2.0 * bx * q3 + 2.0 * bz * q1, 2.0 * bx * q2 + 2.0 * bz * q0, 2.0 * bx * q1 + 2.0 * bz * q3, -2.0 * bx * q0 + 2.0 * bz * q2],
+                    [2.0 * bx * q2, 2.0 * bx * q3 - 4.0 * bz * q1, 2.0 * bx * q0 - 4.0 * bz * q2, 2.0 * bx * q1]
+                ];
+
+                accumulate_gradient(&mut gradient, &jacobian, &f);
+            }
+        }
+
+        let gradient: Quaternion = Quaternion::new(gradient[0], gradient[1], gradient[2], gradient[3]);
+        let norm: f32 = gradient.norm();
+        let correction: Quaternion = if norm > 0.0 { gradient * (self.beta / norm) } else { Quaternion::new(0.0, 0.0, 0.0, 0.0) };
+
+        let omega: [f32; 3] = gyro_to_radians(gyro);
+        let q_dot: Quaternion = self.q * Quaternion::new(0.0, omega[0], omega[1], omega[2]) * 0.5;
+
+        self.q = Quaternion {
+            w: q0 + (q_dot.w - correction.w) * dt,
+            x: q1 + (q_dot.x - correction.x) * dt,
+            y: q2 + (q_dot.y - correction.y) * dt,
+            z: q3 + (q_dot.z - correction.z) * dt
+        }.normalize();
+
+        self.q
+    }
+}
+
+// gradient += Jᵀ * f
+fn accumulate_gradient(gradient: &mut [f32; 4], jacobian: &[[f32; 4]; 3], f: &[f32; 3]) {
+    for (column, value) in gradient.iter_mut().enumerate() {
+        *value += jacobian[0][column] * f[0] + jacobian[1][column] * f[1] + jacobian[2][column] * f[2];
+    }
+}
+
+// Mahony, "Nonlinear Complementary Filters on the Special Orthogonal Group" (2008)
+//
+// The error between the measured and the predicted direction of gravity (and the magnetic field) is the cross product
+// e = v_measured × v_predicted. It is fed back into the gyro rate through a PI controller:
+//
+// ω' = ω + Kp * e + Ki * ∫ e dt
+//
+// The integral part converges to the gyro bias, which makes Mahony cheaper than Madgwick in terms of drift.
+#[derive(Debug)]
+pub struct Mahony {
+    kp: f32,
+    ki: f32,
+    q: Quaternion,
+    integral: [f32; 3],
+    initialized: bool
+}
+
+impl Mahony {
+    pub const fn new(kp: f32, ki: f32) -> Self {
+        Self { kp, ki, q: Quaternion::IDENTITY, integral: [0.0; 3], initialized: false }
+    }
+
+    pub fn set_gains(&mut self, kp: f32, ki: f32) {
+        assert!(kp >= 0.0 && ki >= 0.0, "The gains cannot be negative");
+        self.kp = kp;
+        self.ki = ki;
+
+        if ki == 0.0 {
+            self.integral = [0.0; 3];
+        }
+    }
+
+    pub fn gains(&self) -> (f32, f32) {
+        (self.kp, self.ki)
+    }
+
+    pub fn quaternion(&self) -> Quaternion {
+        self.q
+    }
+
+    pub fn angle(&self) -> Angle {
+        self.q.to_euler()
+    }
+
+    // The estimated gyro bias in rad/s, collected by the integral part
+    pub fn gyro_bias(&self) -> [f32; 3] {
+        [-self.integral[0], -self.integral[1], -self.integral[2]]
+    }
+
+    pub fn reset(&mut self) {
+        self.q = Quaternion::IDENTITY;
+        self.integral = [0.0; 3];
+        self.initialized = false;
+    }
+
+    // 6-DOF update, dt in seconds
+    pub fn update_imu(&mut self, accel: &AccelometerData, gyro: &GyroscopeData, dt: f32) -> Quaternion {
+        self.update_marg(accel, gyro, None, dt)
+    }
+
+    // 9-DOF update. Without magnetometer data this falls back to the 6-DOF update, in which yaw is only integrated.
+    pub fn update_marg(&mut self, accel: &AccelometerData, gyro: &GyroscopeData, mag: Option<&MagnetometerData>, dt: f32) -> Quaternion {
+        if !self.initialized {
+            self.q = initial_orientation(accel);
+            self.initialized = true;
+            return self.q;
+        }
+
+        if dt.is_nan() || dt <= 0.0 {
+            return self.q;
+        }
+
+        let mut omega: [f32; 3] = gyro_to_radians(gyro);
+
+        if let Some(a) = normalize([accel.x, accel.y, accel.z]) {
+            let v: [f32; 3] = self.q.rotate_to_body([0.0, 0.0, 1.0]);
+            let mut error: [f32; 3] = cross(a, v);
+
+            if let Some(m) = mag.and_then(|mag| normalize([mag.x, mag.y, mag.z])) {
+                let h: [f32; 3] = self.q.rotate_to_earth(m);
+                let w: [f32; 3] = self.q.rotate_to_body([sqrtf(h[0] * h[0] + h[1] * h[1]), 0.0, h[2]]);
+                let mag_error: [f32; 3] = cross(m, w);
+
+                error = [error[0] + mag_error[0], error[1] + mag_error[1], error[2] + mag_error[2]];
+            }
+
+            for axis in 0..3 {
+                if self.ki > 0.0 {
+                    self.integral[axis] += self.ki * error[axis] * dt;
+                }
+                omega[axis] += self.kp * error[axis] + self.integral[axis];
+            }
+        }
+
+        self.q = self.q.integrate(omega, dt);
+        self.q
+    }
+}
+
+// Run on the host by `cargo test` in the simulator
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // First order plant y' = (gain * u - y) / τ, stepped with the controller at 1 kHz
+    fn run(pid: &mut PID, y: &mut f32, setpoint: f32, gain: f32, duration: f32, mut observe: impl FnMut(f32, f32)) {
+        const DT: f32 = 0.001;
+        const TAU: f32 = 0.2;
+
+        for _ in 0..(duration / DT) as usize {
+            let u: f32 = pid.update(setpoint, *y, DT);
+            *y += (gain * u - *y) / TAU * DT;
+            observe(u, *y);
+        }
+    }
+
+    #[test]
+    fn pid_step_response_settles_without_error() {
+        let mut pid: PID = PID::new(2.0, 5.0, 0.0);
+        let (mut y, mut peak): (f32, f32) = (0.0, 0.0);
+
+        run(&mut pid, &mut y, 1.0, 1.0, 5.0, |_, y| peak = peak.max(y));
+
+        // The integral removes the error a pure P controller leaves behind: 1 / (1 + Kp)
+        assert!((y - 1.0).abs() < 1e-3, "y = {y}");
+        assert!(peak < 1.2, "overshoot to {peak}");
+        assert!((pid.integral() - 1.0).abs() < 1e-2, "integral {}", pid.integral());
+    }
+
+    #[test]
+    fn pid_output_respects_limits() {
+        let mut pid: PID = PID::new(50.0, 20.0, 1.0).set_output_limits(-0.5, 0.8);
+        let mut y: f32 = 0.0;
+
+        run(&mut pid, &mut y, 1.0, 1.0, 2.0, |u, _| assert!((-0.5..=0.8).contains(&u), "u = {u}"));
+        run(&mut pid, &mut y, -1.0, 1.0, 2.0, |u, _| assert!((-0.5..=0.8).contains(&u), "u = {u}"));
+    }
+
+    // The setpoint is out of reach for 5 s, then drops to 0. Measures how long the output stays saturated
+    fn windup_recovery(anti_windup: AntiWindup) -> (f32, f32) {
+        let mut pid: PID = PID::new(1.0, 2.0, 0.0).set_output_limits(-1.0, 1.0).set_anti_windup(anti_windup);
+        let mut y: f32 = 0.0;
+
+        run(&mut pid, &mut y, 2.0, 1.0, 5.0, |_, _| {});
+        let integral: f32 = pid.integral();
+
+        let mut saturated: f32 = 0.0;
+        run(&mut pid, &mut y, 0.0, 1.0, 5.0, |u, _| if u >= 1.0 { saturated += 0.001 });
+        (integral, saturated)
+    }
+
+    #[test]
+    fn pid_anti_windup_limits_the_integral() {
+        // Back-calculation with kb = 0 is a plain integrator
+        let (plain_integral, plain_saturated) = windup_recovery(AntiWindup::BackCalculation(0.0));
+        let (clamped_integral, clamped_saturated) = windup_recovery(AntiWindup::Clamping);
+        let (back_integral, back_saturated) = windup_recovery(AntiWindup::BackCalculation(2.0));
+
+        assert!(plain_integral > 5.0, "plain integral {plain_integral}");
+        assert!(clamped_integral <= 1.0, "clamped integral {clamped_integral}");
+        assert!(back_integral < 1.0, "back-calculated integral {back_integral}");
+        assert!(plain_saturated > 1.0, "plain integrator saturated for {plain_saturated} s");
+        assert!(clamped_saturated < 0.1 && back_saturated < 0.1, "saturated for {clamped_saturated} s and {back_saturated} s");
+    }
+
+    #[test]
+    fn pid_integral_limit_and_invalid_steps() {
+        let mut pid: PID = PID::new(0.0, 10.0, 0.0).set_integral_limit(0.3);
+        for _ in 0..100 {
+            pid.update(1.0, 0.0, 0.01);
+        }
+        assert_eq!(pid.integral(), 0.3);
+
+        // No integration, only the proportional part follows the error
+        let mut pid: PID = PID::new(2.0, 10.0, 1.0);
+        for dt in [0.0, -0.01, f32::NAN] {
+            assert_eq!(pid.update(1.0, 0.5, dt), 1.0);
+            assert_eq!(pid.integral(), 0.0);
+        }
+    }
+
+    // xorshift, deterministic noise in the range of -amplitude - amplitude
+    fn noise(state: &mut u32, amplitude: f32) -> f32 {
+        *state ^= *state << 13;
+        *state ^= *state >> 17;
+        *state ^= *state << 5;
+        amplitude * (2.0 * (*state as f32 / u32::MAX as f32) - 1.0)
+    }
+
+    // Specific force in body axes while the drone rests at roll and pitch (degrees)
+    fn gravity(roll: f32, pitch: f32) -> [f32; 3] {
+        let (roll, pitch): (f32, f32) = (roll * PI / 180.0, pitch * PI / 180.0);
+        [-sinf(pitch), sinf(roll) * cosf(pitch), cosf(roll) * cosf(pitch)]
+    }
+
+    #[test]
+    fn accelerometer_angles_follow_the_gyro_convention() {
+        // Nose lowered by 30° (positive pitch, like a positive rotation about y) and right side lowered by 20°
+        let angle: Angle = compute_angle_acceleration(&AccelometerData { x: -0.5, y: 0.0, z: 0.866_025_4 });
+        assert!((angle.pitch() - 30.0).abs() < 1e-3 && angle.roll().abs() < 1e-3, "{angle:?}");
+
+        let [x, y, z] = gravity(20.0, 0.0);
+        let angle: Angle = compute_angle_acceleration(&AccelometerData { x, y, z });
+        assert!((angle.roll() - 20.0).abs() < 1e-3 && angle.pitch().abs() < 1e-3, "{angle:?}");
+    }
+
+    #[test]
+    fn complementary_filter_stays_bounded_with_gyro_bias_and_noise() {
+        const DT: f32 = 0.002;
+        const BIAS: [f32; 3] = [2.0, -3.0, 1.0]; // °/s
+        let mut filter: ComplementaryFilter = ComplementaryFilter::new(0.5);
+        let mut state: u32 = 0x1234_5678;
+
+        for step in 0..30_000u64 {
+            let [x, y, z] = gravity(0.0, 0.0);
+            let accel: [f32; 3] = [x + noise(&mut state, 0.05), y + noise(&mut state, 0.05), z + noise(&mut state, 0.05)];
+            let gyro: [f32; 3] = BIAS.map(|bias| bias + noise(&mut state, 1.0));
+            let angle: Angle = filter.update(&DataFrame::from_values(accel, gyro, step * 2000), DT);
+
+            // The bias leaves an offset of bias * τ, the accelerometer keeps it from growing
+            if step > 5000 {
+                assert!(angle.roll().abs() < 1.0 + 0.5 && angle.pitch().abs() < 1.5 + 0.5, "step {step}: {angle:?}");
+            }
+        }
+
+        // Yaw has no reference and drifts with the bias
+        let yaw: f32 = filter.angle().yaw();
+        assert!((yaw - 60.0).abs() < 1.0, "yaw {yaw}");
+    }
+
+    #[test]
+    fn complementary_filter_tracks_a_rotation() {
+        const DT: f32 = 0.002;
+        let mut filter: ComplementaryFilter = ComplementaryFilter::new(0.5);
+
+        // Pitches down with 30 °/s for one second, then rests
+        for step in 0..1000u64 {
+            let pitch: f32 = 30.0 * (step as f32 * DT).min(1.0);
+            let rate: f32 = if step < 500 { 30.0 } else { 0.0 };
+            let angle: Angle = filter.update(&DataFrame::from_values(gravity(0.0, pitch), [0.0, rate, 0.0], step * 2000), DT);
+            assert!((angle.pitch() - pitch).abs() < 0.5, "step {step}: {angle:?}");
+        }
+    }
+
+    #[test]
+    fn heading_fusion_removes_yaw_drift() {
+        const DT: f32 = 0.002;
+        let mut filter: ComplementaryFilter = ComplementaryFilter::new(0.5);
+
+        // Yaw gyro biased by 5 °/s while the heading stays at 178°, the estimate has to settle across ±180°
+        for step in 0..20_000u64 {
+            filter.update(&DataFrame::from_values(gravity(0.0, 0.0), [0.0, 0.0, 5.0], step * 2000), DT);
+            filter.fuse_heading(178.0, DT);
+        }
+        // Settles at 178° + bias * τ, beyond 180°
+        let yaw: f32 = filter.angle().yaw();
+        assert!(yaw < 0.0 && wrap_degrees(yaw - 180.5).abs() < 0.5, "yaw {yaw}");
+    }
+
+    // Earth field pointing north and down, in gauss
+    const EARTH_FIELD: [f32; 3] = [0.2, 0.0, -0.4];
+
+    // Accelerometer and magnetometer of a drone with the orientation q, with noise
+    fn measure(q: &Quaternion, state: &mut u32) -> (AccelometerData, MagnetometerData) {
+        let [x, y, z] = q.rotate_to_body([0.0, 0.0, 1.0]).map(|value| value + noise(state, 0.02));
+        let [m_x, m_y, m_z] = q.rotate_to_body(EARTH_FIELD).map(|value| value + noise(state, 0.01));
+        (AccelometerData { x, y, z }, MagnetometerData { x: m_x, y: m_y, z: m_z })
+    }
+
+    fn angle_error(estimate: &Angle, truth: &Angle) -> [f32; 3] {
+        [estimate.x - truth.x, estimate.y - truth.y, estimate.z - truth.z].map(|error| wrap_degrees(error).abs())
+    }
+
+    #[test]
+    fn madgwick_imu_converges_to_a_static_tilt() {
+        const DT: f32 = 0.002;
+        let mut filter: Madgwick = Madgwick::new(0.1);
+        let mut state: u32 = 0x0bad_cafe;
+        let gyro: GyroscopeData = GyroscopeData { x: 0.0, y: 0.0, z: 0.0 };
+
+        // Starts level, then the accelerometer reports a tilt the gyro never saw
+        let (accel, _) = measure(&Quaternion::IDENTITY, &mut state);
+        filter.update_imu(&accel, &gyro, DT);
+
+        let truth: Angle = Angle { x: 20.0, y: -15.0, z: 0.0 };
+        for _ in 0..5000 {
+            let (accel, _) = measure(&Quaternion::from_euler(&truth), &mut state);
+            filter.update_imu(&accel, &gyro, DT);
+        }
+
+        let error: [f32; 3] = angle_error(&filter.angle(), &truth);
+        assert!(error[0] < 1.0 && error[1] < 1.0, "{:?}", filter.angle());
+    }
+
+    #[test]
+    fn madgwick_imu_follows_a_rotation() {
+        const DT: f32 = 0.002;
+        const RATE: [f32; 3] = [15.0, -10.0, 30.0]; // °/s, body axes
+        let mut filter: Madgwick = Madgwick::new(0.05);
+        let mut state: u32 = 0x2468_ace0;
+        let mut truth: Quaternion = Quaternion::from_euler(&Angle { x: 5.0, y: 10.0, z: 0.0 });
+
+        for step in 0..5000 {
+            let (accel, _) = measure(&truth, &mut state);
+            let [x, y, z] = RATE.map(|rate| rate + noise(&mut state, 0.5));
+            filter.update_imu(&accel, &GyroscopeData { x, y, z }, DT);
+            truth = truth.integrate(RATE.map(|rate| rate * PI / 180.0), DT);
+
+            let error: [f32; 3] = angle_error(&filter.angle(), &truth.to_euler());
+            assert!(error[0] < 2.0 && error[1] < 2.0, "step {step}: {:?} against {:?}", filter.angle(), truth.to_euler());
+        }
+    }
+
+    #[test]
+    fn madgwick_marg_converges_to_the_heading() {
+        const DT: f32 = 0.002;
+        let mut filter: Madgwick = Madgwick::new(0.5);
+        let mut state: u32 = 0x1357_9bdf;
+        let truth: Angle = Angle { x: 10.0, y: 5.0, z: 120.0 };
+        let q: Quaternion = Quaternion::from_euler(&truth);
+        let gyro: GyroscopeData = GyroscopeData { x: 0.0, y: 0.0, z: 0.0 };
+
+        // The accelerometer gives the tilt at the start, the heading has to come from the magnetometer
+        for _ in 0..10_000 {
+            let (accel, mag) = measure(&q, &mut state);
+            filter.update_marg(&accel, &gyro, Some(&mag), DT);
+        }
+
+        let error: [f32; 3] = angle_error(&filter.angle(), &truth);
+        assert!(error.iter().all(|&error| error < 1.0), "{:?}", filter.angle());
+    }
+
+    #[test]
+    fn madgwick_marg_removes_yaw_drift() {
+        const DT: f32 = 0.002;
+        let truth: Angle = Angle { x: 0.0, y: 0.0, z: 45.0 };
+        let q: Quaternion = Quaternion::from_euler(&truth);
+        let (mut imu, mut marg): (Madgwick, Madgwick) = (Madgwick::new(0.5), Madgwick::new(0.5));
+        let mut state: u32 = 0x8765_4321;
+
+        // Yaw gyro biased by 0.5 °/s for 20 s, the 6-DOF yaw only knows the start
+        imu.update_imu(&measure(&q, &mut state).0, &GyroscopeData { x: 0.0, y: 0.0, z: 0.0 }, DT);
+        for _ in 0..10_000 {
+            let (accel, mag) = measure(&q, &mut state);
+            let gyro: GyroscopeData = GyroscopeData { x: noise(&mut state, 0.5), y: noise(&mut state, 0.5), z: 0.5 + noise(&mut state, 0.5) };
+            imu.update_imu(&accel, &gyro, DT);
+            marg.update_marg(&accel, &gyro, Some(&mag), DT);
+        }
+
+        // 6-DOF starts at yaw 0 and drifts by the bias, the 9-DOF error stays at about bias / β
+        assert!((imu.angle().yaw() - 10.0).abs() < 1.0, "{:?}", imu.angle());
+        assert!(angle_error(&marg.angle(), &truth)[2] < 2.0, "{:?}", marg.angle());
+    }
+
+    #[test]
+    fn pid_derivative_ignores_setpoint_steps() {
+        let mut pid: PID = PID::new(0.0, 0.0, 1.0);
+        pid.update(0.0, 0.0, 0.01);
+
+        // Derivative on measurement: a setpoint step doesn't kick, a moving measurement is damped
+        assert_eq!(pid.update(10.0, 0.0, 0.01), 0.0);
+        assert!((pid.update(10.0, 0.1, 0.01) + 10.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,432 @@
+// Translates the throttle and the roll/pitch/yaw demands of the attitude controller into motor outputs.
+//
+// A frame is described by a `Geometry`: one roll, pitch and yaw factor per motor. The motor number is the index into
+// `MotorOutputs` and therefore the LEDC channel of the `ESCControler`. Default layout (Quad-X, seen from above,
+// nose pointing up):
+//
+//   m1 (GPIO27, CW)     m2 (GPIO26, CCW)
+//                 \     /
+//                   [ ]
+//                 /     \
+//   m4 (GPIO23, CCW)    m3 (GPIO25, CW)
+//
+// The axes follow the gyroscope: x points forward, y to the left and z up. A positive roll demand lowers the right
+// side, a positive pitch demand lowers the nose and a positive yaw demand turns the nose to the left. The drag of a
+// clockwise spinning propeller turns the frame counter-clockwise, so yaw is produced by speeding up one spin direction
+// and slowing down the other.
+use core::f32::consts::PI;
+use libm::{cosf, fabsf, sinf, sqrtf};
+
+pub use error_handling::{Axis, MixerError};
+
+// The LEDC peripheral of the ESP32 has 8 low speed channels
+pub const MAX_MOTORS: usize = 8;
+
+mod error_handling {
+    use core::fmt::Debug;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Axis {
+        Throttle,
+        Roll,
+        Pitch,
+        Yaw
+    }
+
+    pub enum MixerError {
+        MotorCount(usize),
+        Uncontrollable(Axis)
+    }
+
+    impl Debug for MixerError {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match self {
+                Self::MotorCount(count) => write!(f, "A frame needs between 4 and {} motors, got {count}", super::MAX_MOTORS),
+                Self::Uncontrollable(axis) => write!(f, "{axis:?} cannot be controlled independently with this geometry")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpinDirection {
+    Clockwise,
+    CounterClockwise
+}
+
+impl SpinDirection {
+    // Reaction torque of the propeller drag around the z axis
+    const fn yaw_factor(&self) -> f32 {
+        match self {
+            Self::Clockwise => 1.0,
+            Self::CounterClockwise => -1.0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Motor {
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32
+}
+
+impl Motor {
+    pub const fn new(roll: f32, pitch: f32, yaw: f32) -> Self {
+        Self { roll, pitch, yaw }
+    }
+
+    // Motor on an arm at `angle` degrees, 0° is the nose and the angle grows counter-clockwise (seen from above).
+    //
+    // The arm lies at (x, y) = (cos θ, sin θ). Motors on the left (y > 0) lift the left side, which is a positive roll,
+    // motors in the back (x < 0) lift the tail, which is a positive pitch.
+    pub fn on_arm(angle: f32, direction: SpinDirection) -> Self {
+        let angle: f32 = angle * PI / 180.0;
+
+        // sin(180°) isn't exactly 0 in f32, such leftovers would otherwise be scaled up to a full factor
+        let snap = |factor: f32| if fabsf(factor) < 1e-6 { 0.0 } else { factor };
+        Self { roll: snap(sinf(angle)), pitch: snap(-cosf(angle)), yaw: direction.yaw_factor() }
+    }
+
+    const fn null() -> Self {
+        Self { roll: 0.0, pitch: 0.0, yaw: 0.0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Geometry {
+    motors: [Motor; MAX_MOTORS],
+    count: usize
+}
+
+impl Geometry {
+    // Every axis is scaled so its largest factor is 1, a full demand on one axis then just reaches the output range.
+    // Fails if the frame has too few or too many motors, or if an axis cannot be driven without affecting the others.
+    pub fn new(motors: &[Motor]) -> Result<Self, MixerError> {
+        if motors.len() < 4 || motors.len() > MAX_MOTORS {
+            return Err(MixerError::MotorCount(motors.len()));
+        }
+
+        let mut geometry: Geometry = Self { motors: [Motor::null(); MAX_MOTORS], count: motors.len() };
+        geometry.motors[..motors.len()].copy_from_slice(motors);
+
+        let largest = |factor: fn(&Motor) -> f32| motors.iter().fold(0.0, |max: f32, motor| max.max(fabsf(factor(motor))));
+        let (roll, pitch, yaw) = (largest(|m| m.roll), largest(|m| m.pitch), largest(|m| m.yaw));
+
+        for motor in geometry.motors[..motors.len()].iter_mut() {
+            if roll > 0.0 { motor.roll /= roll }
+            if pitch > 0.0 { motor.pitch /= pitch }
+            if yaw > 0.0 { motor.yaw /= yaw }
+        }
+
+        geometry.validate()?;
+        Ok(geometry)
+    }
+
+    // m1 front left, then clockwise
+    pub fn quad_x() -> Self {
+        Self::new(&[
+            Motor::on_arm(45.0, SpinDirection::Clockwise),
+            Motor::on_arm(-45.0, SpinDirection::CounterClockwise),
+            Motor::on_arm(-135.0, SpinDirection::Clockwise),
+            Motor::on_arm(135.0, SpinDirection::CounterClockwise)
+        ]).expect("Quad-X is controllable")
+    }
+
+    // m1 front, then clockwise
+    pub fn quad_plus() -> Self {
+        Self::new(&[
+            Motor::on_arm(0.0, SpinDirection::Clockwise),
+            Motor::on_arm(-90.0, SpinDirection::CounterClockwise),
+            Motor::on_arm(180.0, SpinDirection::Clockwise),
+            Motor::on_arm(90.0, SpinDirection::CounterClockwise)
+        ]).expect("Quad-+ is controllable")
+    }
+
+    // Two arms in the front, m1 front left, then clockwise
+    pub fn hex_x() -> Self {
+        Self::new(&[
+            Motor::on_arm(30.0, SpinDirection::Clockwise),
+            Motor::on_arm(-30.0, SpinDirection::CounterClockwise),
+            Motor::on_arm(-90.0, SpinDirection::Clockwise),
+            Motor::on_arm(-150.0, SpinDirection::CounterClockwise),
+            Motor::on_arm(150.0, SpinDirection::Clockwise),
+            Motor::on_arm(90.0, SpinDirection::CounterClockwise)
+        ]).expect("Hex-X is controllable")
+    }
+
+    // Three arms with two coaxial motors each. m1 - m3 are the upper motors (front left, front right, rear),
+    // m4 - m6 the lower ones in the same order
+    pub fn y6() -> Self {
+        Self::new(&[
+            Motor::on_arm(60.0, SpinDirection::Clockwise),
+            Motor::on_arm(-60.0, SpinDirection::Clockwise),
+            Motor::on_arm(180.0, SpinDirection::Clockwise),
+            Motor::on_arm(60.0, SpinDirection::CounterClockwise),
+            Motor::on_arm(-60.0, SpinDirection::CounterClockwise),
+            Motor::on_arm(180.0, SpinDirection::CounterClockwise)
+        ]).expect("Y6 is controllable")
+    }
+
+    // m1 front left, then clockwise
+    pub fn octo_x() -> Self {
+        Self::new(&[
+            Motor::on_arm(22.5, SpinDirection::Clockwise),
+            Motor::on_arm(-22.5, SpinDirection::CounterClockwise),
+            Motor::on_arm(-67.5, SpinDirection::Clockwise),
+            Motor::on_arm(-112.5, SpinDirection::CounterClockwise),
+            Motor::on_arm(-157.5, SpinDirection::Clockwise),
+            Motor::on_arm(157.5, SpinDirection::CounterClockwise),
+            Motor::on_arm(112.5, SpinDirection::Clockwise),
+            Motor::on_arm(67.5, SpinDirection::CounterClockwise)
+        ]).expect("Octo-X is controllable")
+    }
+
+    pub fn motor_count(&self) -> usize {
+        self.count
+    }
+
+    pub fn motors(&self) -> &[Motor] {
+        &self.motors[..self.count]
+    }
+
+    // Throttle, roll, pitch and yaw are controllable independently if their columns (one entry per motor) are
+    // linearly independent. Gram-Schmidt removes from every column the parts that the previous columns can already
+    // produce, if nothing is left, that axis can only be moved together with another one.
+    fn validate(&self) -> Result<(), MixerError> {
+        const AXES: [Axis; 4] = [Axis::Throttle, Axis::Roll, Axis::Pitch, Axis::Yaw];
+
+        let mut basis: [[f32; MAX_MOTORS]; 4] = [[0.0; MAX_MOTORS]; 4];
+
+        for (index, axis) in AXES.iter().enumerate() {
+            let mut column: [f32; MAX_MOTORS] = [0.0; MAX_MOTORS];
+            for (value, motor) in column.iter_mut().zip(self.motors()) {
+                *value = match axis {
+                    Axis::Throttle => 1.0,
+                    Axis::Roll => motor.roll,
+                    Axis::Pitch => motor.pitch,
+                    Axis::Yaw => motor.yaw
+                };
+            }
+
+            let original_norm: f32 = norm(&column);
+
+            for previous in basis[..index].iter() {
+                let projection: f32 = dot(&column, previous);
+                for (value, base) in column.iter_mut().zip(previous) {
+                    *value -= projection * base;
+                }
+            }
+
+            let norm: f32 = norm(&column);
+            if original_norm == 0.0 || norm < 1e-3 * original_norm {
+                return Err(MixerError::Uncontrollable(*axis));
+            }
+
+            for (base, value) in basis[index].iter_mut().zip(column) {
+                *base = value / norm;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn dot(a: &[f32; MAX_MOTORS], b: &[f32; MAX_MOTORS]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn norm(a: &[f32; MAX_MOTORS]) -> f32 {
+    sqrtf(dot(a, a))
+}
+
+// throttle is in the range of 0 - 1, roll, pitch and yaw in the range of -1 - 1
+#[derive(Debug, Clone, Copy)]
+pub struct MixerInput {
+    pub throttle: f32,
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32
+}
+
+// Output of every motor in the range of 0 - 1, in the order of the geometry
+#[derive(Debug, Clone, Copy)]
+pub struct MotorOutputs {
+    outputs: [f32; MAX_MOTORS],
+    count: usize
+}
+
+impl MotorOutputs {
+    pub fn as_slice(&self) -> &[f32] {
+        &self.outputs[..self.count]
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+#[derive(Debug)]
+pub struct Mixer {
+    geometry: Geometry,
+    idle: f32
+}
+
+impl Mixer {
+    pub const fn new(geometry: Geometry) -> Self {
+        Self { geometry, idle: 0.0 }
+    }
+
+    // Lowest output of an armed motor (0 - 1). Keeps the propellers spinning at zero throttle so the attitude
+    // can still be corrected.
+    pub fn set_idle(mut self, idle: f32) -> Self {
+        assert!((0.0..1.0).contains(&idle), "Idle has to be in the range of 0 - 1");
+        self.idle = idle;
+        self
+    }
+
+    pub fn geometry(&self) -> &Geometry {
+        &self.geometry
+    }
+
+    // Clipping every motor on its own would change the ratio between the motors, the drone would then turn in a
+    // direction nobody asked for. Instead the attitude part is kept intact (airmode):
+    //
+    // 1. If the spread between the strongest and the weakest motor exceeds the full output range,
+    //    the attitude demands are scaled down together.
+    // 2. The throttle is shifted until every motor fits into 0 - 1. Attitude authority wins over throttle.
+    pub fn mix(&self, input: &MixerInput) -> MotorOutputs {
+        let MixerInput { throttle, roll, pitch, yaw } = *input;
+        let count: usize = self.geometry.count;
+
+        let mut attitude: [f32; MAX_MOTORS] = [0.0; MAX_MOTORS];
+        for (value, motor) in attitude.iter_mut().zip(self.geometry.motors()) {
+            *value = roll * motor.roll + pitch * motor.pitch + yaw * motor.yaw;
+        }
+
+        let min: f32 = attitude[..count].iter().fold(f32::INFINITY, |min, &value| min.min(value));
+        let max: f32 = attitude[..count].iter().fold(f32::NEG_INFINITY, |max, &value| max.max(value));
+        let range: f32 = max - min;
+
+        let (scale, min, max) = if range > 1.0 { (1.0 / range, min / range, max / range) } else { (1.0, min, max) };
+
+        // After the scaling the spread is 1 only up to rounding, -min may end up slightly above 1 - max. clamp would
+        // panic then, the lower bound wins instead
+        let (low, high): (f32, f32) = (-min, (1.0 - max).max(-min));
+        let throttle: f32 = throttle.max(low).min(high);
+
+        let mut outputs: [f32; MAX_MOTORS] = [0.0; MAX_MOTORS];
+        for (output, attitude) in outputs[..count].iter_mut().zip(attitude) {
+            let value: f32 = (throttle + attitude * scale).clamp(0.0, 1.0);
+            *output = self.idle + (1.0 - self.idle) * value;
+        }
+
+        MotorOutputs { outputs, count }
+    }
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new(Geometry::quad_x())
+    }
+}
+
+// Run on the host by `cargo test` in the simulator
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // xorshift, deterministic without a dependency
+    fn random(state: &mut u32) -> f32 {
+        *state ^= *state << 13;
+        *state ^= *state >> 17;
+        *state ^= *state << 5;
+        *state as f32 / u32::MAX as f32
+    }
+
+    #[test]
+    fn saturating_demands_stay_in_range() {
+        let mixers: [Mixer; 3] = [Mixer::default(), Mixer::new(Geometry::hex_x()).set_idle(0.05), Mixer::new(Geometry::octo_x())];
+        let mut state: u32 = 0x2545_F491;
+
+        for mixer in mixers.iter() {
+            for _ in 0..100_000 {
+                let input: MixerInput = MixerInput {
+                    throttle: random(&mut state),
+                    roll: 6.0 * random(&mut state) - 3.0,
+                    pitch: 6.0 * random(&mut state) - 3.0,
+                    yaw: 6.0 * random(&mut state) - 3.0
+                };
+                let outputs: MotorOutputs = mixer.mix(&input);
+                assert!(outputs.as_slice().iter().all(|output| (0.0..=1.0).contains(output)), "{input:?} -> {outputs:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn saturation_keeps_the_attitude_ratio() {
+        let mixer: Mixer = Mixer::default();
+
+        // Full roll and yaw at full throttle: the throttle gives way, roll and yaw are scaled down together
+        let outputs: MotorOutputs = mixer.mix(&MixerInput { throttle: 1.0, roll: 1.0, pitch: 0.0, yaw: 1.0 });
+        let [m1, m2, m3, m4] = [0, 1, 2, 3].map(|i| outputs.as_slice()[i]);
+        let (max, min): (f32, f32) = (m1.max(m2).max(m3).max(m4), m1.min(m2).min(m3).min(m4));
+        assert!((max - 1.0).abs() < 1e-6 && min.abs() < 1e-6, "{outputs:?}");
+
+        // Roll and yaw keep their ratio: left minus right equals CW minus CCW
+        let roll: f32 = (m1 + m4) - (m2 + m3);
+        let yaw: f32 = (m1 + m3) - (m2 + m4);
+        assert!((roll - yaw).abs() < 1e-5, "roll {roll}, yaw {yaw}");
+    }
+
+    #[test]
+    fn presets_validate() {
+        for (geometry, count) in [
+            (Geometry::quad_x(), 4), (Geometry::quad_plus(), 4), (Geometry::hex_x(), 6), (Geometry::y6(), 6), (Geometry::octo_x(), 8)
+        ] {
+            assert_eq!(geometry.motor_count(), count);
+            assert!(geometry.validate().is_ok());
+
+            // Every axis is normalized to a largest factor of 1
+            let largest = |factor: fn(&Motor) -> f32| geometry.motors().iter().fold(0.0, |max: f32, motor| max.max(fabsf(factor(motor))));
+            for value in [largest(|m| m.roll), largest(|m| m.pitch), largest(|m| m.yaw)] {
+                assert!((value - 1.0).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate_geometries_are_rejected() {
+        let cw = |angle: f32| Motor::on_arm(angle, SpinDirection::Clockwise);
+        let ccw = |angle: f32| Motor::on_arm(angle, SpinDirection::CounterClockwise);
+
+        assert!(matches!(Geometry::new(&[cw(0.0), ccw(120.0), cw(-120.0)]), Err(MixerError::MotorCount(3))));
+        assert!(matches!(Geometry::new(&[cw(0.0); 9]), Err(MixerError::MotorCount(9))));
+
+        // All propellers spin the same way, nothing turns the frame
+        assert!(matches!(Geometry::new(&[cw(45.0), cw(-45.0), cw(-135.0), cw(135.0)]), Err(MixerError::Uncontrollable(Axis::Yaw))));
+
+        // All arms on the longitudinal axis, nothing lifts a side
+        assert!(matches!(Geometry::new(&[cw(0.0), ccw(0.0), cw(180.0), ccw(180.0)]), Err(MixerError::Uncontrollable(Axis::Roll))));
+
+        // Diagonal motors with the same direction: yaw equals pitch
+        assert!(matches!(
+            Geometry::new(&[Motor::new(1.0, -1.0, -1.0), Motor::new(-1.0, -1.0, -1.0), Motor::new(-1.0, 1.0, 1.0), Motor::new(1.0, 1.0, 1.0)]),
+            Err(MixerError::Uncontrollable(Axis::Yaw))
+        ));
+    }
+
+    #[test]
+    fn unsaturated_demands_pass_unchanged() {
+        let outputs: MotorOutputs = Mixer::default().mix(&MixerInput { throttle: 0.5, roll: 0.1, pitch: -0.2, yaw: 0.05 });
+        let expected: [f32; 4] = [0.5 + 0.1 + 0.2 + 0.05, 0.5 - 0.1 + 0.2 - 0.05, 0.5 - 0.1 - 0.2 + 0.05, 0.5 + 0.1 - 0.2 - 0.05];
+
+        for (output, expected) in outputs.as_slice().iter().zip(expected) {
+            assert!((output - expected).abs() < 1e-6, "{outputs:?}");
+        }
+    }
+}
@@ -0,0 +1,354 @@
+// Voting between two MPU-6050s, so a loose wire or a dead sensor doesn't leave the estimator without data.
+//
+// Every cycle both units are read. A unit is faulty if its read failed, its output froze (a real sensor always has
+// some noise in the last bits) or a raw reading is at the end of its range. A faulty unit is only trusted again after
+// it was healthy for the recovery time, so a wobbling connector doesn't switch back and forth.
+//
+// If both units are healthy they are compared. With two units there is no majority, a lasting disagreement can only
+// be reported: the active unit stays selected and blending stops until they agree again.
+//
+// Switching the unit makes the estimator see the difference of both calibrations as a step, which it handles like a
+// short disturbance.
+use libm::fabsf;
+
+use crate::gy521::DataFrame;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImuUnit {
+    Primary,
+    Secondary
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImuFault {
+    // The read failed
+    Missing,
+    // The output didn't change for the stuck limit
+    Stuck,
+    // A raw reading is at the end of its range, see `DataFrame::get_accel_saturated`. Still used if no other unit is
+    // left
+    Saturated
+}
+
+// What the estimator gets, for telemetry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ActiveImu {
+    None,
+    Primary,
+    Secondary,
+    Blended
+}
+
+#[derive(Debug, Clone, Copy)]
+struct UnitMonitor {
+    fault: Option<ImuFault>,
+    healthy_time: f32,
+    last: [f32; 6],
+    repeats: u32
+}
+
+impl UnitMonitor {
+    // A unit which never failed is trusted from the start
+    const fn new(recovery_time: f32) -> Self {
+        Self { fault: None, healthy_time: recovery_time, last: [f32::NAN; 6], repeats: 0 }
+    }
+}
+
+#[derive(Debug)]
+pub struct ImuVoter {
+    accel_tolerance: f32,
+    gyro_tolerance: f32,
+    disagreement_time: f32,
+    recovery_time: f32,
+    stuck_limit: u32,
+    blend: bool,
+    units: [UnitMonitor; 2],
+    disagreeing_time: f32,
+    active: ActiveImu
+}
+
+impl ImuVoter {
+    pub fn new() -> Self {
+        let recovery_time: f32 = 1.0;
+
+        Self {
+            accel_tolerance: 0.3,
+            gyro_tolerance: 10.0,
+            disagreement_time: 0.1,
+            recovery_time,
+            stuck_limit: 50,
+            blend: true,
+            units: [UnitMonitor::new(recovery_time); 2],
+            disagreeing_time: 0.0,
+            active: ActiveImu::None
+        }
+    }
+
+    // Largest difference between both units per axis, in g and °/s. Both see a different acceleration while the
+    // drone rotates, unless they are mounted close to each other
+    pub fn set_tolerance(mut self, accel: f32, gyro: f32) -> Self {
+        assert!(accel > 0.0 && gyro > 0.0, "The tolerances must be positive");
+        self.accel_tolerance = accel;
+        self.gyro_tolerance = gyro;
+        self
+    }
+
+    // Seconds the units have to differ before it counts as disagreement, rides out single outliers
+    pub fn set_disagreement_time(mut self, disagreement_time: f32) -> Self {
+        assert!(disagreement_time >= 0.0, "The disagreement time cannot be negative");
+        self.disagreement_time = disagreement_time;
+        self
+    }
+
+    // Seconds a faulty unit has to be healthy again before it is used
+    pub fn set_recovery_time(mut self, recovery_time: f32) -> Self {
+        assert!(recovery_time >= 0.0, "The recovery time cannot be negative");
+        self.recovery_time = recovery_time;
+        self.units = [UnitMonitor::new(recovery_time); 2];
+        self
+    }
+
+    // Number of identical frames after which a unit counts as stuck
+    pub fn set_stuck_limit(mut self, frames: u32) -> Self {
+        assert!(frames > 0, "The stuck limit must be positive");
+        self.stuck_limit = frames;
+        self
+    }
+
+    // Without blending only one unit is used at a time, with it the mean of both while they agree
+    pub fn set_blend(mut self, blend: bool) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn active(&self) -> ActiveImu {
+        self.active
+    }
+
+    pub fn fault(&self, unit: ImuUnit) -> Option<ImuFault> {
+        self.units[unit as usize].fault
+    }
+
+    // True while both units are healthy but measure different things
+    pub fn disagreeing(&self) -> bool {
+        self.disagreeing_time > self.disagreement_time
+    }
+
+    // frames are the reads of this cycle (None if it failed), dt is the time in seconds since the last cycle.
+    // Returns the frame for the estimator, None if no unit is left
+    pub fn update(&mut self, primary: Option<DataFrame>, secondary: Option<DataFrame>, dt: f32) -> Option<DataFrame> {
+        let dt: f32 = if dt.is_nan() || dt < 0.0 { 0.0 } else { dt };
+
+        self.monitor(ImuUnit::Primary, primary.as_ref(), dt);
+        self.monitor(ImuUnit::Secondary, secondary.as_ref(), dt);
+
+        let healthy: [bool; 2] = [0, 1].map(|unit| self.units[unit].fault.is_none());
+        let usable: [bool; 2] = [0, 1].map(|unit| healthy[unit] && self.units[unit].healthy_time >= self.recovery_time);
+
+        // Also during the recovery time, so a unit which comes back with different values doesn't get blended in
+        self.disagreeing_time = match (&primary, &secondary) {
+            (Some(a), Some(b)) if healthy[0] && healthy[1] && !self.agree(a, b) => self.disagreeing_time + dt,
+            _ => 0.0
+        };
+
+        self.active = match usable {
+            [true, true] if self.disagreeing() => match self.active {
+                ActiveImu::Secondary => ActiveImu::Secondary,
+                _ => ActiveImu::Primary
+            },
+            [true, true] if self.blend => ActiveImu::Blended,
+            [true, true] => match self.active {
+                ActiveImu::Secondary => ActiveImu::Secondary,
+                _ => ActiveImu::Primary
+            },
+            [true, false] => ActiveImu::Primary,
+            [false, true] => ActiveImu::Secondary,
+            // Clipped data is better than none
+            [false, false] => {
+                let saturated = |unit: ImuUnit| self.units[unit as usize].fault == Some(ImuFault::Saturated);
+                match (saturated(ImuUnit::Primary), saturated(ImuUnit::Secondary)) {
+                    (true, true) if self.active == ActiveImu::Secondary => ActiveImu::Secondary,
+                    (true, _) => ActiveImu::Primary,
+                    (false, true) => ActiveImu::Secondary,
+                    (false, false) => ActiveImu::None
+                }
+            }
+        };
+
+        match self.active {
+            ActiveImu::None => None,
+            ActiveImu::Primary => primary,
+            ActiveImu::Secondary => secondary,
+            ActiveImu::Blended => match (primary, secondary) {
+                (Some(a), Some(b)) => Some(a.average(&b)),
+                (a, b) => a.or(b)
+            }
+        }
+    }
+
+    fn monitor(&mut self, unit: ImuUnit, frame: Option<&DataFrame>, dt: f32) {
+        let stuck_limit: u32 = self.stuck_limit;
+        let monitor: &mut UnitMonitor = &mut self.units[unit as usize];
+
+        let fault: Option<ImuFault> = match frame {
+            None => Some(ImuFault::Missing),
+            Some(frame) => {
+                let (accel, gyro) = (frame.get_accel(), frame.get_gyro());
+                let values: [f32; 6] = [accel.x, accel.y, accel.z, gyro.x, gyro.y, gyro.z];
+
+                // A saturated unit repeats its values too, but it isn't stuck
+                let saturated: bool = frame.get_accel_saturated().contains(&true) || frame.get_gyro_saturated().contains(&true);
+
+                monitor.repeats = if values == monitor.last && !saturated { monitor.repeats + 1 } else { 0 };
+                monitor.last = values;
+
+                if monitor.repeats >= stuck_limit {
+                    Some(ImuFault::Stuck)
+                } else if saturated {
+                    Some(ImuFault::Saturated)
+                } else {
+                    None
+                }
+            }
+        };
+
+        monitor.healthy_time = if fault.is_some() { 0.0 } else { monitor.healthy_time + dt };
+        monitor.fault = fault;
+    }
+
+    fn agree(&self, a: &DataFrame, b: &DataFrame) -> bool {
+        let (a_accel, b_accel) = (a.get_accel(), b.get_accel());
+        let (a_gyro, b_gyro) = (a.get_gyro(), b.get_gyro());
+
+        fabsf(a_accel.x - b_accel.x) <= self.accel_tolerance
+            && fabsf(a_accel.y - b_accel.y) <= self.accel_tolerance
+            && fabsf(a_accel.z - b_accel.z) <= self.accel_tolerance
+            && fabsf(a_gyro.x - b_gyro.x) <= self.gyro_tolerance
+            && fabsf(a_gyro.y - b_gyro.y) <= self.gyro_tolerance
+            && fabsf(a_gyro.z - b_gyro.z) <= self.gyro_tolerance
+    }
+}
+
+impl Default for ImuVoter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Run on the host by `cargo test` in the simulator
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saturation_follows_the_raw_flag() {
+        let mut voter: ImuVoter = ImuVoter::new().set_recovery_time(0.0);
+
+        // Far beyond the default range but not flagged, e.g. a calibration scaling a raw value up: still healthy
+        let large: DataFrame = DataFrame::from_values([20.0, 0.0, 1.0], [0.0, 3000.0, 0.0], 0);
+        voter.update(Some(large), Some(DataFrame::resting(0, 1.0)), 0.001);
+        assert_eq!(voter.fault(ImuUnit::Primary), None);
+
+        // A flagged axis saturates the unit, however small the calibrated value. The other unit takes over
+        for step in 1..200 {
+            let clamped: DataFrame = DataFrame::resting(step, 1.0).set_saturated([false; 3], [false, false, true]);
+            voter.update(Some(clamped), Some(DataFrame::resting(step, 1.0)), 0.001);
+        }
+        assert_eq!(voter.fault(ImuUnit::Primary), Some(ImuFault::Saturated));
+        assert_eq!(voter.active(), ActiveImu::Secondary);
+
+        // Without another unit the saturated one is used, also while its values repeat
+        for step in 200..400 {
+            let clamped: DataFrame = DataFrame::from_values([0.0, 0.0, 16.0], [0.0; 3], step * 1000).set_saturated([false, false, true], [false; 3]);
+            voter.update(Some(clamped), None, 0.001);
+        }
+        assert_eq!(voter.fault(ImuUnit::Primary), Some(ImuFault::Saturated));
+        assert_eq!(voter.active(), ActiveImu::Primary);
+    }
+
+    #[test]
+    fn disagreement_stops_blending_until_the_units_agree() {
+        let mut voter: ImuVoter = ImuVoter::new();
+        voter.update(Some(DataFrame::resting(0, 1.0)), Some(DataFrame::resting(0, 1.0)), 0.001);
+        assert_eq!(voter.active(), ActiveImu::Blended);
+
+        // The secondary measures 20 °/s more around z, twice the tolerance
+        let turning = |step: u64| DataFrame::resting(step, 1.0).remove_gyro_bias([0.0, 0.0, -20.0]);
+
+        // Shorter than the disagreement time it is an outlier
+        for step in 1..50 {
+            voter.update(Some(DataFrame::resting(step, 1.0)), Some(turning(step)), 0.001);
+        }
+        assert!(!voter.disagreeing());
+        assert_eq!(voter.active(), ActiveImu::Blended);
+
+        // Longer, blending stops and the primary, the last one selected, is used alone
+        for step in 50..150 {
+            voter.update(Some(DataFrame::resting(step, 1.0)), Some(turning(step)), 0.001);
+        }
+        assert!(voter.disagreeing());
+        assert_eq!(voter.active(), ActiveImu::Primary);
+
+        let frame: DataFrame = voter.update(Some(DataFrame::resting(150, 1.0)), Some(turning(150)), 0.001).expect("primary");
+        assert!(frame.get_gyro().z.abs() < 0.01, "{:?}", frame.get_gyro());
+
+        // Once they agree again they are blended at once
+        let frame: DataFrame = voter.update(Some(DataFrame::resting(151, 1.0)), Some(DataFrame::resting(151, 1.0)), 0.001).expect("both");
+        assert!(!voter.disagreeing());
+        assert_eq!(voter.active(), ActiveImu::Blended);
+        assert!(frame.get_gyro().z.abs() < 0.01, "{:?}", frame.get_gyro());
+    }
+
+    #[test]
+    fn a_disagreement_keeps_the_selected_unit() {
+        let mut voter: ImuVoter = ImuVoter::new();
+
+        // Without the primary the secondary is selected
+        voter.update(None, Some(DataFrame::resting(0, 1.0)), 0.001);
+        assert_eq!(voter.active(), ActiveImu::Secondary);
+
+        // The primary comes back disagreeing. Neither during nor after its recovery time is it blended in
+        for step in 1..1200 {
+            voter.update(Some(DataFrame::resting(step, 1.0).remove_gyro_bias([0.0, 30.0, 0.0])), Some(DataFrame::resting(step, 1.0)), 0.001);
+            assert_eq!(voter.active(), ActiveImu::Secondary, "frame {step}");
+        }
+        assert_eq!(voter.fault(ImuUnit::Primary), None);
+        assert!(voter.disagreeing());
+    }
+
+    #[test]
+    fn a_stuck_unit_is_trusted_again_after_the_recovery_time() {
+        let mut voter: ImuVoter = ImuVoter::new();
+
+        // The primary repeats its values, only the timestamp moves on
+        let frozen = |step: u64| DataFrame::from_values([0.0, 0.0, 1.0], [0.0; 3], step * 1000);
+        for step in 0..50 {
+            voter.update(Some(frozen(step)), Some(DataFrame::resting(step, 1.0)), 0.001);
+        }
+        assert_eq!(voter.fault(ImuUnit::Primary), None);
+        assert_eq!(voter.active(), ActiveImu::Blended);
+
+        // The 50th repetition
+        voter.update(Some(frozen(50)), Some(DataFrame::resting(50, 1.0)), 0.001);
+        assert_eq!(voter.fault(ImuUnit::Primary), Some(ImuFault::Stuck));
+        assert_eq!(voter.active(), ActiveImu::Secondary);
+
+        // Healthy again, but only used after one second
+        for step in 51..900 {
+            voter.update(Some(DataFrame::resting(step, 1.0)), Some(DataFrame::resting(step, 1.0)), 0.001);
+        }
+        assert_eq!(voter.fault(ImuUnit::Primary), None);
+        assert_eq!(voter.active(), ActiveImu::Secondary);
+
+        for step in 900..1100 {
+            voter.update(Some(DataFrame::resting(step, 1.0)), Some(DataFrame::resting(step, 1.0)), 0.001);
+        }
+        assert_eq!(voter.active(), ActiveImu::Blended);
+
+        // A single failure restarts the recovery time
+        voter.update(None, Some(DataFrame::resting(1100, 1.0)), 0.001);
+        assert_eq!(voter.fault(ImuUnit::Primary), Some(ImuFault::Missing));
+        voter.update(Some(DataFrame::resting(1101, 1.0)), Some(DataFrame::resting(1101, 1.0)), 0.001);
+        assert_eq!(voter.active(), ActiveImu::Secondary);
+    }
+}
@@ -0,0 +1,182 @@
+// Refines the gyro bias while the drone doesn't move.
+//
+// `GY521::calibrate` only takes a snapshot at boot, the remaining bias (and its drift with temperature) is integrated
+// by the estimators. Roll and pitch are pulled back by the accelerometer, yaw is not. While the drone rests, every
+// gyro reading is pure bias plus noise, so the bias follows it through a slow low-pass:
+//
+// bias(t) = bias(t - 1) + β * (ω - bias(t - 1)),   β = dt / (τ + dt)
+//
+// The drone counts as resting when, over a short window, the accelerometer measures 1g, the gyro variance is low and
+// the gyro mean is close to the bias (a slow steady rotation has no variance either). All of this has to hold for the
+// settle time before the bias is touched.
+//
+// Vibration of spinning motors usually fails the variance test, but a smooth hover may pass all of them. Only feed
+// frames while the drone is disarmed.
+use libm::{expf, fabsf, sqrtf};
+
+use crate::gy521::{AccelometerData, DataFrame, GyroscopeData};
+
+// Time constant of the window the resting criteria are evaluated over, in seconds
+const WINDOW: f32 = 0.5;
+
+#[derive(Debug)]
+pub struct GyroBiasEstimator {
+    accel_tolerance: f32,
+    gyro_variance_limit: f32,
+    max_rate: f32,
+    settle_time: f32,
+    time_constant: f32,
+    accel_magnitude: f32,
+    gyro_mean: [f32; 3],
+    gyro_variance: [f32; 3],
+    rest_time: f32,
+    bias: [f32; 3],
+    confidence: f32,
+    initialized: bool
+}
+
+impl GyroBiasEstimator {
+    pub const fn new() -> Self {
+        Self {
+            accel_tolerance: 0.05,
+            gyro_variance_limit: 0.25,
+            max_rate: 5.0,
+            settle_time: 1.0,
+            time_constant: 5.0,
+            accel_magnitude: 1.0,
+            gyro_mean: [0.0; 3],
+            gyro_variance: [0.0; 3],
+            rest_time: 0.0,
+            bias: [0.0; 3],
+            confidence: 0.0,
+            initialized: false
+        }
+    }
+
+    // Largest deviation of the accelerometer magnitude from 1g, in g
+    pub fn set_accel_tolerance(mut self, tolerance: f32) -> Self {
+        assert!(tolerance > 0.0, "The tolerance must be positive");
+        self.accel_tolerance = tolerance;
+        self
+    }
+
+    // Largest gyro variance per axis in (°/s)², has to be above the noise of the sensor
+    pub fn set_gyro_variance_limit(mut self, limit: f32) -> Self {
+        assert!(limit > 0.0, "The variance limit must be positive");
+        self.gyro_variance_limit = limit;
+        self
+    }
+
+    // Largest difference between the gyro mean and the bias in °/s, slower rotations are taken for bias
+    pub fn set_max_rate(mut self, max_rate: f32) -> Self {
+        assert!(max_rate > 0.0, "The maximal rate must be positive");
+        self.max_rate = max_rate;
+        self
+    }
+
+    // Seconds the drone has to rest before the bias is refined
+    pub fn set_settle_time(mut self, settle_time: f32) -> Self {
+        assert!(settle_time >= 0.0, "The settle time cannot be negative");
+        self.settle_time = settle_time;
+        self
+    }
+
+    // τ of the bias filter in seconds, longer averages more noise away but follows the temperature slower
+    pub fn set_time_constant(mut self, time_constant: f32) -> Self {
+        assert!(time_constant > 0.0, "The time constant must be positive");
+        self.time_constant = time_constant;
+        self
+    }
+
+    // Estimated bias in °/s, on top of the calibration of the driver
+    pub fn bias(&self) -> [f32; 3] {
+        self.bias
+    }
+
+    // 0 - 1, grows while the drone rests and fades while it moves, since the bias may have drifted meanwhile
+    pub fn confidence(&self) -> f32 {
+        self.confidence
+    }
+
+    pub fn is_stationary(&self) -> bool {
+        self.rest_time >= self.settle_time
+    }
+
+    // Per axis standard deviation of the gyro over the window in °/s
+    pub fn gyro_deviation(&self) -> [f32; 3] {
+        self.gyro_variance.map(sqrtf)
+    }
+
+    // Forgets the bias and the confidence
+    pub fn reset(&mut self) {
+        *self = Self {
+            accel_tolerance: self.accel_tolerance,
+            gyro_variance_limit: self.gyro_variance_limit,
+            max_rate: self.max_rate,
+            settle_time: self.settle_time,
+            time_constant: self.time_constant,
+            ..Self::new()
+        };
+    }
+
+    // frame is the uncorrected output of the driver, dt the time in seconds since the previous frame.
+    // Returns whether the drone is resting
+    pub fn update(&mut self, frame: &DataFrame, dt: f32) -> bool {
+        let AccelometerData { x: a_x, y: a_y, z: a_z } = *frame.get_accel();
+        let GyroscopeData { x, y, z } = *frame.get_gyro();
+        let gyro: [f32; 3] = [x, y, z];
+        let magnitude: f32 = sqrtf(a_x * a_x + a_y * a_y + a_z * a_z);
+
+        // The window starts at the first frame, not at rest
+        if !self.initialized {
+            self.accel_magnitude = magnitude;
+            self.gyro_mean = gyro;
+            self.initialized = true;
+            return false;
+        }
+
+        if dt.is_nan() || dt <= 0.0 {
+            return self.is_stationary();
+        }
+
+        // Exponentially weighted mean and variance
+        let alpha: f32 = dt / (WINDOW + dt);
+        self.accel_magnitude += alpha * (magnitude - self.accel_magnitude);
+        for ((value, mean), variance) in gyro.iter().zip(self.gyro_mean.iter_mut()).zip(self.gyro_variance.iter_mut()) {
+            let difference: f32 = value - *mean;
+            *mean += alpha * difference;
+            *variance = (1.0 - alpha) * (*variance + alpha * difference * difference);
+        }
+
+        let resting: bool = fabsf(self.accel_magnitude - 1.0) < self.accel_tolerance
+            && (0..3).all(|axis| {
+                self.gyro_variance[axis] < self.gyro_variance_limit && fabsf(self.gyro_mean[axis] - self.bias[axis]) < self.max_rate
+            });
+
+        self.rest_time = if resting { self.rest_time + dt } else { 0.0 };
+
+        if self.is_stationary() {
+            let beta: f32 = dt / (self.time_constant + dt);
+            for (bias, value) in self.bias.iter_mut().zip(gyro) {
+                *bias += beta * (value - *bias);
+            }
+            self.confidence += beta * (1.0 - self.confidence);
+        } else {
+            // Fades ten times slower than it grows
+            self.confidence *= expf(-dt / (10.0 * self.time_constant));
+        }
+
+        self.is_stationary()
+    }
+
+    // The frame with the estimated bias removed from the gyro
+    pub fn correct(&self, frame: &DataFrame) -> DataFrame {
+        frame.remove_gyro_bias(self.bias)
+    }
+}
+
+impl Default for GyroBiasEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,196 @@
+// Synthesizes the `DataFrame`s an MPU-6050 would deliver for the simulated motion.
+//
+// The signal chain follows the chip: the true motion passes the DLPF (modelled as a first order low-pass whose time
+// constant equals the group delay of the datasheet), bias and noise are added, and the result is quantized to the
+// 16 bit registers, saturating at the full scale range. The register bytes go through `DataFrame::new`, exactly like
+// in the driver.
+use crate::{
+    gy521::{BoardAlignment, CalibrationOffsets, DataFrame, Dlpf, MPUConfig, ScalingFactor, TemperatureSweep, FRAME_SIZE},
+    noise::Noise,
+    physics::{Quadcopter, Vector}
+};
+
+#[derive(Debug, Clone)]
+pub struct ImuConfig {
+    pub accel_noise: f64,    // g, standard deviation
+    pub gyro_noise: f64,     // °/s, standard deviation
+    pub accel_bias: Vector,  // g
+    pub gyro_bias: Vector,   // °/s
+    pub dlpf_delay: f64,     // s
+    pub temperature: f64,    // °C, die temperature
+    // Drift of the gyro bias relative to 25 °C: linear (°/s/°C) and quadratic (°/s/°C²) part per axis
+    pub gyro_temperature_drift: Vector,
+    pub gyro_temperature_curvature: Vector,
+    // How the sensor is mounted on the frame, the driver is configured with the same alignment
+    pub mounting: BoardAlignment
+}
+
+impl Default for ImuConfig {
+    // Values in the range of a real MPU-6050 after calibration, with the DLPF of `MPUConfig::default`
+    fn default() -> Self {
+        Self {
+            accel_noise: 0.01,
+            gyro_noise: 0.1,
+            accel_bias: [0.005, -0.005, 0.01],
+            gyro_bias: [0.1, -0.1, 0.1],
+            dlpf_delay: Dlpf::Hz_20.get_delay() as f64 / 1_000_000.0,
+            temperature: 25.0,
+            gyro_temperature_drift: [0.03, -0.02, 0.04],
+            gyro_temperature_curvature: [0.0005, 0.0003, -0.0004],
+            mounting: BoardAlignment::default()
+        }
+    }
+}
+
+// Faults injected into `ImuModel::try_read`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImuState {
+    Healthy,
+    // The read fails, e.g. a loose wire
+    Disconnected,
+    // The registers keep the last value
+    Frozen
+}
+
+pub struct ImuModel {
+    config: ImuConfig,
+    scaling_factor: ScalingFactor,
+    accel: Vector,
+    gyro: Vector,
+    noise: Noise,
+    calibration: CalibrationOffsets,
+    state: ImuState,
+    frozen: Option<[u8; FRAME_SIZE]>
+}
+
+impl ImuModel {
+    pub fn new(config: ImuConfig, mpu_config: &MPUConfig, noise: Noise) -> Self {
+        Self {
+            config,
+            scaling_factor: mpu_config.get_scaling_factor(),
+            accel: [0.0, 0.0, 1.0],
+            gyro: [0.0; 3],
+            noise,
+            calibration: CalibrationOffsets::default(),
+            state: ImuState::Healthy,
+            frozen: None
+        }
+    }
+
+    pub fn set_state(&mut self, state: ImuState) {
+        self.state = state;
+    }
+
+    pub fn set_temperature(&mut self, temperature: f64) {
+        self.config.temperature = temperature;
+    }
+
+    // Applied to every frame of `read`, like the calibration of the driver
+    pub fn calibration(&self) -> &CalibrationOffsets {
+        &self.calibration
+    }
+
+    pub fn set_calibration(&mut self, calibration: CalibrationOffsets) {
+        self.calibration = calibration;
+    }
+
+    // Same as `GY521::measure_gyro_temperature`: averages the raw gyro output and the die temperature
+    pub fn measure_gyro_temperature(&mut self, sweep: &mut TemperatureSweep, samples: u16) {
+        let mut gyro: [f32; 3] = [0.0; 3];
+        let mut temperature: f32 = 0.0;
+
+        for _ in 0..samples {
+            let frame: DataFrame = DataFrame::new(
+                self.registers(), &self.scaling_factor, &CalibrationOffsets::default(), &BoardAlignment::default(), 0
+            );
+            let raw: [f32; 3] = [frame.get_gyro().x, frame.get_gyro().y, frame.get_gyro().z];
+
+            for axis in 0..3 {
+                gyro[axis] += raw[axis] * self.scaling_factor.g / samples as f32;
+            }
+            temperature += frame.get_temperature() / samples as f32;
+        }
+
+        sweep.add(temperature, gyro);
+    }
+
+    // Same as `GY521::apply_gyro_temperature_calibration`, false if the sweep can't be fitted
+    pub fn apply_gyro_temperature_calibration(&mut self, sweep: &TemperatureSweep, order: usize) -> bool {
+        let Some((bias, model)) = sweep.fit(order) else {
+            return false;
+        };
+
+        self.calibration.gyro_bias = bias;
+        self.calibration.gyro_temperature = model;
+        true
+    }
+
+    // Has to be called after every physics step, dt is the length of that step
+    pub fn record(&mut self, quad: &Quadcopter, dt: f64) {
+        let accel: Vector = quad.specific_force();
+        let gyro: Vector = quad.state().angular_velocity.map(f64::to_degrees);
+
+        let alpha: f64 = if self.config.dlpf_delay > 0.0 { 1.0 - (-dt / self.config.dlpf_delay).exp() } else { 1.0 };
+
+        for axis in 0..3 {
+            self.accel[axis] += (accel[axis] - self.accel[axis]) * alpha;
+            self.gyro[axis] += (gyro[axis] - self.gyro[axis]) * alpha;
+        }
+    }
+
+    // timestamp in µs, like `esp_hal::time::now`
+    pub fn read(&mut self, timestamp: u64) -> DataFrame {
+        DataFrame::new(self.registers(), &self.scaling_factor, &self.calibration, &self.config.mounting, timestamp)
+    }
+
+    // Like `read`, with the fault of `set_state`. None is a failed read
+    pub fn try_read(&mut self, timestamp: u64) -> Option<DataFrame> {
+        let registers: [u8; FRAME_SIZE] = match self.state {
+            ImuState::Healthy => {
+                self.frozen = None;
+                self.registers()
+            },
+            ImuState::Disconnected => return None,
+            ImuState::Frozen => match self.frozen {
+                Some(registers) => registers,
+                None => {
+                    let registers: [u8; FRAME_SIZE] = self.registers();
+                    *self.frozen.insert(registers)
+                }
+            }
+        };
+
+        Some(DataFrame::new(registers, &self.scaling_factor, &self.calibration, &self.config.mounting, timestamp))
+    }
+
+    // Same layout as the burst read of the driver: accel, temperature, gyro
+    fn registers(&mut self) -> [u8; FRAME_SIZE] {
+        let mut registers: [u8; FRAME_SIZE] = [0; FRAME_SIZE];
+        let delta: f64 = self.config.temperature - 25.0;
+
+        // The motion is in body axes, the sensor measures it in its own: sensor = mountingᵀ * body
+        let m: [[f32; 3]; 3] = self.config.mounting.matrix();
+        let to_sensor = |body: Vector| -> Vector {
+            [0, 1, 2].map(|column| (0..3).map(|row| m[row][column] as f64 * body[row]).sum())
+        };
+        let (accel, gyro): (Vector, Vector) = (to_sensor(self.accel), to_sensor(self.gyro));
+
+        for axis in 0..3 {
+            let drift: f64 = self.config.gyro_temperature_drift[axis] * delta + self.config.gyro_temperature_curvature[axis] * delta * delta;
+
+            let a: f64 = accel[axis] + self.config.accel_bias[axis] + self.noise.gaussian(self.config.accel_noise);
+            let g: f64 = gyro[axis] + self.config.gyro_bias[axis] + drift + self.noise.gaussian(self.config.gyro_noise);
+
+            registers[2 * axis..2 * axis + 2].copy_from_slice(&Self::to_register(a, self.scaling_factor.a as f64, 0.0).to_be_bytes());
+            registers[8 + 2 * axis..8 + 2 * axis + 2].copy_from_slice(&Self::to_register(g, self.scaling_factor.g as f64, 0.0).to_be_bytes());
+        }
+
+        // TEMP_OUT = (T - 36.53) * 340
+        registers[6..8].copy_from_slice(&Self::to_register(self.config.temperature, 340.0, 36.53).to_be_bytes());
+        registers
+    }
+
+    fn to_register(value: f64, scaling_factor: f64, offset: f64) -> i16 {
+        ((value - offset) * scaling_factor).round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+    }
+}
@@ -0,0 +1,41 @@
+#![allow(uncommon_codepoints)]
+
+// The portable parts of the flight controller. They are compiled for the host exactly as they are used on the ESP32,
+// which is why they may only depend on `core`, `libm` and each other.
+#[path = "../../flight_controller/src/math.rs"]
+pub mod math;
+
+#[path = "../../flight_controller/src/mixer.rs"]
+pub mod mixer;
+
+#[path = "../../flight_controller/src/controller.rs"]
+pub mod controller;
+
+#[path = "../../flight_controller/src/stationary.rs"]
+pub mod stationary;
+
+#[path = "../../flight_controller/src/filter.rs"]
+pub mod filter;
+
+#[path = "../../flight_controller/src/redundancy.rs"]
+pub mod redundancy;
+
+#[path = "../../flight_controller/src/health.rs"]
+pub mod health;
+
+// The MPU-6050 driver only depends on embedded-hal, on the host it runs against `gy521::mock`
+#[path = "../../flight_controller/src/gy521/mod.rs"]
+pub mod gy521;
+
+// Behind the bypass of the MPU-6050, on the host it runs against `compass::mock`
+#[path = "../../flight_controller/src/compass/mod.rs"]
+pub mod compass;
+
+// Shares the bus with the MPU-6050, on the host it runs against `barometer::mock`
+#[path = "../../flight_controller/src/barometer/mod.rs"]
+pub mod barometer;
+
+pub mod noise;
+pub mod physics;
+pub mod imu;
+pub mod scenario;
@@ -0,0 +1,40 @@
+// Deterministic noise source, every run of a scenario with the same seed produces the same result.
+//
+// xorshift64* for uniform numbers, Box-Muller for normal distributed ones.
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone)]
+pub struct Noise {
+    state: u64,
+    spare: Option<f64>
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        // A state of 0 would stay 0 forever
+        Self { state: seed.max(1), spare: None }
+    }
+
+    // Uniform in the range of [0, 1)
+    pub fn uniform(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Normal distribution with mean 0 and the standard deviation `sigma`
+    pub fn gaussian(&mut self, sigma: f64) -> f64 {
+        if let Some(spare) = self.spare.take() {
+            return spare * sigma;
+        }
+
+        let u1: f64 = self.uniform().max(f64::MIN_POSITIVE);
+        let u2: f64 = self.uniform();
+        let radius: f64 = (-2.0 * u1.ln()).sqrt();
+
+        self.spare = Some(radius * (2.0 * PI * u2).sin());
+        radius * (2.0 * PI * u2).cos() * sigma
+    }
+}
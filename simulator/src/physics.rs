@@ -0,0 +1,256 @@
+// Rigid body dynamics of a multicopter.
+//
+// Earth frame: x north, y west, z up. Body frame: the axes of the gyroscope, x forward, y left, z up.
+// The state is integrated with semi-implicit Euler, which is stable for the small steps used here (≤ 1 ms).
+pub const GRAVITY: f64 = 9.81;
+
+pub type Vector = [f64; 3];
+
+pub fn cross(a: Vector, b: Vector) -> Vector {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0]
+    ]
+}
+
+// Orientation of the body relative to the earth, v_earth = q ⊗ v_body ⊗ q*.
+// Deliberately independent of `math::Quaternion`, the ground truth must not share bugs with the estimator.
+#[derive(Debug, Clone, Copy)]
+pub struct Rotation {
+    w: f64,
+    x: f64,
+    y: f64,
+    z: f64
+}
+
+impl Rotation {
+    pub const IDENTITY: Self = Self { w: 1.0, x: 0.0, y: 0.0, z: 0.0 };
+
+    // Angles in degrees, yaw is applied first, then pitch, then roll
+    pub fn from_euler(roll: f64, pitch: f64, yaw: f64) -> Self {
+        let (sr, cr) = (roll.to_radians() / 2.0).sin_cos();
+        let (sp, cp) = (pitch.to_radians() / 2.0).sin_cos();
+        let (sy, cy) = (yaw.to_radians() / 2.0).sin_cos();
+
+        Self {
+            w: cr * cp * cy + sr * sp * sy,
+            x: sr * cp * cy - cr * sp * sy,
+            y: cr * sp * cy + sr * cp * sy,
+            z: cr * cp * sy - sr * sp * cy
+        }
+    }
+
+    // Roll, pitch and yaw in degrees
+    pub fn to_euler(&self) -> Vector {
+        let Self { w, x, y, z } = *self;
+
+        [
+            (2.0 * (w * x + y * z)).atan2(1.0 - 2.0 * (x * x + y * y)).to_degrees(),
+            (2.0 * (w * y - z * x)).clamp(-1.0, 1.0).asin().to_degrees(),
+            (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (y * y + z * z)).to_degrees()
+        ]
+    }
+
+    pub fn to_earth(&self, v: Vector) -> Vector {
+        let Self { w, x, y, z } = *self;
+
+        [
+            (1.0 - 2.0 * (y * y + z * z)) * v[0] + 2.0 * (x * y - w * z) * v[1] + 2.0 * (x * z + w * y) * v[2],
+            2.0 * (x * y + w * z) * v[0] + (1.0 - 2.0 * (x * x + z * z)) * v[1] + 2.0 * (y * z - w * x) * v[2],
+            2.0 * (x * z - w * y) * v[0] + 2.0 * (y * z + w * x) * v[1] + (1.0 - 2.0 * (x * x + y * y)) * v[2]
+        ]
+    }
+
+    pub fn to_body(&self, v: Vector) -> Vector {
+        Self { w: self.w, x: -self.x, y: -self.y, z: -self.z }.to_earth(v)
+    }
+
+    // q̇ = 1/2 * q ⊗ (0, ω), ω in rad/s in the body frame
+    fn integrate(&self, omega: Vector, dt: f64) -> Self {
+        let Self { w, x, y, z } = *self;
+        let [p, q, r] = omega;
+
+        let next: Rotation = Self {
+            w: w + 0.5 * (-x * p - y * q - z * r) * dt,
+            x: x + 0.5 * (w * p + y * r - z * q) * dt,
+            y: y + 0.5 * (w * q - x * r + z * p) * dt,
+            z: z + 0.5 * (w * r + x * q - y * p) * dt
+        };
+
+        let norm: f64 = (next.w * next.w + next.x * next.x + next.y * next.y + next.z * next.z).sqrt();
+        Self { w: next.w / norm, x: next.x / norm, y: next.y / norm, z: next.z / norm }
+    }
+}
+
+// position in meters in the body frame (x forward, y left), direction +1 for clockwise and -1 for counter-clockwise
+#[derive(Debug, Clone, Copy)]
+pub struct SimMotor {
+    pub position: [f64; 2],
+    pub direction: f64
+}
+
+#[derive(Debug, Clone)]
+pub struct Parameters {
+    pub mass: f64,                   // kg
+    pub inertia: Vector,             // kg m², principal axes of the body frame
+    pub motors: Vec<SimMotor>,
+    pub max_thrust: f64,             // N per motor at full command
+    pub thrust_expo: f64,            // 0 = thrust linear in the command, 1 = quadratic
+    pub motor_time_constant: f64,    // s
+    pub torque_coefficient: f64,     // Nm of propeller drag torque per N of thrust
+    pub linear_drag: f64,            // N / (m/s)
+    pub angular_drag: f64            // Nm / (rad/s)
+}
+
+impl Parameters {
+    // A 450 mm Quad-X of about 1 kg in the motor order of `Geometry::quad_x`
+    pub fn quad_x() -> Self {
+        let arm: f64 = 0.225 / 2f64.sqrt();
+
+        Self {
+            mass: 1.0,
+            inertia: [0.011, 0.011, 0.021],
+            motors: vec![
+                SimMotor { position: [arm, arm], direction: 1.0 },
+                SimMotor { position: [arm, -arm], direction: -1.0 },
+                SimMotor { position: [-arm, -arm], direction: 1.0 },
+                SimMotor { position: [-arm, arm], direction: -1.0 }
+            ],
+            max_thrust: 8.0,
+            thrust_expo: 0.5,
+            motor_time_constant: 0.03,
+            torque_coefficient: 0.016,
+            linear_drag: 0.2,
+            angular_drag: 0.002
+        }
+    }
+
+    pub fn thrust(&self, command: f64) -> f64 {
+        let command: f64 = command.clamp(0.0, 1.0);
+        self.max_thrust * ((1.0 - self.thrust_expo) * command + self.thrust_expo * command * command)
+    }
+
+    // The command at which the motors together carry the weight
+    pub fn hover_command(&self) -> f64 {
+        let thrust: f64 = self.mass * GRAVITY / self.motors.len() as f64 / self.max_thrust;
+        let expo: f64 = self.thrust_expo;
+
+        if expo == 0.0 {
+            thrust
+        } else {
+            (-(1.0 - expo) + ((1.0 - expo).powi(2) + 4.0 * expo * thrust).sqrt()) / (2.0 * expo)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct State {
+    pub position: Vector,            // m, earth frame
+    pub velocity: Vector,            // m/s, earth frame
+    pub acceleration: Vector,        // m/s², earth frame
+    pub attitude: Rotation,
+    pub angular_velocity: Vector,    // rad/s, body frame
+    pub motor_thrust: Vec<f64>       // N
+}
+
+pub struct Quadcopter {
+    parameters: Parameters,
+    state: State
+}
+
+impl Quadcopter {
+    // Starts hovering at `altitude` meters with the motors already spinning at hover thrust
+    pub fn new(parameters: Parameters, altitude: f64) -> Self {
+        let hover_thrust: f64 = parameters.mass * GRAVITY / parameters.motors.len() as f64;
+
+        let state: State = State {
+            position: [0.0, 0.0, altitude],
+            velocity: [0.0; 3],
+            acceleration: [0.0; 3],
+            attitude: Rotation::IDENTITY,
+            angular_velocity: [0.0; 3],
+            motor_thrust: vec![hover_thrust; parameters.motors.len()]
+        };
+
+        Self { parameters, state }
+    }
+
+    pub fn parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn set_attitude(&mut self, attitude: Rotation) {
+        self.state.attitude = attitude;
+    }
+
+    // What an accelerometer measures in g: the acceleration minus gravity, in the body frame
+    pub fn specific_force(&self) -> Vector {
+        let [ax, ay, az] = self.state.acceleration;
+        let force: Vector = self.state.attitude.to_body([ax, ay, az + GRAVITY]);
+
+        [force[0] / GRAVITY, force[1] / GRAVITY, force[2] / GRAVITY]
+    }
+
+    // commands are the mixer outputs (0 - 1), one per motor. disturbance is an external torque in Nm in the body frame.
+    pub fn step(&mut self, commands: &[f64], disturbance: Vector, dt: f64) {
+        let parameters: &Parameters = &self.parameters;
+        let state: &mut State = &mut self.state;
+
+        // First order motor lag
+        let lag: f64 = 1.0 - (-dt / parameters.motor_time_constant).exp();
+        for (thrust, &command) in state.motor_thrust.iter_mut().zip(commands) {
+            *thrust += (parameters.thrust(command) - *thrust) * lag;
+        }
+
+        let mut torque: Vector = disturbance;
+        let mut total_thrust: f64 = 0.0;
+
+        for (motor, &thrust) in parameters.motors.iter().zip(&state.motor_thrust) {
+            let [x, y] = motor.position;
+
+            // r × (0, 0, T) plus the drag torque of the propeller
+            torque[0] += y * thrust;
+            torque[1] -= x * thrust;
+            torque[2] += motor.direction * parameters.torque_coefficient * thrust;
+            total_thrust += thrust;
+        }
+
+        // Translation
+        let thrust: Vector = state.attitude.to_earth([0.0, 0.0, total_thrust]);
+        let mut acceleration: Vector = [0.0; 3];
+        for ((acceleration, thrust), velocity) in acceleration.iter_mut().zip(thrust).zip(state.velocity) {
+            *acceleration = (thrust - parameters.linear_drag * velocity) / parameters.mass;
+        }
+        acceleration[2] -= GRAVITY;
+
+        for ((position, velocity), acceleration) in state.position.iter_mut().zip(state.velocity.iter_mut()).zip(acceleration) {
+            *velocity += acceleration * dt;
+            *position += *velocity * dt;
+        }
+
+        // The ground carries the drone
+        if state.position[2] <= 0.0 && state.velocity[2] <= 0.0 {
+            state.position[2] = 0.0;
+            state.velocity = [0.0; 3];
+            acceleration = [0.0; 3];
+        }
+        state.acceleration = acceleration;
+
+        // Rotation: I ω̇ = τ - ω × (I ω)
+        let omega: Vector = state.angular_velocity;
+        let inertia: Vector = parameters.inertia;
+        let gyroscopic: Vector = cross(omega, [inertia[0] * omega[0], inertia[1] * omega[1], inertia[2] * omega[2]]);
+
+        for axis in 0..3 {
+            let angular_acceleration: f64 = (torque[axis] - gyroscopic[axis] - parameters.angular_drag * omega[axis]) / inertia[axis];
+            state.angular_velocity[axis] += angular_acceleration * dt;
+        }
+
+        state.attitude = state.attitude.integrate(state.angular_velocity, dt);
+    }
+}
@@ -0,0 +1,196 @@
+// Closed loop between the simulated drone and the unmodified flight controller code:
+//
+//   Quadcopter ──► ImuModel ──► DataFrame ──► TimeStep ──► Mahony ──► AttitudeController ──► Mixer ──┐
+//        ▲                                                                                           │
+//        └──────────────────────────────── motor commands (whole percentages) ◄──────────────────────┘
+//
+// With a second IMU both are read every cycle and `ImuVoter` picks the frame for the estimator.
+//
+// The physics run at a fixed step, the control loop at its own period with random jitter, so the measured time
+// steps differ like on the real hardware.
+use crate::{
+    controller::{AttitudeController, FlightMode, Setpoint},
+    gy521::{DataFrame, MPUConfig},
+    imu::{ImuConfig, ImuModel, ImuState},
+    math::{Angle, Mahony, TimeStep},
+    mixer::{Mixer, MixerInput},
+    noise::Noise,
+    physics::{Parameters, Quadcopter, Vector},
+    redundancy::{ActiveImu, ImuVoter}
+};
+
+// Everything the simulation knows about one control cycle
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub time: f64,               // s
+    pub attitude: Vector,        // true roll, pitch and yaw in degrees
+    pub rate: Vector,            // true rates in °/s
+    pub estimate: Angle,
+    pub rate_setpoint: [f32; 3],
+    pub mixer_input: MixerInput,
+    pub altitude: f64,           // m
+    pub active_imu: ActiveImu    // Primary without a second IMU, None if there was no frame
+}
+
+pub struct Simulation {
+    quad: Quadcopter,
+    imu: ImuModel,
+    secondary: Option<(ImuModel, ImuVoter)>,
+    imu_faults: fn(f64) -> [ImuState; 2],
+    time_step: TimeStep,
+    estimator: Mahony,
+    controller: AttitudeController,
+    mixer: Mixer,
+    commands: Vec<f64>,
+
+    physics_step: f64,
+    control_period: f64,
+    control_jitter: f64,
+    jitter: Noise,
+    time: f64,
+    last_control: f64
+}
+
+impl Simulation {
+    // Quad-X hovering at 10 m in Angle mode with the default gains of the flight controller
+    pub fn new(seed: u64) -> Self {
+        let parameters: Parameters = Parameters::quad_x();
+        let hover: f64 = parameters.hover_command();
+
+        Self {
+            commands: vec![hover; parameters.motors.len()],
+            quad: Quadcopter::new(parameters, 10.0),
+            imu: ImuModel::new(ImuConfig::default(), &MPUConfig::default(), Noise::new(seed)),
+            secondary: None,
+            imu_faults: |_| [ImuState::Healthy; 2],
+            time_step: TimeStep::new(),
+            estimator: Mahony::new(0.2, 0.02),
+            controller: AttitudeController::default(),
+            mixer: Mixer::default(),
+            physics_step: 0.0005,
+            control_period: 0.004,
+            control_jitter: 0.0005,
+            jitter: Noise::new(seed.wrapping_add(1)),
+            time: 0.0,
+            last_control: 0.0
+        }
+    }
+
+    pub fn set_imu(mut self, config: ImuConfig, seed: u64) -> Self {
+        self.imu = ImuModel::new(config, &MPUConfig::default(), Noise::new(seed));
+        self
+    }
+
+    // Adds a second IMU, voted with the first one by `voter`
+    pub fn set_secondary_imu(mut self, config: ImuConfig, seed: u64, voter: ImuVoter) -> Self {
+        self.secondary = Some((ImuModel::new(config, &MPUConfig::default(), Noise::new(seed)), voter));
+        self
+    }
+
+    // State of the primary and the secondary IMU over the simulation time
+    pub fn set_imu_faults(mut self, faults: fn(f64) -> [ImuState; 2]) -> Self {
+        self.imu_faults = faults;
+        self
+    }
+
+    pub fn set_mode(mut self, mode: FlightMode) -> Self {
+        self.controller.set_mode(mode);
+        self
+    }
+
+    pub fn quad(&self) -> &Quadcopter {
+        &self.quad
+    }
+
+    pub fn controller_mut(&mut self) -> &mut AttitudeController {
+        &mut self.controller
+    }
+
+    // The throttle that carries the weight of the drone
+    pub fn hover_throttle(&self) -> f32 {
+        self.quad.parameters().hover_command() as f32
+    }
+
+    // Runs for `duration` seconds. `setpoint` and `disturbance` (torque in Nm, body frame) are sampled with the
+    // simulation time, `observe` is called after every control cycle.
+    pub fn run(
+        &mut self,
+        duration: f64,
+        mut setpoint: impl FnMut(f64) -> Setpoint,
+        mut disturbance: impl FnMut(f64) -> Vector,
+        mut observe: impl FnMut(&Record)
+    ) {
+        let end: f64 = self.time + duration;
+        let mut next_control: f64 = self.time;
+
+        while self.time < end {
+            if self.time >= next_control {
+                let record: Record = self.control(setpoint(self.time));
+                observe(&record);
+
+                next_control += self.control_period + (self.jitter.uniform() - 0.5) * 2.0 * self.control_jitter;
+            }
+
+            self.quad.step(&self.commands, disturbance(self.time), self.physics_step);
+            self.imu.record(&self.quad, self.physics_step);
+            if let Some((secondary, _)) = self.secondary.as_mut() {
+                secondary.record(&self.quad, self.physics_step);
+            }
+            self.time += self.physics_step;
+        }
+    }
+
+    fn control(&mut self, setpoint: Setpoint) -> Record {
+        let timestamp: u64 = (self.time * 1_000_000.0) as u64;
+        let [primary_state, secondary_state] = (self.imu_faults)(self.time);
+        self.imu.set_state(primary_state);
+
+        let primary: Option<DataFrame> = self.imu.try_read(timestamp);
+        let (frame, active_imu): (Option<DataFrame>, ActiveImu) = match self.secondary.as_mut() {
+            Some((secondary, voter)) => {
+                secondary.set_state(secondary_state);
+                let secondary: Option<DataFrame> = secondary.try_read(timestamp);
+
+                let frame: Option<DataFrame> = voter.update(primary, secondary, (self.time - self.last_control) as f32);
+                (frame, voter.active())
+            },
+            None => {
+                let active_imu: ActiveImu = if primary.is_some() { ActiveImu::Primary } else { ActiveImu::None };
+                (primary, active_imu)
+            }
+        };
+        self.last_control = self.time;
+
+        let mut mixer_input: MixerInput = MixerInput { throttle: setpoint.throttle, roll: 0.0, pitch: 0.0, yaw: 0.0 };
+
+        // Without a frame the motors keep their last command
+        if let Some(frame) = frame {
+            if let Some(dt) = self.time_step.step_frame(&frame) {
+                self.estimator.update_imu(frame.get_accel(), frame.get_gyro(), dt);
+                mixer_input = self.controller.update(&setpoint, &self.estimator.angle(), frame.get_gyro(), dt);
+
+                // The ESCControler only sets whole percentages
+                let outputs = self.mixer.mix(&mixer_input);
+                for (command, &output) in self.commands.iter_mut().zip(outputs.as_slice()) {
+                    *command = (output as f64 * 100.0).round() / 100.0;
+                }
+            } else {
+                // Seeds the estimator with the accelerometer
+                self.estimator.update_imu(frame.get_accel(), frame.get_gyro(), 0.0);
+            }
+        }
+
+        let state = self.quad.state();
+
+        Record {
+            time: self.time,
+            attitude: state.attitude.to_euler(),
+            rate: state.angular_velocity.map(f64::to_degrees),
+            estimate: self.estimator.angle(),
+            rate_setpoint: self.controller.rate_setpoint(),
+            mixer_input,
+            altitude: state.position[2],
+            active_imu
+        }
+    }
+}
@@ -0,0 +1,536 @@
+// Regression scenarios for the flight control code, run by `cargo test`. Every scenario is a test which fails if one
+// of its metrics misses the limit, `cargo test -- --nocapture` prints the metrics of all of them.
+use std::{cell::RefCell, ops::Range};
+use embedded_hal_bus::i2c::RefCellDevice;
+use simulator::{
+    barometer::{
+        mock::MockBarometer, altitude_pressure, pressure_altitude, Address as BaroAddress, Altimeter,
+        Barometer, BarometerModel, BaroConfig, Bmp280Calibration, Bmp388Calibration, IirFilter, Oversampling,
+        BMP388_CALIBRATION_SIZE
+    },
+    compass::{mock::{MockBus, MockMagnetometer}, Compass, CompassModel, MagCalibrationSweep},
+    controller::{FlightMode, Setpoint},
+    gy521::{mock::{mock_clock, MockDelay, MockMPU6050}, AccelometerData, Address, BoardAlignment, DataFrame, GY521, MPUConfig, SensorAxis, TemperatureSweep},
+    health::{HealthState, SensorHealth},
+    imu::{ImuConfig, ImuModel, ImuState},
+    math::{compute_angle_magnetometer, MagnetometerData},
+    noise::Noise,
+    physics::{Rotation, Vector},
+    redundancy::{ActiveImu, ImuVoter},
+    scenario::{Record, Simulation},
+    stationary::GyroBiasEstimator
+};
+
+struct Outcome {
+    name: &'static str,
+    metrics: Vec<(&'static str, f64, f64)> // name, value, limit (value has to stay below)
+}
+
+impl Outcome {
+    fn passed(&self) -> bool {
+        self.metrics.iter().all(|(_, value, limit)| value.is_finite() && value < limit)
+    }
+
+    fn check(self) {
+        let passed: bool = self.passed();
+
+        println!("{} {}", if passed { "PASS" } else { "FAIL" }, self.name);
+        for (name, value, limit) in self.metrics.iter() {
+            println!("    {name}: {value:.3} (limit {limit})");
+        }
+        assert!(passed, "{} missed its limits", self.name);
+    }
+}
+
+fn level(throttle: f32) -> impl FnMut(f64) -> Setpoint {
+    move |_| Setpoint { throttle, roll: 0.0, pitch: 0.0, yaw: 0.0 }
+}
+
+fn no_disturbance(_: f64) -> Vector {
+    [0.0; 3]
+}
+
+// Angle mode with centered sticks and a biased, noisy IMU. The attitude must stay level.
+#[test]
+fn hover() {
+    let mut simulation: Simulation = Simulation::new(1);
+    let throttle: f32 = simulation.hover_throttle();
+
+    let mut max_tilt: f64 = 0.0;
+    let mut max_estimate_error: f64 = 0.0;
+
+    simulation.run(10.0, level(throttle), no_disturbance, |record: &Record| {
+        if record.time > 1.0 {
+            max_tilt = max_tilt.max(record.attitude[0].abs()).max(record.attitude[1].abs());
+            max_estimate_error = max_estimate_error.max(estimate_error(record));
+        }
+    });
+
+    Outcome { name: "hover", metrics: vec![("max tilt [°]", max_tilt, 2.0), ("max estimate error [°]", max_estimate_error, 2.0)] }.check()
+}
+
+// Like hover, but the breakout is mounted upside down with its y axis pointing backwards and a slightly tilted mount.
+// With the alignment configured the estimate must be in body axes.
+#[test]
+fn rotated_mounting() {
+    let mounting: BoardAlignment = BoardAlignment::new(SensorAxis::NegY, SensorAxis::NegZ)
+        .expect("perpendicular axes")
+        .set_trim(2.0, -3.0, 10.0);
+    let mut simulation: Simulation = Simulation::new(6).set_imu(ImuConfig { mounting, ..ImuConfig::default() }, 6);
+    let throttle: f32 = simulation.hover_throttle();
+
+    let mut max_tilt: f64 = 0.0;
+    let mut max_estimate_error: f64 = 0.0;
+
+    simulation.run(10.0, level(throttle), no_disturbance, |record: &Record| {
+        if record.time > 1.0 {
+            max_tilt = max_tilt.max(record.attitude[0].abs()).max(record.attitude[1].abs());
+            max_estimate_error = max_estimate_error.max(estimate_error(record));
+        }
+    });
+
+    Outcome { name: "rotated mounting", metrics: vec![("max tilt [°]", max_tilt, 2.0), ("max estimate error [°]", max_estimate_error, 2.0)] }.check()
+}
+
+// Hover with two IMUs: the primary loses its connection from 3 s to 5 s, the secondary freezes at 7 s. The voter has
+// to switch to the remaining unit every time, the drone must not notice.
+#[test]
+fn redundant_imus() {
+    let faults = |time: f64| -> [ImuState; 2] {
+        let primary: ImuState = if (3.0..5.0).contains(&time) { ImuState::Disconnected } else { ImuState::Healthy };
+        let secondary: ImuState = if time >= 7.0 { ImuState::Frozen } else { ImuState::Healthy };
+        [primary, secondary]
+    };
+
+    let secondary: ImuConfig = ImuConfig { accel_bias: [-0.01, 0.005, 0.0], gyro_bias: [-0.1, 0.2, 0.0], ..ImuConfig::default() };
+    let mut simulation: Simulation = Simulation::new(10)
+        .set_secondary_imu(secondary, 11, ImuVoter::new())
+        .set_imu_faults(faults);
+    let throttle: f32 = simulation.hover_throttle();
+
+    let mut max_tilt: f64 = 0.0;
+    let mut without_imu: f64 = 0.0;
+    let mut wrong_unit: f64 = 0.0;
+
+    simulation.run(10.0, level(throttle), no_disturbance, |record: &Record| {
+        if record.time > 1.0 {
+            max_tilt = max_tilt.max(record.attitude[0].abs()).max(record.attitude[1].abs());
+        }
+
+        // The primary is trusted again 1 s after it reconnected, the frozen secondary is found after 50 frames
+        let expected: Option<ActiveImu> = match record.time {
+            time if (3.0..6.0).contains(&time) => Some(ActiveImu::Secondary),
+            time if (6.0..7.0).contains(&time) => Some(ActiveImu::Blended),
+            time if time >= 7.25 => Some(ActiveImu::Primary),
+            _ => None
+        };
+
+        if record.active_imu == ActiveImu::None {
+            without_imu += 1.0;
+        }
+        if expected.is_some_and(|expected| expected != record.active_imu) {
+            wrong_unit += 1.0;
+        }
+    });
+
+    Outcome {
+        name: "redundant IMUs",
+        metrics: vec![("max tilt [°]", max_tilt, 2.0), ("cycles without IMU", without_imu, 0.5), ("cycles on the wrong unit", wrong_unit, 0.5)]
+    }.check()
+}
+
+// Angle mode, half stick on one axis at 1 s: the attitude has to settle at half the maximal angle (15°)
+fn angle_step(name: &'static str, axis: usize) -> Outcome {
+    let mut simulation: Simulation = Simulation::new(2);
+    let throttle: f32 = simulation.hover_throttle();
+
+    let target: f64 = 15.0;
+    let mut overshoot: f64 = 0.0;
+    let mut rise_time: f64 = f64::INFINITY;
+    let mut tracking_error: f64 = 0.0;
+    let mut estimate_error: f64 = 0.0;
+
+    let setpoint = |time: f64| {
+        let stick: f32 = if time >= 1.0 { 0.5 } else { 0.0 };
+        Setpoint { throttle, roll: if axis == 0 { stick } else { 0.0 }, pitch: if axis == 1 { stick } else { 0.0 }, yaw: 0.0 }
+    };
+
+    simulation.run(2.5, setpoint, no_disturbance, |record: &Record| {
+        let angle: f64 = record.attitude[axis];
+        let estimate: f64 = if axis == 0 { record.estimate.roll() } else { record.estimate.pitch() } as f64;
+
+        if record.time >= 1.0 {
+            overshoot = overshoot.max(angle - target);
+
+            if angle >= 0.9 * target {
+                rise_time = rise_time.min(record.time - 1.0);
+            }
+        }
+
+        // While the drone accelerates sideways the accelerometer doesn't point at the ground, the estimate
+        // drifts off slowly. The controller is judged against the estimate, the estimator against the truth.
+        if record.time >= 2.0 {
+            tracking_error = tracking_error.max((estimate - target).abs());
+            estimate_error = estimate_error.max((angle - estimate).abs());
+        }
+    });
+
+    Outcome {
+        name,
+        metrics: vec![
+            ("rise time to 90% [s]", rise_time, 0.6),
+            ("overshoot [°]", overshoot, 6.0),
+            ("tracking error 1 s after the step [°]", tracking_error, 1.5),
+            ("estimate error 1 s after the step [°]", estimate_error, 5.0)
+        ]
+    }
+}
+
+#[test]
+fn roll_step() {
+    angle_step("roll step", 0).check()
+}
+
+#[test]
+fn pitch_step() {
+    angle_step("pitch step", 1).check()
+}
+
+// Acro mode, a quarter stick roll: the rate has to follow 90 °/s
+#[test]
+fn rate_step() {
+    let mut simulation: Simulation = Simulation::new(3).set_mode(FlightMode::Acro);
+    let throttle: f32 = simulation.hover_throttle();
+
+    let mut rate_error: f64 = 0.0;
+    let setpoint = |time: f64| Setpoint { throttle, roll: if time >= 0.5 { 0.25 } else { 0.0 }, pitch: 0.0, yaw: 0.0 };
+
+    simulation.run(1.5, setpoint, no_disturbance, |record: &Record| {
+        if record.time >= 0.8 {
+            rate_error = rate_error.max((record.rate[0] - 90.0).abs());
+        }
+    });
+
+    Outcome { name: "acro rate step", metrics: vec![("rate error after 0.3 s [°/s]", rate_error, 9.0)] }.check()
+}
+
+// Angle mode, a torque impulse on roll and pitch (e.g. a gust) at 2 s. The drone has to level itself again.
+#[test]
+fn disturbance_rejection() {
+    let mut simulation: Simulation = Simulation::new(4);
+    let throttle: f32 = simulation.hover_throttle();
+
+    let mut max_deviation: f64 = 0.0;
+    let mut recovered_error: f64 = 0.0;
+
+    let gust = |time: f64| if (2.0..2.1).contains(&time) { [0.3, -0.2, 0.05] } else { [0.0; 3] };
+
+    simulation.run(4.0, level(throttle), gust, |record: &Record| {
+        let tilt: f64 = record.attitude[0].abs().max(record.attitude[1].abs());
+
+        if record.time >= 2.0 {
+            max_deviation = max_deviation.max(tilt);
+        }
+
+        if record.time >= 3.0 {
+            recovered_error = recovered_error.max(tilt);
+        }
+    });
+
+    Outcome { name: "disturbance rejection", metrics: vec![("max deviation [°]", max_deviation, 6.0), ("tilt 1 s after the gust [°]", recovered_error, 2.0)] }.check()
+}
+
+// The resting IMU warms up from 20 °C to 50 °C while its gyro bias drifts. After a quadratic fit of the sweep the
+// remaining bias has to stay small over the whole range.
+#[test]
+fn gyro_temperature_compensation() {
+    let mut imu: ImuModel = ImuModel::new(ImuConfig::default(), &MPUConfig::default(), Noise::new(5));
+    let mut sweep: TemperatureSweep = TemperatureSweep::new(35.0);
+
+    for step in 0..=12 {
+        imu.set_temperature(20.0 + 2.5 * step as f64);
+        imu.measure_gyro_temperature(&mut sweep, 200);
+    }
+
+    let fitted: bool = imu.apply_gyro_temperature_calibration(&sweep, 2);
+
+    let mut max_residual: f64 = if fitted { 0.0 } else { f64::INFINITY };
+    for temperature in [20.0, 27.0, 33.0, 41.0, 50.0] {
+        imu.set_temperature(temperature);
+
+        let mut gyro: Vector = [0.0; 3];
+        for _ in 0..200 {
+            let frame: DataFrame = imu.read(0);
+            gyro[0] += frame.get_gyro().x as f64 / 200.0;
+            gyro[1] += frame.get_gyro().y as f64 / 200.0;
+            gyro[2] += frame.get_gyro().z as f64 / 200.0;
+        }
+
+        max_residual = gyro.iter().fold(max_residual, |max, value| max.max(value.abs()));
+    }
+
+    Outcome { name: "gyro temperature compensation", metrics: vec![("max residual bias [°/s]", max_residual, 0.05)] }.check()
+}
+
+// Feeds 1 kHz frames of a motionless IMU to a `GyroBiasEstimator`, returns the estimator and the time it detected rest
+fn estimate_bias_at_rest(config: ImuConfig, seed: u64, duration: f64) -> (GyroBiasEstimator, f64) {
+    let mut imu: ImuModel = ImuModel::new(config, &MPUConfig::default(), Noise::new(seed));
+    let mut estimator: GyroBiasEstimator = GyroBiasEstimator::new();
+    let mut rest_time: f64 = 0.0;
+
+    for step in 0..(duration * 1000.0) as u64 {
+        if estimator.update(&imu.read(step * 1000), 0.001) {
+            rest_time += 0.001;
+        }
+    }
+
+    (estimator, rest_time)
+}
+
+// The resting IMU has a residual bias, which the estimator has to find. A steady rotation (here a bias far above
+// the limit) and vibration must not be taken for rest.
+#[test]
+fn gyro_bias_at_rest() {
+    let config: ImuConfig = ImuConfig::default();
+    let (estimator, _) = estimate_bias_at_rest(config.clone(), 7, 60.0);
+    let bias_error: f64 = (0..3).map(|axis| (estimator.bias()[axis] as f64 - config.gyro_bias[axis]).abs()).fold(0.0, f64::max);
+
+    let (_, rotating) = estimate_bias_at_rest(ImuConfig { gyro_bias: [0.0, 0.0, 20.0], ..ImuConfig::default() }, 8, 10.0);
+    let (_, vibrating) = estimate_bias_at_rest(ImuConfig { accel_noise: 0.3, gyro_noise: 3.0, ..ImuConfig::default() }, 9, 10.0);
+
+    Outcome {
+        name: "gyro bias at rest",
+        metrics: vec![
+            ("bias error after 60 s [°/s]", bias_error, 0.02),
+            ("missing confidence after 60 s", 1.0 - estimator.confidence() as f64, 0.1),
+            ("rest detected while rotating [s]", rotating, 0.001),
+            ("rest detected while vibrating [s]", vibrating, 0.001)
+        ]
+    }.check()
+}
+
+// Feeds 1 kHz frames of a motionless IMU to a `SensorHealth` for 2 s. From onset on the IMU is in state, and there
+// are no frames during gap. Returns the monitor and the number of frames whose state wasn't expected, healthy before
+// onset and expected from 0.2 s after it
+fn monitor_health(config: ImuConfig, onset: f64, state: ImuState, gap: Range<f64>, expected: HealthState) -> (SensorHealth, f64) {
+    let mut imu: ImuModel = ImuModel::new(config, &MPUConfig::default(), Noise::new(12));
+    let mut health: SensorHealth = SensorHealth::new(&MPUConfig::default());
+    let mut wrong: f64 = 0.0;
+
+    for step in 0..2000 {
+        let time: f64 = step as f64 / 1000.0;
+        imu.set_state(if time >= onset { state } else { ImuState::Healthy });
+
+        let frame: Option<DataFrame> = if gap.contains(&time) { None } else { imu.try_read(step * 1000) };
+        let result: HealthState = match frame {
+            Some(frame) => health.update(&frame),
+            None => health.check(step * 1000)
+        };
+
+        let expected: Option<HealthState> = match time {
+            time if (0.2..onset).contains(&time) => Some(HealthState::Healthy),
+            time if time >= onset + 0.2 => Some(expected),
+            _ => None
+        };
+        if expected.is_some_and(|expected| expected != result) {
+            wrong += 1.0;
+        }
+    }
+
+    (health, wrong)
+}
+
+// A resting IMU has to be healthy. Vibration, clipping and short gaps degrade it, a frozen sensor or a long gap
+// (checked while no frame arrives) fail it.
+#[test]
+fn sensor_health() {
+    let vibration: ImuConfig = ImuConfig { accel_noise: 0.8, gyro_noise: 30.0, ..ImuConfig::default() };
+    let clipping: ImuConfig = ImuConfig { accel_bias: [0.0, 0.0, 8.0], ..ImuConfig::default() };
+
+    let (resting, resting_wrong) = monitor_health(ImuConfig::default(), 0.0, ImuState::Healthy, 0.0..0.0, HealthState::Healthy);
+    let (vibrating, vibrating_wrong) = monitor_health(vibration, 0.0, ImuState::Healthy, 0.0..0.0, HealthState::Degraded);
+    let (_, clipping_wrong) = monitor_health(clipping, 0.0, ImuState::Healthy, 0.0..0.0, HealthState::Degraded);
+    let (_, frozen_wrong) = monitor_health(ImuConfig::default(), 1.0, ImuState::Frozen, 0.0..0.0, HealthState::Failed);
+    // 20 ms without frames degrade for a second, a lost sensor fails after the timeout
+    let (_, gap_wrong) = monitor_health(ImuConfig::default(), 1.0, ImuState::Healthy, 1.0..1.02, HealthState::Degraded);
+    let (_, lost_wrong) = monitor_health(ImuConfig::default(), 1.0, ImuState::Disconnected, 0.0..0.0, HealthState::Failed);
+
+    Outcome {
+        name: "sensor health",
+        metrics: vec![
+            ("wrong state at rest", resting_wrong, 0.5),
+            ("wrong state while vibrating", vibrating_wrong, 0.5),
+            ("wrong state while clipping", clipping_wrong, 0.5),
+            ("wrong state while frozen", frozen_wrong, 0.5),
+            ("wrong state after a gap", gap_wrong, 0.5),
+            ("wrong state without frames", lost_wrong, 0.5),
+            ("accel vibration error [g]", (vibrating.accel_vibration()[0] as f64 - 0.8).abs(), 0.1),
+            ("accel vibration at rest [g]", resting.accel_vibration()[0] as f64, 0.05)
+        ]
+    }.check()
+}
+
+// Earth field in gauss (x north, y west, z up) of central Europe: 0.2 G horizontal at an inclination of 65°
+const EARTH_FIELD: Vector = [0.2, 0.0, -0.43];
+const HARD_IRON: Vector = [0.08, -0.12, 0.05];
+const SOFT_IRON: [Vector; 3] = [[1.08, 0.04, -0.02], [0.04, 0.93, 0.03], [-0.02, 0.03, 1.01]];
+
+// Raw output of the magnetometer in orientation: the earth field in body axes, turned into the chip axes of the
+// mounting, distorted by the frame and quantized to the range of model
+fn magnetometer_field(model: CompassModel, orientation: &Rotation, mounting: &BoardAlignment, noise: &mut Noise) -> [i16; 3] {
+    let body: Vector = orientation.to_body(EARTH_FIELD);
+    let m: [[f32; 3]; 3] = mounting.matrix();
+    let sensor: Vector = [0, 1, 2].map(|column| (0..3).map(|row| m[row][column] as f64 * body[row]).sum());
+
+    [0, 1, 2].map(|row| {
+        let distorted: f64 = (0..3).map(|k| SOFT_IRON[row][k] * sensor[k]).sum::<f64>() + HARD_IRON[row] + noise.gaussian(0.002);
+        (distorted * model.sensitivity() as f64).round() as i16
+    })
+}
+
+// GY-87 style: the magnetometer sits behind the bypass of the MPU-6050. It is mounted backwards and distorted by the
+// frame, after a calibration with random orientations the tilt compensated heading has to match the true yaw in any
+// attitude. Both chip variants.
+#[test]
+fn compass_heading() {
+    let mounting: BoardAlignment = BoardAlignment::new(SensorAxis::NegX, SensorAxis::PosZ).expect("perpendicular axes");
+    let mut noise: Noise = Noise::new(13);
+
+    let mut heading_error: f64 = 0.0;
+    let mut field_error: f64 = 0.0;
+
+    for model in [CompassModel::Hmc5883l, CompassModel::Qmc5883l] {
+        let bus: RefCell<MockBus> = RefCell::new(MockBus::new(MockMPU6050::new(Address::AD0Low), MockMagnetometer::new(model)));
+        let mut imu: GY521<RefCellDevice<MockBus>, MockDelay> = GY521::new(RefCellDevice::new(&bus), Address::AD0Low, MockDelay, mock_clock);
+        imu.init(MPUConfig::default()).expect("mock MPU-6050");
+        imu.set_bypass(true).expect("mock MPU-6050");
+
+        let Ok(mut compass) = Compass::detect(RefCellDevice::new(&bus)) else {
+            panic!("No magnetometer found behind the bypass");
+        };
+        compass.init().expect("mock magnetometer");
+        compass.set_alignment(mounting);
+
+        let mut sweep: MagCalibrationSweep = MagCalibrationSweep::new();
+        for _ in 0..500 {
+            let orientation: Rotation = Rotation::from_euler(
+                (noise.uniform() - 0.5) * 360.0, (noise.uniform() - 0.5) * 180.0, (noise.uniform() - 0.5) * 360.0
+            );
+            bus.borrow_mut().magnetometer.set_field(magnetometer_field(model, &orientation, &mounting, &mut noise));
+            sweep.add(compass.read_raw().expect("mock magnetometer"));
+        }
+        compass.set_calibration(sweep.fit().unwrap_or_default());
+
+        let true_strength: f64 = EARTH_FIELD.iter().map(|value| value * value).sum::<f64>().sqrt();
+        for yaw in (-180..180).step_by(15).map(f64::from) {
+            for (roll, pitch) in [(0.0, 0.0), (20.0, -10.0), (-25.0, 15.0), (10.0, 30.0)] {
+                let orientation: Rotation = Rotation::from_euler(roll, pitch, yaw);
+                bus.borrow_mut().magnetometer.set_field(magnetometer_field(model, &orientation, &mounting, &mut noise));
+
+                let [x, y, z] = orientation.to_body([0.0, 0.0, 1.0]).map(|value| value as f32);
+                let mag: MagnetometerData = compass.read().expect("mock magnetometer");
+                let estimate: f64 = compute_angle_magnetometer(&AccelometerData { x, y, z }, &mag).yaw() as f64;
+
+                heading_error = heading_error.max(((estimate - yaw + 540.0) % 360.0 - 180.0).abs());
+                let strength: f64 = ((mag.x * mag.x + mag.y * mag.y + mag.z * mag.z) as f64).sqrt();
+                field_error = field_error.max((strength - true_strength).abs());
+            }
+        }
+    }
+
+    Outcome {
+        name: "compass heading",
+        metrics: vec![
+            // The 2 mG noise alone is 0.6° at 0.2 G horizontal field
+            ("max heading error [°]", heading_error, 2.5),
+            ("max field strength error [G]", field_error, 0.01)
+        ]
+    }.check()
+}
+
+// Raw value in range whose compensation is closest to target, the compensation is monotonic
+fn raw_for(target: f64, range: Range<u32>, compensate: impl Fn(u32) -> f64) -> u32 {
+    let increasing: bool = compensate(range.end - 1) > compensate(range.start);
+    let (mut low, mut high): (u32, u32) = (range.start, range.end - 1);
+
+    while high - low > 1 {
+        let middle: u32 = low + (high - low) / 2;
+        if (compensate(middle) < target) == increasing { low = middle } else { high = middle }
+    }
+    if (compensate(low) - target).abs() < (compensate(high) - target).abs() { low } else { high }
+}
+
+// Raw pressure and temperature the chip of mock measures at altitude (standard atmosphere) and temperature
+fn barometer_raw(mock: &MockBarometer, altitude: f64, temperature: f64) -> (u32, u32) {
+    let pressure: f64 = altitude_pressure(altitude as f32) as f64;
+
+    match mock.model() {
+        BarometerModel::Bmp280 => {
+            let calibration: Bmp280Calibration = Bmp280Calibration::from_registers(&mock.calibration());
+            let raw_t: u32 = raw_for(temperature, 0..1 << 20, |raw| calibration.temperature(raw as i32).1 as f64 / 100.0);
+            let t_fine: i32 = calibration.temperature(raw_t as i32).0;
+            let raw_p: u32 = raw_for(pressure, 0..1 << 20, |raw| calibration.pressure(raw as i32, t_fine).unwrap_or(0) as f64 / 256.0);
+            (raw_p, raw_t)
+        },
+        BarometerModel::Bmp388 => {
+            let registers: [u8; BMP388_CALIBRATION_SIZE] = mock.calibration()[..BMP388_CALIBRATION_SIZE].try_into().expect("calibration size");
+            let calibration: Bmp388Calibration = Bmp388Calibration::from_registers(&registers);
+            let raw_t: u32 = raw_for(temperature, 1..1 << 24, |raw| calibration.temperature(raw));
+            let t_lin: f64 = calibration.temperature(raw_t);
+            (raw_for(pressure, 1..1 << 24, |raw| calibration.pressure(raw, t_lin)), raw_t)
+        }
+    }
+}
+
+// Both chips have to take every oversampling, and after arming 450 m above sea level a climb of 30 m has to be
+// measured above the ground reference.
+#[test]
+fn barometer() {
+    let mut rejected: f64 = 0.0;
+    let mut altitude_error: f64 = 0.0;
+
+    let oversampling: [Oversampling; 5] = [Oversampling::X1, Oversampling::X2, Oversampling::X4, Oversampling::X8, Oversampling::X16];
+    for model in [BarometerModel::Bmp280, BarometerModel::Bmp388] {
+        let bus: RefCell<MockBarometer> = RefCell::new(MockBarometer::new(model, BaroAddress::SdoHigh));
+        let mut barometer: Barometer<RefCellDevice<MockBarometer>, MockDelay> = Barometer::new(RefCellDevice::new(&bus), BaroAddress::SdoHigh, MockDelay);
+
+        for pressure in oversampling {
+            for temperature in oversampling {
+                let config: BaroConfig = BaroConfig::new().set_pressure_oversampling(pressure).set_temperature_oversampling(temperature).set_filter(IirFilter::C16);
+                if barometer.init(config).ok() != Some(model) {
+                    rejected += 1.0;
+                }
+            }
+        }
+        barometer.init(BaroConfig::default()).expect("mock barometer");
+
+        let mut altimeter: Altimeter = Altimeter::new();
+        let (raw_p, raw_t) = barometer_raw(&bus.borrow(), 450.0, 20.0);
+        bus.borrow_mut().set_raw(raw_p, raw_t);
+        altimeter.set_ground_reference(barometer.read().expect("mock barometer").pressure);
+
+        for altitude in [0.0, 1.0, 5.0, 30.0] {
+            // The chip warms up in the sun while climbing
+            let (raw_p, raw_t) = barometer_raw(&bus.borrow(), 450.0 + altitude, 20.0 + altitude / 3.0);
+            bus.borrow_mut().set_raw(raw_p, raw_t);
+            let estimate: f32 = altimeter.altitude(barometer.read().expect("mock barometer").pressure).unwrap_or(f32::NAN);
+            altitude_error = altitude_error.max((estimate as f64 - altitude).abs());
+        }
+    }
+
+    // Standard atmosphere at 1000 m
+    let isa_error: f64 = (pressure_altitude(89874.6) as f64 - 1000.0).abs();
+
+    Outcome {
+        name: "barometer",
+        metrics: vec![
+            ("configurations rejected", rejected, 0.5),
+            ("max altitude error above ground [m]", altitude_error, 0.05),
+            ("pressure altitude error at 1000 m [m]", isa_error, 0.5)
+        ]
+    }.check()
+}
+
+fn estimate_error(record: &Record) -> f64 {
+    (record.attitude[0] - record.estimate.roll() as f64).abs().max((record.attitude[1] - record.estimate.pitch() as f64).abs())
+}